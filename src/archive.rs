@@ -0,0 +1,108 @@
+use anyhow::{anyhow, Error};
+
+use crate::object::Object;
+
+const MAGIC: &[u8; 4] = b"NOXA";
+const VERSION: u8 = 1;
+
+/// Library of objects. When linking, a member is only included if it exports a symbol
+/// that the program needs, so unused library routines do not take space in the image.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Archive {
+    pub members: Vec<Object>,
+}
+
+impl Archive {
+    pub fn is_archive(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.members.len() as u16).to_be_bytes());
+        for member in &self.members {
+            let member_bytes = member.to_bytes();
+            bytes.extend_from_slice(&(member_bytes.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&member_bytes);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if !Self::is_archive(bytes) {
+            return Err(anyhow!("not a Nox library archive"));
+        }
+        let version = *bytes
+            .get(MAGIC.len())
+            .ok_or_else(|| anyhow!("unexpected end of archive"))?;
+        if version != VERSION {
+            return Err(anyhow!("unsupported archive version {}", version));
+        }
+        let mut position = MAGIC.len() + 1;
+        let count = read(bytes, &mut position, 2)?;
+        let mut archive = Archive::default();
+        for _ in 0..u16::from_be_bytes([count[0], count[1]]) {
+            let len = read(bytes, &mut position, 4)?;
+            let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            archive
+                .members
+                .push(Object::from_bytes(read(bytes, &mut position, len)?)?);
+        }
+        if position != bytes.len() {
+            return Err(anyhow!("trailing data after the archive"));
+        }
+        Ok(archive)
+    }
+
+    /// Adds the members that export symbols still undefined in `objects`,
+    /// repeating until members pulled in do not need anything more from the archives
+    pub fn resolve(archives: &[Archive], objects: &mut Vec<Object>, verbose: bool) {
+        let mut included: Vec<(usize, usize)> = vec![];
+        loop {
+            let undefined: Vec<&String> = objects
+                .iter()
+                .flat_map(|o| o.imports.iter())
+                .filter(|import| {
+                    !objects
+                        .iter()
+                        .any(|o| o.exports().any(|s| &&s.name == import))
+                })
+                .collect();
+            let pulled = archives
+                .iter()
+                .enumerate()
+                .find_map(|(archive_n, archive)| {
+                    archive
+                        .members
+                        .iter()
+                        .enumerate()
+                        .find_map(|(member_n, member)| {
+                            let needed = member.exports().find(|s| undefined.contains(&&s.name))?;
+                            (!included.contains(&(archive_n, member_n))).then_some((
+                                archive_n,
+                                member_n,
+                                needed.name.clone(),
+                            ))
+                        })
+                });
+            let Some((archive_n, member_n, symbol)) = pulled else {
+                break;
+            };
+            let member = &archives[archive_n].members[member_n];
+            if verbose {
+                println!("Including {} from archive for `{}`", member.source, symbol);
+            }
+            included.push((archive_n, member_n));
+            objects.push(member.clone());
+        }
+    }
+}
+
+fn read<'a>(bytes: &'a [u8], position: &mut usize, n: usize) -> Result<&'a [u8], Error> {
+    let slice = bytes
+        .get(*position..*position + n)
+        .ok_or_else(|| anyhow!("unexpected end of archive"))?;
+    *position += n;
+    Ok(slice)
+}
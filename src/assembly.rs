@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use crate::source::Location;
+
+/// Everything produced by assembling a program into a memory image
+#[derive(Debug, Clone)]
+pub struct Assembly {
+    pub bytes: Vec<u8>,
+    pub symbols: Vec<SymbolInfo>,
+    pub source_map: SourceMap,
+    pub diagnostics: Vec<Diagnostic>, // warnings, errors fail the assembly
+}
+
+impl Assembly {
+    pub fn symbol(&self, name: &str) -> Option<&SymbolInfo> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the closest label at or before `address`, eg. to print addresses as `label+offset`
+    pub fn symbolize(&self, address: u16) -> Option<(&SymbolInfo, u16)> {
+        self.symbols
+            .iter()
+            .filter(|s| s.address <= address)
+            .max_by_key(|s| s.address)
+            .map(|s| (s, address - s.address))
+    }
+
+    /// `label` or `label+0x3`, `None` if there is no label before `address`
+    pub fn symbolized(&self, address: u16) -> Option<String> {
+        self.symbolize(address)
+            .map(|(symbol, offset)| match offset {
+                0 => symbol.name.clone(),
+                offset => format!("{}+0x{:x}", symbol.name, offset),
+            })
+    }
+
+    /// Name of the label placed exactly at `address`
+    pub fn label_at(&self, address: u16) -> Option<String> {
+        self.symbolize(address)
+            .filter(|(_, offset)| *offset == 0)
+            .map(|(symbol, _)| symbol.name.clone())
+    }
+}
+
+/// Label and the address it was placed at
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolInfo {
+    pub name: String,
+    pub address: u16,
+    pub location: Location,
+}
+
+/// Maps addresses in the image to the source lines they were assembled from
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    pub entries: Vec<SourceMapEntry>,
+}
+
+/// Bytes `start..start + size` were assembled from `location`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMapEntry {
+    pub start: u16,
+    pub size: u16,
+    pub location: Location,
+}
+
+impl SourceMap {
+    pub fn lookup(&self, address: u16) -> Option<&SourceMapEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.start <= address && (address as usize) < e.start as usize + e.size as usize)
+    }
+
+    /// Returns the entries assembled from `location`
+    pub fn addresses_of<'a>(
+        &'a self,
+        location: &'a Location,
+    ) -> impl Iterator<Item = &'a SourceMapEntry> {
+        self.entries.iter().filter(move |e| &e.location == location)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problem found in the source. Errors are returned wrapped in `anyhow::Error`,
+/// so they can be recovered with `downcast_ref::<Diagnostic>()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn error(location: Option<Location>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            location,
+        }
+    }
+
+    pub fn warning(location: Option<Location>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            location,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match &self.location {
+            Some(location) => write!(f, "{}: {}: {}", location, severity, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
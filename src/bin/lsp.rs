@@ -0,0 +1,9 @@
+//! Language server for Nox ASM, see `nox_asm::lsp`
+
+use nox_asm::lsp::LanguageServer;
+
+fn main() {
+    LanguageServer::new()
+        .run(std::io::stdin().lock(), &mut std::io::stdout().lock())
+        .unwrap();
+}
@@ -0,0 +1,484 @@
+//! Control-flow graph and call graph of linked programs.
+//!
+//! Basic blocks start at labels, jump and call targets and after every `JMP`, conditional jump,
+//! `CALL`, `RET` and `HALT`. Routines are the blocks reachable from the reset address, a `CALL`
+//! target or a `.proc` without following calls; labelled code that is not reached from any of them (eg.
+//! interrupt handlers or tests) starts a routine of its own. Both graphs are exported as
+//! Graphviz DOT or JSON:
+//!
+//! ```text
+//! nox_asm graph -i program.nox | dot -Tsvg > cfg.svg
+//! nox_asm graph -i program.nox --call-graph --json
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use serde_json::{json, Value};
+
+use crate::{
+    assembly::Assembly,
+    disassembler::Disassembled,
+    emulator::Cpu,
+    ir::{Directive, Operand, Statement, StatementKind},
+    opcodes::Opcode,
+    source::Location,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockInstruction {
+    pub address: u16,
+    pub opcode: Opcode,
+    pub operand: Option<u16>, // resolved value of the operand
+    pub location: Location,
+}
+
+/// How a block is left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    Fallthrough(u16), // the next instruction starts another block
+    Jump(u16),
+    Branch {
+        opcode: Opcode, // `JZE`, `JOF`, `JER` or `JOK`
+        taken: u16,
+        not_taken: u16,
+    },
+    Call {
+        target: u16,
+        return_to: u16,
+    },
+    Return(Opcode),
+    Halt,
+    End, // runs into something that is not an instruction
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    pub end: u16, // address after the last instruction
+    pub instructions: Vec<BlockInstruction>,
+    pub terminator: Terminator,
+}
+
+impl Block {
+    /// Blocks that can run after this one in the same routine, a `CALL` continues after it returns
+    pub fn successors(&self) -> Vec<u16> {
+        match self.terminator {
+            Terminator::Fallthrough(next) | Terminator::Jump(next) => vec![next],
+            Terminator::Branch {
+                taken, not_taken, ..
+            } if taken == not_taken => vec![taken],
+            Terminator::Branch {
+                taken, not_taken, ..
+            } => vec![taken, not_taken],
+            Terminator::Call { return_to, .. } => vec![return_to],
+            Terminator::Return(_) | Terminator::Halt | Terminator::End => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routine {
+    pub entry: u16,
+    pub name: String,
+    pub blocks: Vec<u16>, // in address order, the entry is not necessarily the first one
+    pub calls: Vec<u16>,  // entries of the called routines, once per call site
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<u16, Block>,
+    routines: BTreeMap<u16, Routine>,
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of linked `statements`, `resolve` returns label addresses
+    pub fn new(statements: &[Statement], resolve: impl Fn(&str) -> Option<u16>) -> Self {
+        let mut instructions = BTreeMap::new();
+        let mut labels: BTreeMap<u16, &str> = BTreeMap::new();
+        let mut procs = vec![];
+        for statement in statements {
+            if let Some(name) = statement.label() {
+                labels.entry(statement.address).or_insert(name);
+            }
+            if let StatementKind::Directive(Directive::Proc(_)) = statement.kind {
+                procs.push(statement.address);
+            }
+            let Some(instruction) = statement.instruction() else {
+                continue;
+            };
+            let operand = instruction.operands.first().map(|operand| match operand {
+                Operand::Immediate8(value) => *value as u16,
+                Operand::Immediate16(value) | Operand::Address(value) => *value,
+                Operand::Label(name) | Operand::LabelAddress(name) => {
+                    resolve(name).unwrap_or_default()
+                }
+            });
+            let instruction = BlockInstruction {
+                address: statement.address,
+                opcode: instruction.opcode,
+                operand,
+                location: statement.span.clone(),
+            };
+            instructions.insert(statement.address, instruction);
+        }
+
+        let mut leaders: BTreeSet<u16> = labels.keys().copied().collect();
+        for instruction in instructions.values() {
+            let opcode = instruction.opcode;
+            if opcode == Opcode::JUMP || opcode == Opcode::CALL || opcode.is_conditional_jump() {
+                leaders.insert(instruction.operand.unwrap_or_default());
+            }
+            if ends_block(opcode) {
+                leaders.insert(instruction.address.wrapping_add(opcode.size()));
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<Block> = None;
+        for instruction in instructions.values() {
+            let address = instruction.address;
+            let mut block = match current.take() {
+                Some(block) if block.end == address && !leaders.contains(&address) => block,
+                previous => {
+                    if let Some(mut previous) = previous {
+                        previous.terminator = if instructions.contains_key(&previous.end) {
+                            Terminator::Fallthrough(previous.end)
+                        } else {
+                            Terminator::End
+                        };
+                        blocks.insert(previous.start, previous);
+                    }
+                    Block {
+                        start: address,
+                        end: address,
+                        instructions: vec![],
+                        terminator: Terminator::End,
+                    }
+                }
+            };
+            let opcode = instruction.opcode;
+            let target = instruction.operand.unwrap_or_default();
+            block.end = address.wrapping_add(opcode.size());
+            block.instructions.push(instruction.clone());
+            let terminator = match opcode {
+                Opcode::JUMP => Terminator::Jump(target),
+                Opcode::CALL => Terminator::Call {
+                    target,
+                    return_to: block.end,
+                },
+                Opcode::HALT => Terminator::Halt,
+                _ if opcode.is_conditional_jump() => Terminator::Branch {
+                    opcode,
+                    taken: target,
+                    not_taken: block.end,
+                },
+                _ if opcode.is_return() => Terminator::Return(opcode),
+                _ => {
+                    current = Some(block);
+                    continue;
+                }
+            };
+            block.terminator = terminator;
+            blocks.insert(block.start, block);
+        }
+        if let Some(block) = current {
+            blocks.insert(block.start, block);
+        }
+
+        let mut graph = Self {
+            blocks,
+            routines: BTreeMap::new(),
+        };
+        graph.find_routines(&labels, procs);
+        graph
+    }
+
+    /// `.proc` blocks are routines even when they are only jumped to
+    fn find_routines(&mut self, labels: &BTreeMap<u16, &str>, procs: Vec<u16>) {
+        let mut assigned = HashSet::new();
+        let mut entries = procs;
+        entries.push(Cpu::default().pc);
+        loop {
+            while let Some(entry) = entries.pop() {
+                if self.routines.contains_key(&entry) || !self.blocks.contains_key(&entry) {
+                    continue;
+                }
+                let mut blocks = BTreeSet::new();
+                let mut calls = vec![];
+                let mut queue = vec![entry];
+                while let Some(address) = queue.pop() {
+                    let Some(block) = self.blocks.get(&address) else {
+                        continue; // jumps out of the program
+                    };
+                    if !blocks.insert(address) {
+                        continue;
+                    }
+                    if let Terminator::Call { target, .. } = block.terminator {
+                        calls.push(target);
+                        entries.push(target);
+                    }
+                    queue.extend(block.successors());
+                }
+                assigned.extend(blocks.iter().copied());
+                let name = match labels.get(&entry) {
+                    Some(name) => name.to_string(),
+                    None => format!("0x{:04x}", entry),
+                };
+                let routine = Routine {
+                    entry,
+                    name,
+                    blocks: blocks.into_iter().collect(),
+                    calls,
+                };
+                self.routines.insert(entry, routine);
+            }
+            // Code that is only reached in other ways, eg. through `POP AB IRA`
+            let unreached = self
+                .blocks
+                .keys()
+                .find(|a| !assigned.contains(*a) && labels.contains_key(*a));
+            match unreached {
+                Some(address) => entries.push(*address),
+                None => break,
+            }
+        }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// Block starting at `address`
+    pub fn block(&self, address: u16) -> Option<&Block> {
+        self.blocks.get(&address)
+    }
+
+    pub fn routines(&self) -> impl Iterator<Item = &Routine> {
+        self.routines.values()
+    }
+
+    /// Routine entered at `address`
+    pub fn routine(&self, address: u16) -> Option<&Routine> {
+        self.routines.get(&address)
+    }
+
+    /// Control-flow graph of the routines in `entries` (all if empty), a cluster per routine
+    pub fn cfg_to_dot(&self, assembly: &Assembly, entries: &[u16]) -> String {
+        let mut dot = "digraph cfg {\n    node [shape=box, fontname=monospace];\n".to_owned();
+        for routine in self.selected(entries) {
+            let id = |address: u16| format!("\"r{:04x}_{:04x}\"", routine.entry, address);
+            dot.push_str(&format!(
+                "    subgraph cluster_{:04x} {{\n        label={};\n",
+                routine.entry,
+                quote(&routine.name)
+            ));
+            for block in routine.blocks.iter().map(|a| &self.blocks[a]) {
+                let mut label = String::new();
+                if let Some(name) = assembly.label_at(block.start) {
+                    label.push_str(&format!("{}:\\l", escape(&name)));
+                }
+                for (instruction, text) in
+                    block.instructions.iter().zip(disassemble(block, assembly))
+                {
+                    let line = format!("0x{:04x}  {}", instruction.address, text);
+                    label.push_str(&escape(&line));
+                    label.push_str("\\l");
+                }
+                dot.push_str(&format!(
+                    "        {} [label=\"{}\"];\n",
+                    id(block.start),
+                    label
+                ));
+            }
+            for block in routine.blocks.iter().map(|a| &self.blocks[a]) {
+                let edges: Vec<(u16, &str)> = match block.terminator {
+                    Terminator::Branch {
+                        opcode,
+                        taken,
+                        not_taken,
+                    } => vec![(taken, opcode.mnemonic()), (not_taken, "")],
+                    Terminator::Call { return_to, .. } => vec![(return_to, "")],
+                    _ => block.successors().into_iter().map(|s| (s, "")).collect(),
+                };
+                for (target, label) in edges {
+                    if !self.blocks.contains_key(&target) {
+                        continue;
+                    }
+                    let attributes = match label {
+                        "" => String::new(),
+                        label => format!(" [label={}]", quote(label)),
+                    };
+                    dot.push_str(&format!(
+                        "        {} -> {}{};\n",
+                        id(block.start),
+                        id(target),
+                        attributes
+                    ));
+                }
+            }
+            dot.push_str("    }\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Control-flow graph of the routines in `entries` (all if empty) as JSON
+    pub fn cfg_to_json(&self, assembly: &Assembly, entries: &[u16]) -> Value {
+        let routines: Vec<Value> = self
+            .selected(entries)
+            .map(|routine| {
+                let blocks: Vec<Value> = routine
+                    .blocks
+                    .iter()
+                    .map(|a| &self.blocks[a])
+                    .map(|block| {
+                        let instructions: Vec<Value> = block
+                            .instructions
+                            .iter()
+                            .zip(disassemble(block, assembly))
+                            .map(|(instruction, text)| {
+                                json!({
+                                    "address": instruction.address,
+                                    "text": text,
+                                    "location": instruction.location.to_string(),
+                                })
+                            })
+                            .collect();
+                        json!({
+                            "start": block.start,
+                            "end": block.end,
+                            "label": assembly.label_at(block.start),
+                            "instructions": instructions,
+                            "terminator": terminator_name(&block.terminator),
+                            "successors": block
+                                .successors()
+                                .into_iter()
+                                .filter(|s| self.blocks.contains_key(s))
+                                .collect::<Vec<u16>>(),
+                        })
+                    })
+                    .collect();
+                json!({
+                    "name": routine.name,
+                    "entry": routine.entry,
+                    "blocks": blocks,
+                    "calls": self.callees(routine),
+                })
+            })
+            .collect();
+        json!({ "routines": routines })
+    }
+
+    /// Routines and the routines they call, edges are labelled with the number of call sites
+    pub fn call_graph_to_dot(&self) -> String {
+        let mut dot = "digraph calls {\n    node [shape=box, fontname=monospace];\n".to_owned();
+        for routine in self.routines.values() {
+            dot.push_str(&format!("    {};\n", quote(&routine.name)));
+        }
+        for (caller, callee, sites) in self.call_edges() {
+            dot.push_str(&format!(
+                "    {} -> {} [label=\"{}\"];\n",
+                quote(caller),
+                quote(&callee),
+                sites
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn call_graph_to_json(&self) -> Value {
+        let routines: Vec<Value> = self
+            .routines
+            .values()
+            .map(|r| json!({ "name": r.name, "entry": r.entry }))
+            .collect();
+        let calls: Vec<Value> = self
+            .call_edges()
+            .into_iter()
+            .map(|(caller, callee, sites)| {
+                json!({ "caller": caller, "callee": callee, "sites": sites })
+            })
+            .collect();
+        json!({ "routines": routines, "calls": calls })
+    }
+
+    fn selected<'a>(&'a self, entries: &'a [u16]) -> impl Iterator<Item = &'a Routine> {
+        self.routines
+            .values()
+            .filter(move |r| entries.is_empty() || entries.contains(&r.entry))
+    }
+
+    /// Names of the routines called by `routine`, without duplicates
+    fn callees(&self, routine: &Routine) -> Vec<String> {
+        let mut names: Vec<String> = routine.calls.iter().map(|c| self.name(*c)).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn call_edges(&self) -> Vec<(&str, String, usize)> {
+        let mut edges = vec![];
+        for routine in self.routines.values() {
+            let mut sites: BTreeMap<String, usize> = BTreeMap::new();
+            for callee in &routine.calls {
+                *sites.entry(self.name(*callee)).or_default() += 1;
+            }
+            for (callee, count) in sites {
+                edges.push((routine.name.as_str(), callee, count));
+            }
+        }
+        edges
+    }
+
+    /// Name of the routine at `entry`, or its address if it is not a part of the program
+    fn name(&self, entry: u16) -> String {
+        match self.routines.get(&entry) {
+            Some(routine) => routine.name.clone(),
+            None => format!("0x{:04x}", entry),
+        }
+    }
+}
+
+/// Instructions of the block in assembly syntax
+fn disassemble(block: &Block, assembly: &Assembly) -> Vec<String> {
+    block
+        .instructions
+        .iter()
+        .map(|instruction| {
+            Disassembled {
+                address: instruction.address,
+                opcode: Some(instruction.opcode),
+                operand: instruction.operand,
+                size: instruction.opcode.size(),
+            }
+            .format(&assembly.bytes, |a| assembly.label_at(a))
+        })
+        .collect()
+}
+
+fn terminator_name(terminator: &Terminator) -> &'static str {
+    match terminator {
+        Terminator::Fallthrough(_) => "fallthrough",
+        Terminator::Jump(_) => "jump",
+        Terminator::Branch { .. } => "branch",
+        Terminator::Call { .. } => "call",
+        Terminator::Return(_) => "return",
+        Terminator::Halt => "halt",
+        Terminator::End => "end",
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn ends_block(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::JUMP | Opcode::CALL | Opcode::HALT)
+        || opcode.is_conditional_jump()
+        || opcode.is_return()
+}
@@ -0,0 +1,150 @@
+//! Checks `.proc` contracts against the routines of the [`ControlFlowGraph`]:
+//!
+//! ```text
+//! .proc strlen uses A, HLI preserves B returns A
+//! ```
+//!
+//! - every register the routine writes is in `uses`, `preserves` or `returns`, when `uses` is given
+//! - registers in `preserves` have the values the caller left in them when the routine returns,
+//!   besides the values declared with `returns`
+//!
+//! A `CALL` writes the registers the called routine leaves changed for its caller, which is never
+//! HI or LI since `RET` restores them. A and B can be used as scratch by a routine preserving them,
+//! as long as it pops everything it pushed and leaves the values below alone.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    cfg::{ControlFlowGraph, Terminator},
+    ir::{Proc, Register},
+    opcodes::Opcode,
+    register_stack::{self, RegisterStackAnalysis},
+    source::Location,
+};
+
+/// Where a register is written first, for messages
+struct Write {
+    location: Location,
+    callee: Option<String>, // routine called by the instruction, which writes the register
+}
+
+/// Breaks of the contract of the `.proc` at `entry`, with where they happen
+pub fn check(
+    proc: &Proc,
+    entry: u16,
+    location: &Location,
+    graph: &ControlFlowGraph,
+    stacks: &RegisterStackAnalysis,
+) -> Vec<(Location, String)> {
+    let mut errors = vec![];
+    for register in &proc.preserves {
+        if !stacks.preserves(entry, *register) {
+            errors.push((
+                location.clone(),
+                format!(
+                    "`{}` does not preserve {}: it takes values it did not push or leaves its own behind",
+                    proc.name, register
+                ),
+            ));
+        }
+    }
+    let Some(uses) = &proc.uses else {
+        return errors;
+    };
+    for (register, write) in writes(entry, graph, stacks) {
+        if uses.contains(&register)
+            || proc.preserves.contains(&register)
+            || proc.returns.contains(&register)
+        {
+            continue;
+        }
+        let message = match write.callee {
+            Some(callee) => format!(
+                "`{}` writes {} by calling `{}`, but does not declare it in `uses`",
+                proc.name, register, callee
+            ),
+            None => format!(
+                "`{}` writes {}, but does not declare it in `uses`",
+                proc.name, register
+            ),
+        };
+        errors.push((write.location, message));
+    }
+    errors
+}
+
+/// Registers written by the routine at `entry`, in the order of `Register`
+fn writes(
+    entry: u16,
+    graph: &ControlFlowGraph,
+    stacks: &RegisterStackAnalysis,
+) -> BTreeMap<Register, Write> {
+    let mut writes = BTreeMap::new();
+    let Some(routine) = graph.routine(entry) else {
+        return writes;
+    };
+    for block in routine.blocks.iter().filter_map(|b| graph.block(*b)) {
+        for instruction in &block.instructions {
+            for register in written_by(instruction.opcode, instruction.operand) {
+                writes.entry(register).or_insert_with(|| Write {
+                    location: instruction.location.clone(),
+                    callee: None,
+                });
+            }
+        }
+        let Terminator::Call { target, .. } = block.terminator else {
+            continue;
+        };
+        let Some(call) = block.instructions.last() else {
+            continue;
+        };
+        for register in [Register::A, Register::B] {
+            if !stacks.preserves(target, register) {
+                writes.entry(register).or_insert_with(|| Write {
+                    location: call.location.clone(),
+                    callee: graph.routine(target).map(|r| r.name.clone()),
+                });
+            }
+        }
+    }
+    writes
+}
+
+fn written_by(opcode: Opcode, operand: Option<u16>) -> Vec<Register> {
+    let mut registers = vec![];
+    let (a, b) = register_stack::effect(opcode, operand);
+    let shifts_ab = matches!(opcode, Opcode::SHIFT_LEFT_AB | Opcode::SHIFT_RIGHT_AB);
+    if a != 0 || shifts_ab || matches!(opcode, Opcode::SHIFT_LEFT_A | Opcode::SHIFT_RIGHT_A) {
+        registers.push(Register::A);
+    }
+    if b != 0 || shifts_ab || matches!(opcode, Opcode::SHIFT_LEFT_B | Opcode::SHIFT_RIGHT_B) {
+        registers.push(Register::B);
+    }
+    match opcode {
+        Opcode::PUSH_IMMEDIATE_HI
+        | Opcode::PUSH_ABSOLUTE_HI
+        | Opcode::POP_A_HI
+        | Opcode::POP_B_HI
+        | Opcode::INC_HI
+        | Opcode::DEC_HI
+        | Opcode::ZERO_HI
+        | Opcode::POP_STACK_HI => registers.push(Register::HI),
+        Opcode::PUSH_IMMEDIATE_LI
+        | Opcode::PUSH_ABSOLUTE_LI
+        | Opcode::POP_A_LI
+        | Opcode::POP_B_LI
+        | Opcode::INC_LI
+        | Opcode::DEC_LI
+        | Opcode::ZERO_LI
+        | Opcode::POP_STACK_LI => registers.push(Register::LI),
+        Opcode::SWAP_HI_LI
+        | Opcode::PUSH_IMMEDIATE_HLI
+        | Opcode::PUSH_ABSOLUTE_HLI
+        | Opcode::POP_AB_HLI
+        | Opcode::INC_HLI
+        | Opcode::DEC_HLI
+        | Opcode::ZERO_HLI => registers.extend([Register::HI, Register::LI]),
+        _ => (),
+    }
+    registers
+}
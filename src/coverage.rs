@@ -0,0 +1,166 @@
+//! Line and branch coverage of programs run in the [`Emulator`], mapped back to the source lines
+//! the instructions were assembled from. Written as lcov tracefiles or as annotated sources:
+//!
+//! ```text
+//!            loop:
+//!       30      PUSH &HLI A
+//!       30      JZE done  // taken 5, not taken 25
+//!    #####      JER error
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    emulator::{BranchCount, Emulator},
+    ir::Statement,
+    source::Location,
+};
+
+/// Coverage of a single source line. Lines without instructions are not tracked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineCoverage {
+    pub count: u64,
+    pub branch: Option<BranchCount>, // for conditional jumps
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    files: BTreeMap<PathBuf, BTreeMap<usize, LineCoverage>>,
+}
+
+impl Coverage {
+    /// Collects the coverage of the instructions of `statements` recorded by `emulator`
+    pub fn new(statements: &[Statement], emulator: &Emulator) -> Self {
+        let mut files: BTreeMap<PathBuf, BTreeMap<usize, LineCoverage>> = BTreeMap::new();
+        for statement in statements {
+            let Some(instruction) = statement.instruction() else {
+                continue;
+            };
+            // A file included more than once has its lines counted for every copy
+            let line = files
+                .entry(statement.span.file.clone())
+                .or_default()
+                .entry(statement.span.line)
+                .or_default();
+            line.count += emulator.execution_count(statement.address);
+            if instruction.opcode.is_conditional_jump() {
+                let count = emulator
+                    .branches()
+                    .get(&statement.address)
+                    .copied()
+                    .unwrap_or_default();
+                let branch = line.branch.get_or_insert_with(BranchCount::default);
+                branch.taken += count.taken;
+                branch.not_taken += count.not_taken;
+            }
+        }
+        Self { files }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+
+    /// Coverage of the lines of `file` with instructions, by line number
+    pub fn lines(&self, file: &Path) -> Option<&BTreeMap<usize, LineCoverage>> {
+        self.files.get(file)
+    }
+
+    /// Lines with instructions and how many of them were executed
+    pub fn summary(&self, file: &Path) -> (usize, usize) {
+        let lines = self.files.get(file);
+        let found = lines.map(BTreeMap::len).unwrap_or_default();
+        let hit = lines
+            .map(|lines| lines.values().filter(|l| l.count > 0).count())
+            .unwrap_or_default();
+        (found, hit)
+    }
+
+    /// Tracefile in the lcov format, eg. for `genhtml` or editor plugins
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (file, lines) in &self.files {
+            lcov.push_str(&format!("TN:\nSF:{}\n", file.display()));
+            let (mut branches_found, mut branches_hit) = (0, 0);
+            for (line, coverage) in lines {
+                let Some(branch) = coverage.branch else {
+                    continue;
+                };
+                // Branch 0 is the jump, branch 1 falls through
+                for (n, count) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                    let count = if coverage.count == 0 {
+                        "-".to_owned()
+                    } else {
+                        count.to_string()
+                    };
+                    lcov.push_str(&format!("BRDA:{},0,{},{}\n", line, n, count));
+                }
+                branches_found += 2;
+                branches_hit += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+            }
+            lcov.push_str(&format!("BRF:{}\nBRH:{}\n", branches_found, branches_hit));
+            for (line, coverage) in lines {
+                lcov.push_str(&format!("DA:{},{}\n", line, coverage.count));
+            }
+            let (found, hit) = self.summary(file);
+            lcov.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", found, hit));
+        }
+        lcov
+    }
+
+    /// Source files with each line prefixed with the times it was executed, `#####` if never.
+    /// `lines` are the source lines of the program, see [`crate::Assembler::source_lines`]
+    pub fn annotate(&self, lines: &[(Location, String)]) -> String {
+        let mut sources: BTreeMap<&Path, BTreeMap<usize, &str>> = BTreeMap::new();
+        for (location, text) in lines {
+            sources
+                .entry(&location.file)
+                .or_default()
+                .insert(location.line, text);
+        }
+        let mut output = String::new();
+        for (file, source) in sources {
+            let (found, hit) = self.summary(file);
+            let percent = if found == 0 {
+                100.0
+            } else {
+                hit as f64 * 100.0 / found as f64
+            };
+            output.push_str(&format!(
+                "// {}: {}/{} lines ({:.1}%)\n",
+                file.display(),
+                hit,
+                found,
+                percent
+            ));
+            let coverage = self.files.get(file);
+            for (line, text) in source {
+                let Some(line) = coverage.and_then(|c| c.get(&line)) else {
+                    output.push_str(format!("{:>9}  {}", "", text).trim_end());
+                    output.push('\n');
+                    continue;
+                };
+                let count = match line.count {
+                    0 => "#####".to_owned(),
+                    count => count.to_string(),
+                };
+                let branch = match line.branch {
+                    Some(_) if line.count == 0 => String::new(),
+                    Some(BranchCount { taken: 0, .. }) => "  // never taken".to_owned(),
+                    Some(BranchCount { not_taken: 0, .. }) => "  // always taken".to_owned(),
+                    Some(branch) => format!(
+                        "  // taken {}, not taken {}",
+                        branch.taken, branch.not_taken
+                    ),
+                    None => String::new(),
+                };
+                output.push_str(&format!("{:>9}  {}{}\n", count, text, branch));
+            }
+            output.push('\n');
+        }
+        output
+    }
+}
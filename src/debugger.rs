@@ -0,0 +1,383 @@
+//! Interactive step debugger running a program in the [`Emulator`].
+
+use std::io::{BufRead, Write};
+
+use anyhow::{anyhow, Error};
+
+use crate::{
+    assembly::Assembly,
+    disassembler::disassemble,
+    emulator::{Emulator, Event, Fault},
+    opcodes::Opcode,
+};
+
+/// Limit of instructions executed by a single `continue`, `next` or `finish`
+pub const MAX_STEPS: usize = 10_000_000;
+
+const HELP: &str = "\
+commands:
+  step, s [n]          execute n instructions (default 1)
+  next, n              execute one instruction, stepping over CALL
+  finish               run until the current routine returns
+  continue, c          run until a breakpoint, watchpoint or HALT
+  break, b <location>  set a breakpoint, eg. `b main`, `b loop+0x3`, `b 0x0010`
+  delete, d <location> remove a breakpoint
+  watch, w <address>   stop after the memory byte is written
+  unwatch <address>    remove a watchpoint
+  regs, r              show registers and flags
+  stack                show the memory stack of the current routine
+  backtrace, bt        show routines entered with CALL and interrupt handlers
+  irq                  request an interrupt, taken when IRQ is set
+  mem, x <addr> [len]  show memory
+  where, l [n]         disassemble n instructions from PC (default 5)
+  reset                reload the program and reset the CPU
+  quit, q              exit";
+
+/// Why running stopped
+enum Stop {
+    Breakpoint(u16),
+    Watchpoint(u16, u8, u8),
+    Halt,
+    Fault(Fault),
+    Limit,
+    Done, // the requested steps were executed
+}
+
+pub struct Debugger {
+    emulator: Emulator,
+    assembly: Assembly,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<u16>,
+}
+
+impl Debugger {
+    pub fn new(assembly: Assembly) -> Self {
+        let emulator = Emulator::new(&assembly.bytes);
+        Self::with_emulator(assembly, emulator)
+    }
+
+    /// Debugs `assembly` running in an emulator with devices mapped
+    pub fn with_emulator(assembly: Assembly, emulator: Emulator) -> Self {
+        Self {
+            emulator,
+            assembly,
+            breakpoints: vec![],
+            watchpoints: vec![],
+        }
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    /// Reads commands from `input` until `quit` or end of input
+    pub fn run(&mut self, input: impl BufRead, output: &mut impl Write) -> Result<(), Error> {
+        writeln!(output, "{}", self.where_(1))?;
+        write!(output, "(nox) ")?;
+        output.flush()?;
+        let mut last_command = String::new();
+        for line in input.lines() {
+            let mut line = line?.trim().to_owned();
+            if line.is_empty() {
+                line = last_command.clone(); // repeat, like gdb
+            }
+            match line.as_str() {
+                "quit" | "q" => break,
+                "" => (),
+                _ => {
+                    if let Err(e) = self.execute(&line, output) {
+                        writeln!(output, "error: {}", e)?;
+                    }
+                }
+            }
+            last_command = line;
+            write!(output, "(nox) ")?;
+            output.flush()?;
+        }
+        writeln!(output)?;
+        Ok(())
+    }
+
+    /// Executes a single debugger command
+    pub fn execute(&mut self, line: &str, output: &mut impl Write) -> Result<(), Error> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(());
+        };
+        match command {
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(count) => parse_number(count)? as usize,
+                    None => 1,
+                };
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.single_step();
+                    if !matches!(stop, Stop::Done) {
+                        break;
+                    }
+                }
+                self.report(stop, output)?
+            }
+            "next" | "n" => {
+                let depth = self.emulator.call_depth();
+                let stop = if self.emulator.next_opcode() == Some(Opcode::CALL) {
+                    self.run_until(|e| e.call_depth() <= depth)
+                } else {
+                    self.single_step()
+                };
+                self.report(stop, output)?
+            }
+            "finish" => {
+                let depth = self.emulator.call_depth();
+                if depth == 0 {
+                    return Err(anyhow!("not inside a routine entered with CALL"));
+                }
+                let stop = self.run_until(|e| e.call_depth() < depth);
+                self.report(stop, output)?
+            }
+            "continue" | "c" => {
+                let stop = self.run_until(|_| false);
+                self.report(stop, output)?
+            }
+            "break" | "b" => {
+                let address = self.resolve(args.first())?;
+                if !self.breakpoints.contains(&address) {
+                    self.breakpoints.push(address);
+                }
+                writeln!(output, "breakpoint at {}", self.describe(address))?
+            }
+            "delete" | "d" => {
+                let address = self.resolve(args.first())?;
+                self.breakpoints.retain(|b| *b != address);
+            }
+            "watch" | "w" => {
+                let address = self.resolve(args.first())?;
+                if !self.watchpoints.contains(&address) {
+                    self.watchpoints.push(address);
+                }
+                writeln!(output, "watchpoint at {}", self.describe(address))?
+            }
+            "unwatch" => {
+                let address = self.resolve(args.first())?;
+                self.watchpoints.retain(|w| *w != address);
+            }
+            "regs" | "r" => writeln!(output, "{}", self.registers())?,
+            "stack" => writeln!(output, "{}", self.stack())?,
+            "backtrace" | "bt" => writeln!(output, "{}", self.backtrace())?,
+            "mem" | "x" => {
+                let address = self.resolve(args.first())?;
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len)?,
+                    None => 16,
+                };
+                writeln!(output, "{}", self.dump(address, len))?
+            }
+            "where" | "l" => {
+                let count = match args.first() {
+                    Some(count) => parse_number(count)? as usize,
+                    None => 5,
+                };
+                writeln!(output, "{}", self.where_(count))?
+            }
+            "irq" => {
+                self.emulator.request_interrupt();
+                if !self.emulator.cpu.flags.irq {
+                    writeln!(output, "interrupts are disabled, the request is pending")?
+                }
+            }
+            "reset" => {
+                self.emulator.reset();
+                writeln!(output, "{}", self.where_(1))?
+            }
+            "help" | "h" => writeln!(output, "{}", HELP)?,
+            _ => return Err(anyhow!("unknown command `{}`, try `help`", command)),
+        }
+        Ok(())
+    }
+
+    fn single_step(&mut self) -> Stop {
+        match self.emulator.step() {
+            Ok(step) if step.event == Some(Event::Halt) => Stop::Halt,
+            Ok(_) => {
+                let watched = self
+                    .emulator
+                    .last_writes()
+                    .iter()
+                    .find(|w| self.watchpoints.contains(&w.address));
+                match watched {
+                    Some(write) => Stop::Watchpoint(write.address, write.old, write.new),
+                    None => Stop::Done,
+                }
+            }
+            Err(fault) => Stop::Fault(fault),
+        }
+    }
+
+    /// Steps until `done` returns true after a step or something stops the program.
+    /// Breakpoints are not checked before the first step, so it is possible to continue from them
+    fn run_until(&mut self, done: impl Fn(&Emulator) -> bool) -> Stop {
+        for n in 0..MAX_STEPS {
+            if n > 0 && self.breakpoints.contains(&self.emulator.cpu.pc) {
+                return Stop::Breakpoint(self.emulator.cpu.pc);
+            }
+            let stop = self.single_step();
+            if !matches!(stop, Stop::Done) || done(&self.emulator) {
+                return stop;
+            }
+        }
+        Stop::Limit
+    }
+
+    fn report(&self, stop: Stop, output: &mut impl Write) -> Result<(), Error> {
+        match stop {
+            Stop::Breakpoint(address) => writeln!(output, "breakpoint {}", self.describe(address))?,
+            Stop::Watchpoint(address, old, new) => writeln!(
+                output,
+                "watchpoint {}: 0x{:02x} -> 0x{:02x}",
+                self.describe(address),
+                old,
+                new
+            )?,
+            Stop::Halt => writeln!(output, "halted")?,
+            Stop::Fault(fault) => writeln!(output, "fault: {}", fault)?,
+            Stop::Limit => writeln!(output, "stopped after {} instructions", MAX_STEPS)?,
+            Stop::Done => (),
+        }
+        writeln!(output, "{}", self.where_(1))?;
+        Ok(())
+    }
+
+    /// Parses `label`, `label+offset` or an address
+    fn resolve(&self, location: Option<&&str>) -> Result<u16, Error> {
+        let location = location.ok_or_else(|| anyhow!("missing address or label"))?;
+        let (base, offset) = match location.split_once('+') {
+            Some((base, offset)) => (base, parse_number(offset)?),
+            None => (*location, 0),
+        };
+        let base = match self.assembly.symbol(base) {
+            Some(symbol) => symbol.address,
+            None => parse_number(base.trim_start_matches('&'))
+                .map_err(|_| anyhow!("`{}` is not a label or address", base))?,
+        };
+        Ok(base.wrapping_add(offset))
+    }
+
+    /// `0x0012 <main+0x2>`
+    fn describe(&self, address: u16) -> String {
+        match self.assembly.symbolized(address) {
+            Some(symbol) => format!("0x{:04x} <{}>", address, symbol),
+            None => format!("0x{:04x}", address),
+        }
+    }
+
+    fn where_(&self, count: usize) -> String {
+        let memory = self.emulator.memory();
+        let mut address = self.emulator.cpu.pc;
+        let mut lines = vec![];
+        for n in 0..count {
+            let instruction = disassemble(memory, address);
+            let text = instruction.format(memory, |a| self.assembly.label_at(a));
+            let source = self
+                .assembly
+                .source_map
+                .lookup(address)
+                .map(|e| format!("  ; {}", e.location))
+                .unwrap_or_default();
+            let marker = if n == 0 { "=>" } else { "  " };
+            lines.push(format!(
+                "{} {:<28} {:<24}{}",
+                marker,
+                self.describe(address),
+                text,
+                source
+            ));
+            address = address.wrapping_add(instruction.size);
+        }
+        lines.join("\n")
+    }
+
+    fn registers(&self) -> String {
+        let cpu = &self.emulator.cpu;
+        let values = |values: &[u8]| {
+            values
+                .iter()
+                .map(|v| format!("0x{:02x}", v))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "PC  {}\n\
+             A   [{}]\n\
+             B   [{}]\n\
+             HLI 0x{:04x}  EX 0x{:02x}  IRA 0x{:04x}\n\
+             SA  0x{:04x}  SS 0x{:04x}  SP 0x{:04x}\n\
+             flags {}",
+            self.describe(cpu.pc),
+            values(&cpu.a),
+            values(&cpu.b),
+            cpu.hli(),
+            cpu.exit_code,
+            cpu.irq_address,
+            cpu.stack_address,
+            cpu.stack_size,
+            cpu.stack_pointer,
+            cpu.flags
+        )
+    }
+
+    fn stack(&self) -> String {
+        let cpu = &self.emulator.cpu;
+        let used = cpu.stack_pointer.wrapping_sub(cpu.stack_address);
+        if used == 0 {
+            return "stack is empty".to_owned();
+        }
+        self.dump(cpu.stack_address, used)
+    }
+
+    fn backtrace(&self) -> String {
+        let mut lines = vec![format!("#0 {}", self.describe(self.emulator.cpu.pc))];
+        for (n, frame) in self.emulator.call_stack().iter().rev().enumerate() {
+            let from = if frame.interrupt {
+                format!("interrupted at {}", self.describe(frame.return_address))
+            } else {
+                // size of CALL
+                format!(
+                    "called from {}",
+                    self.describe(frame.return_address.wrapping_sub(3))
+                )
+            };
+            lines.push(format!(
+                "#{} {} {}",
+                n + 1,
+                self.describe(frame.target),
+                from
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn dump(&self, address: u16, len: u16) -> String {
+        let memory = self.emulator.memory();
+        let mut lines = vec![];
+        let mut offset = 0u16;
+        while offset < len {
+            let start = address.wrapping_add(offset);
+            let count = (len - offset).min(16);
+            let bytes: Vec<String> = (0..count)
+                .map(|i| format!("{:02x}", memory[start.wrapping_add(i) as usize]))
+                .collect();
+            lines.push(format!("0x{:04x}: {}", start, bytes.join(" ")));
+            offset += count;
+        }
+        lines.join("\n")
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, Error> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>(),
+    };
+    parsed.map_err(|_| anyhow!("`{}` is not a number", text))
+}
@@ -0,0 +1,48 @@
+use crate::opcodes::{Opcode, OperandKind};
+
+/// Single decoded instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disassembled {
+    pub address: u16,
+    pub opcode: Option<Opcode>, // `None` if the byte is not a valid opcode
+    pub operand: Option<u16>,
+    pub size: u16,
+}
+
+impl Disassembled {
+    /// Formats the instruction in assembly syntax. Addresses that `symbolize` knows
+    /// are printed as labels, so the output reads like the source
+    pub fn format(&self, memory: &[u8], symbolize: impl Fn(u16) -> Option<String>) -> String {
+        let Some(opcode) = self.opcode else {
+            return format!("$ 0x{:02x}", memory[self.address as usize]);
+        };
+        let operand = match (opcode.operand(), self.operand) {
+            (Some(OperandKind::Immediate8), Some(value)) => format!("0x{:02x}", value),
+            (Some(OperandKind::Immediate16), Some(value)) => format!("0x{:04x}", value),
+            (Some(OperandKind::Address), Some(value)) => {
+                symbolize(value).unwrap_or_else(|| format!("&0x{:04x}", value))
+            }
+            _ => String::new(),
+        };
+        opcode.syntax().replace("{}", &operand)
+    }
+}
+
+/// Decodes the instruction at `address`
+pub fn disassemble(memory: &[u8], address: u16) -> Disassembled {
+    let byte = |offset: u16| memory[address.wrapping_add(offset) as usize];
+    let opcode = Opcode::from_byte(byte(0));
+    let operand = match opcode.and_then(|o| o.operand()) {
+        Some(OperandKind::Immediate8) => Some(byte(1) as u16),
+        Some(OperandKind::Immediate16 | OperandKind::Address) => {
+            Some(u16::from_be_bytes([byte(1), byte(2)]))
+        }
+        None => None,
+    };
+    Disassembled {
+        address,
+        opcode,
+        operand,
+        size: opcode.map(|o| o.size()).unwrap_or(1),
+    }
+}
@@ -0,0 +1,327 @@
+//! In-process model of the Nox CPU, used by the debugger to run assembled programs.
+//!
+//! The semantics follow the opcode descriptions in [`crate::opcodes`]:
+//! - A and B are stacks of up to 256 bytes. `PUSH` copies a value to them, `POP` moves the last one out.
+//!   Two-operand ALU instructions read the last values of A and B and push the result to the target
+//!   register, eg. `ADD A B` pushes A + B to B and `SUB B A` pushes B - A to A. Shifts change the last value.
+//! - AB is A (high byte) with B (low byte), HLI is HI (high byte) with LI (low byte).
+//! - Arithmetic sets ZERO if the result is 0 and OVF on carry or borrow. `CMP` sets ZERO if the
+//!   values are equal and OVF if the register is greater: `CMP 0x03 A` if A > 3, `CMP A B` if A > B.
+//!   Moving values between registers does not change flags.
+//! - The memory stack starts at `stack_address` and grows upwards, `stack_size` bytes at most.
+//!   `CALL` pushes flags, HI, LI, return address, `stack_size` and `stack_address`, then gives the
+//!   callee the rest of the stack as a new frame. `RET` restores them (except ERR/OK which tell
+//!   the result) and sets the exit code. With `stack_size` 0 calls are disabled.
+//!
+//! Interrupts are taken between instructions while IRQ is set and a device requests one: the
+//! CPU enters the handler at the address set with `POP AB IRA` like a `CALL`, with IRQ cleared
+//! until the handler returns. Its `RET` restores all flags and keeps the exit code.
+//!
+//! Devices can be mapped to address ranges (see [`Device`] and [`devices`]), and ranges of memory
+//! can be protected like ROM - writing them stops the program with [`Fault::RomWrite`].
+
+pub mod cpu;
+pub mod devices;
+
+use std::{collections::HashMap, ops::RangeInclusive};
+
+pub use cpu::{Bus, Cpu, Event, Fault, Flags, Register, Step};
+
+use crate::opcodes::Opcode;
+
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// Memory write done by the last executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+/// Routine entered by `CALL` or an interrupt that did not return yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub target: u16,
+    pub return_address: u16,
+    pub interrupt: bool,
+}
+
+/// How many times a conditional jump was taken and not taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Memory-mapped peripheral. Offsets are relative to the start of the range it is mapped to
+pub trait Device {
+    fn read(&mut self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, value: u8);
+
+    /// Called after every executed instruction
+    fn tick(&mut self) {}
+
+    /// State of the interrupt request line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Called when the emulator is reset
+    fn reset(&mut self) {}
+}
+
+struct MappedDevice {
+    range: RangeInclusive<u16>,
+    device: Box<dyn Device>,
+}
+
+/// CPU with 64 KiB of memory loaded with a memory image, and devices mapped over it
+pub struct Emulator {
+    pub cpu: Cpu,
+    memory: Vec<u8>,
+    devices: Vec<MappedDevice>,
+    rom: Vec<RangeInclusive<u16>>,
+    image: Vec<u8>,
+    call_stack: Vec<Frame>,
+    writes: Vec<MemoryWrite>,
+    requested_irq: bool,
+    executed: Vec<u64>, // times an instruction was executed at each address, kept across resets
+    branches: HashMap<u16, BranchCount>,
+}
+
+struct RecordingBus<'a> {
+    memory: &'a mut [u8],
+    devices: &'a mut [MappedDevice],
+    rom: &'a [RangeInclusive<u16>],
+    writes: &'a mut Vec<MemoryWrite>,
+    rom_write: Option<u16>,
+}
+
+impl RecordingBus<'_> {
+    fn device(&mut self, address: u16) -> Option<(&mut MappedDevice, u16)> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.range.contains(&address))
+            .map(|d| {
+                let offset = address - d.range.start();
+                (d, offset)
+            })
+    }
+}
+
+impl Bus for RecordingBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        match self.device(address) {
+            Some((mapped, offset)) => mapped.device.read(offset),
+            None => self.memory[address as usize],
+        }
+    }
+
+    /// Device writes are also kept in memory, so they show up in memory dumps
+    fn write(&mut self, address: u16, value: u8) {
+        if self.rom.iter().any(|r| r.contains(&address)) {
+            self.rom_write.get_or_insert(address);
+            return;
+        }
+        if let Some((mapped, offset)) = self.device(address) {
+            mapped.device.write(offset, value);
+        }
+        self.writes.push(MemoryWrite {
+            address,
+            old: self.memory[address as usize],
+            new: value,
+        });
+        self.memory[address as usize] = value;
+    }
+}
+
+impl Emulator {
+    pub fn new(image: &[u8]) -> Self {
+        let mut emulator = Self {
+            cpu: Cpu::default(),
+            memory: vec![],
+            devices: vec![],
+            rom: vec![],
+            image: image[..image.len().min(MEMORY_SIZE)].to_vec(),
+            call_stack: vec![],
+            writes: vec![],
+            requested_irq: false,
+            executed: vec![0; MEMORY_SIZE],
+            branches: HashMap::new(),
+        };
+        emulator.reset();
+        emulator
+    }
+
+    /// Reloads the image and resets the CPU
+    pub fn reset(&mut self) {
+        self.cpu = Cpu::default();
+        self.memory = vec![0; MEMORY_SIZE];
+        self.memory[..self.image.len()].copy_from_slice(&self.image);
+        self.call_stack.clear();
+        self.writes.clear();
+        self.requested_irq = false;
+        for mapped in &mut self.devices {
+            mapped.device.reset();
+        }
+    }
+
+    /// Maps `device` over `range`, it takes precedence over memory and devices mapped before
+    pub fn map_device(&mut self, range: RangeInclusive<u16>, device: impl Device + 'static) {
+        self.devices.insert(
+            0,
+            MappedDevice {
+                range,
+                device: Box::new(device),
+            },
+        );
+    }
+
+    /// Makes `range` read-only
+    pub fn protect(&mut self, range: RangeInclusive<u16>) {
+        self.rom.push(range);
+    }
+
+    /// State of the interrupt request lines of all devices
+    pub fn irq_pending(&self) -> bool {
+        self.requested_irq || self.devices.iter().any(|d| d.device.irq())
+    }
+
+    /// Requests an interrupt, handled as soon as IRQ is set, eg. to test handlers without a device
+    pub fn request_interrupt(&mut self) {
+        self.requested_irq = true;
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Routines entered with `CALL`, outermost first
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// Times an instruction at `address` was executed. Coverage is kept across resets, so it
+    /// adds up over several runs, eg. a test suite
+    pub fn execution_count(&self, address: u16) -> u64 {
+        self.executed[address as usize]
+    }
+
+    /// Conditional jumps executed, by their address
+    pub fn branches(&self) -> &HashMap<u16, BranchCount> {
+        &self.branches
+    }
+
+    pub fn clear_coverage(&mut self) {
+        self.executed = vec![0; MEMORY_SIZE];
+        self.branches.clear();
+    }
+
+    /// Memory writes done by the last executed instruction
+    pub fn last_writes(&self) -> &[MemoryWrite] {
+        &self.writes
+    }
+
+    /// Opcode of the next instruction, `None` if the byte at PC is not a valid opcode
+    pub fn next_opcode(&self) -> Option<Opcode> {
+        Opcode::from_byte(self.memory[self.cpu.pc as usize])
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self) -> Result<Step, Fault> {
+        self.writes.clear();
+        let interrupt = self.cpu.flags.irq && !self.cpu.halted && self.irq_pending();
+        let mut bus = RecordingBus {
+            memory: &mut self.memory,
+            devices: &mut self.devices,
+            rom: &self.rom,
+            writes: &mut self.writes,
+            rom_write: None,
+        };
+        // Interrupts are taken between instructions, when IRQ is set
+        let step = if interrupt {
+            self.requested_irq = false;
+            self.cpu.interrupt(&mut bus)?
+        } else {
+            self.cpu.step(&mut bus)?
+        };
+        let rom_write = bus.rom_write;
+        if let Some(opcode) = step.opcode {
+            self.executed[step.address as usize] += 1;
+            if opcode.is_conditional_jump() {
+                let branch = self.branches.entry(step.address).or_default();
+                if self.cpu.pc == step.address.wrapping_add(opcode.size()) {
+                    branch.not_taken += 1;
+                } else {
+                    branch.taken += 1;
+                }
+            }
+        }
+        if step.opcode.is_some() {
+            for mapped in &mut self.devices {
+                mapped.device.tick();
+            }
+        }
+        if let Some(target) = rom_write {
+            return Err(Fault::RomWrite {
+                address: step.address,
+                target,
+            });
+        }
+        match step.event {
+            Some(Event::Call {
+                target,
+                return_address,
+            }) => {
+                self.call_stack.push(Frame {
+                    target,
+                    return_address,
+                    interrupt: false,
+                });
+            }
+            Some(Event::Interrupt {
+                target,
+                return_address,
+            }) => {
+                self.call_stack.push(Frame {
+                    target,
+                    return_address,
+                    interrupt: true,
+                });
+            }
+            Some(Event::Return { .. }) => {
+                self.call_stack.pop();
+            }
+            _ => (),
+        }
+        Ok(step)
+    }
+
+    /// Enters the routine at `target` as if `CALL` was executed at PC, its `RET` continues at PC
+    pub fn call(&mut self, target: u16) -> Result<(), Fault> {
+        let return_address = self.cpu.pc;
+        let mut bus = RecordingBus {
+            memory: &mut self.memory,
+            devices: &mut self.devices,
+            rom: &self.rom,
+            writes: &mut self.writes,
+            rom_write: None,
+        };
+        self.cpu.call(&mut bus, target, return_address)?;
+        self.call_stack.push(Frame {
+            target,
+            return_address,
+            interrupt: false,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,1026 @@
+use std::fmt::Display;
+
+use crate::opcodes::Opcode;
+
+/// Maximum number of values the A and B register stacks can hold
+pub const REGISTER_STACK_DEPTH: usize = 256;
+
+/// Number of bytes `CALL` pushes to the stack: flags, HI, LI, PC, `stack_size` and `stack_address`
+pub const CALL_FRAME_SIZE: u16 = 9;
+
+/// Memory as seen by the CPU
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub zero: bool,
+    pub overflow: bool,
+    pub error: bool,
+    pub ok: bool,
+    pub irq: bool, // interrupts enabled
+}
+
+impl Flags {
+    pub fn to_byte(self) -> u8 {
+        self.zero as u8
+            | (self.overflow as u8) << 1
+            | (self.error as u8) << 2
+            | (self.ok as u8) << 3
+            | (self.irq as u8) << 4
+    }
+
+    pub fn from_byte(byte: u8) -> Self {
+        Self {
+            zero: byte & 0b00001 != 0,
+            overflow: byte & 0b00010 != 0,
+            error: byte & 0b00100 != 0,
+            ok: byte & 0b01000 != 0,
+            irq: byte & 0b10000 != 0,
+        }
+    }
+}
+
+impl Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |set: bool, name: &'static str| if set { name } else { "---" };
+        write!(
+            f,
+            "{} {} {} {} {}",
+            flag(self.zero, "ZER"),
+            flag(self.overflow, "OVF"),
+            flag(self.error, "ERR"),
+            flag(self.ok, "OK "),
+            flag(self.irq, "IRQ")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    B,
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Register::A => write!(f, "A"),
+            Register::B => write!(f, "B"),
+        }
+    }
+}
+
+/// Reason the CPU could not execute an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    InvalidOpcode { address: u16, byte: u8 },
+    EmptyRegister { address: u16, register: Register },
+    RegisterOverflow { address: u16, register: Register },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    CallDisabled { address: u16 }, // `stack_size` == 0
+    RomWrite { address: u16, target: u16 },
+    Halted,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::InvalidOpcode { address, byte } => {
+                write!(f, "invalid opcode 0x{:02x} at 0x{:04x}", byte, address)
+            }
+            Fault::EmptyRegister { address, register } => {
+                write!(f, "register {} is empty at 0x{:04x}", register, address)
+            }
+            Fault::RegisterOverflow { address, register } => {
+                write!(f, "register {} overflown at 0x{:04x}", register, address)
+            }
+            Fault::StackOverflow { address } => write!(f, "stack overflow at 0x{:04x}", address),
+            Fault::StackUnderflow { address } => {
+                write!(f, "stack underflow at 0x{:04x}", address)
+            }
+            Fault::CallDisabled { address } => write!(
+                f,
+                "CALL/RET at 0x{:04x} with stack size 0, calls are disabled",
+                address
+            ),
+            Fault::RomWrite { address, target } => write!(
+                f,
+                "write to read-only memory at 0x{:04x} by the instruction at 0x{:04x}",
+                target, address
+            ),
+            Fault::Halted => write!(f, "the CPU is halted"),
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Control flow change caused by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Call { target: u16, return_address: u16 },
+    Interrupt { target: u16, return_address: u16 },
+    Return { to: u16 },
+    Halt,
+}
+
+/// Result of executing a single instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub address: u16,
+    pub opcode: Option<Opcode>, // `None` when entering an interrupt handler
+    pub event: Option<Event>,
+}
+
+/// State of the Nox CPU.
+///
+/// A and B are stacks of bytes - pushing to them keeps the previous values, reading uses the last one.
+/// AB is the pair of A (high byte) and B (low byte). HI and LI are plain registers, HLI is the pair
+/// of them. The memory stack starts at `stack_address` and grows upwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cpu {
+    pub pc: u16,
+    pub a: Vec<u8>,
+    pub b: Vec<u8>,
+    pub hi: u8,
+    pub li: u8,
+    pub exit_code: u8,
+    pub flags: Flags,
+    pub stack_address: u16,
+    pub stack_size: u16,
+    pub stack_pointer: u16,
+    pub irq_address: u16,
+    pub halted: bool,
+    pub interrupt_frames: Vec<u16>, // `stack_address` of the frames of running interrupt handlers
+}
+
+impl Default for Cpu {
+    /// Reset state. The stack takes the top of RAM of the default memory layout,
+    /// programs can move it with `POP AB SA` and `POP AB SS`
+    fn default() -> Self {
+        Self {
+            pc: 0x0000,
+            a: vec![],
+            b: vec![],
+            hi: 0,
+            li: 0,
+            exit_code: 0,
+            flags: Flags::default(),
+            stack_address: 0xf000,
+            stack_size: 0x0ff0,
+            stack_pointer: 0xf000,
+            irq_address: 0x0000,
+            halted: false,
+            interrupt_frames: vec![],
+        }
+    }
+}
+
+impl Cpu {
+    pub fn hli(&self) -> u16 {
+        u16::from_be_bytes([self.hi, self.li])
+    }
+
+    pub fn set_hli(&mut self, value: u16) {
+        [self.hi, self.li] = value.to_be_bytes();
+    }
+
+    fn register(&mut self, register: Register) -> &mut Vec<u8> {
+        match register {
+            Register::A => &mut self.a,
+            Register::B => &mut self.b,
+        }
+    }
+
+    pub fn peek(&self, register: Register) -> Option<u8> {
+        match register {
+            Register::A => self.a.last().copied(),
+            Register::B => self.b.last().copied(),
+        }
+    }
+
+    /// Executes the instruction at `pc`
+    pub fn step(&mut self, bus: &mut dyn Bus) -> Result<Step, Fault> {
+        if self.halted {
+            return Err(Fault::Halted);
+        }
+        let mut exec = Execution {
+            address: self.pc,
+            cpu: self,
+            bus,
+        };
+        exec.run()
+    }
+
+    /// Enters the interrupt handler at `irq_address`. The frame is the same as for `CALL`, with
+    /// IRQ cleared while the handler runs. The `RET` of the handler restores all flags (IRQ included)
+    /// and leaves the exit code unchanged, so the interrupted code can continue undisturbed
+    pub fn interrupt(&mut self, bus: &mut dyn Bus) -> Result<Step, Fault> {
+        let address = self.pc;
+        let target = self.irq_address;
+        let mut exec = Execution {
+            address,
+            cpu: self,
+            bus,
+        };
+        exec.call(target, address)?;
+        self.flags.irq = false;
+        self.interrupt_frames.push(self.stack_address);
+        Ok(Step {
+            address,
+            opcode: None,
+            event: Some(Event::Interrupt {
+                target,
+                return_address: address,
+            }),
+        })
+    }
+
+    /// Pushes the call frame and jumps to `target`, as `CALL` does.
+    /// `return_address` is where the matching `RET` continues.
+    pub fn call(
+        &mut self,
+        bus: &mut dyn Bus,
+        target: u16,
+        return_address: u16,
+    ) -> Result<(), Fault> {
+        let mut exec = Execution {
+            address: self.pc,
+            cpu: self,
+            bus,
+        };
+        exec.call(target, return_address)
+    }
+}
+
+struct Execution<'a> {
+    address: u16, // of the instruction being executed
+    cpu: &'a mut Cpu,
+    bus: &'a mut dyn Bus,
+}
+
+impl Execution<'_> {
+    fn fetch8(&mut self) -> u8 {
+        let value = self.bus.read(self.cpu.pc);
+        self.cpu.pc = self.cpu.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        u16::from_be_bytes([self.fetch8(), self.fetch8()])
+    }
+
+    fn read16(&mut self, address: u16) -> u16 {
+        u16::from_be_bytes([
+            self.bus.read(address),
+            self.bus.read(address.wrapping_add(1)),
+        ])
+    }
+
+    fn write16(&mut self, address: u16, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.bus.write(address, high);
+        self.bus.write(address.wrapping_add(1), low);
+    }
+
+    fn peek(&mut self, register: Register) -> Result<u8, Fault> {
+        self.cpu.peek(register).ok_or(Fault::EmptyRegister {
+            address: self.address,
+            register,
+        })
+    }
+
+    fn pop(&mut self, register: Register) -> Result<u8, Fault> {
+        let address = self.address;
+        self.cpu
+            .register(register)
+            .pop()
+            .ok_or(Fault::EmptyRegister { address, register })
+    }
+
+    fn push(&mut self, register: Register, value: u8) -> Result<(), Fault> {
+        let address = self.address;
+        let stack = self.cpu.register(register);
+        if stack.len() >= REGISTER_STACK_DEPTH {
+            return Err(Fault::RegisterOverflow { address, register });
+        }
+        stack.push(value);
+        Ok(())
+    }
+
+    fn peek_ab(&mut self) -> Result<u16, Fault> {
+        Ok(u16::from_be_bytes([
+            self.peek(Register::A)?,
+            self.peek(Register::B)?,
+        ]))
+    }
+
+    fn pop_ab(&mut self) -> Result<u16, Fault> {
+        Ok(u16::from_be_bytes([
+            self.pop(Register::A)?,
+            self.pop(Register::B)?,
+        ]))
+    }
+
+    fn push_ab(&mut self, value: u16) -> Result<(), Fault> {
+        let [high, low] = value.to_be_bytes();
+        self.push(Register::A, high)?;
+        self.push(Register::B, low)
+    }
+
+    /// Replaces the last value of the register, used by the shifts
+    fn replace(&mut self, register: Register, value: u8) -> Result<(), Fault> {
+        self.pop(register)?;
+        self.push(register, value)
+    }
+
+    fn stack_push(&mut self, value: u8) -> Result<(), Fault> {
+        let used = self.cpu.stack_pointer.wrapping_sub(self.cpu.stack_address);
+        if used >= self.cpu.stack_size {
+            return Err(Fault::StackOverflow {
+                address: self.address,
+            });
+        }
+        self.bus.write(self.cpu.stack_pointer, value);
+        self.cpu.stack_pointer = self.cpu.stack_pointer.wrapping_add(1);
+        Ok(())
+    }
+
+    fn stack_pop(&mut self) -> Result<u8, Fault> {
+        if self.cpu.stack_pointer == self.cpu.stack_address {
+            return Err(Fault::StackUnderflow {
+                address: self.address,
+            });
+        }
+        self.cpu.stack_pointer = self.cpu.stack_pointer.wrapping_sub(1);
+        Ok(self.bus.read(self.cpu.stack_pointer))
+    }
+
+    fn set_result_flags(&mut self, zero: bool, overflow: bool) {
+        self.cpu.flags.zero = zero;
+        self.cpu.flags.overflow = overflow;
+    }
+
+    fn add8(&mut self, x: u8, y: u8) -> u8 {
+        let (result, carry) = x.overflowing_add(y);
+        self.set_result_flags(result == 0, carry);
+        result
+    }
+
+    fn sub8(&mut self, x: u8, y: u8) -> u8 {
+        let (result, borrow) = x.overflowing_sub(y);
+        self.set_result_flags(result == 0, borrow);
+        result
+    }
+
+    fn add16(&mut self, x: u16, y: u16) -> u16 {
+        let (result, carry) = x.overflowing_add(y);
+        self.set_result_flags(result == 0, carry);
+        result
+    }
+
+    fn sub16(&mut self, x: u16, y: u16) -> u16 {
+        let (result, borrow) = x.overflowing_sub(y);
+        self.set_result_flags(result == 0, borrow);
+        result
+    }
+
+    fn logic8(&mut self, result: u8) -> u8 {
+        self.set_result_flags(result == 0, false);
+        result
+    }
+
+    fn logic16(&mut self, result: u16) -> u16 {
+        self.set_result_flags(result == 0, false);
+        result
+    }
+
+    /// ZERO if equal, OVF if `x` > `y`, both cleared if `x` < `y`
+    fn compare(&mut self, x: u16, y: u16) {
+        self.set_result_flags(x == y, x > y);
+    }
+
+    fn shift8(&mut self, register: Register, left: bool) -> Result<(), Fault> {
+        let value = self.peek(register)?;
+        let (result, out) = if left {
+            (value << 1, value & 0x80 != 0)
+        } else {
+            (value >> 1, value & 0x01 != 0)
+        };
+        self.set_result_flags(result == 0, out);
+        self.replace(register, result)
+    }
+
+    fn jump_if(&mut self, condition: bool) {
+        let target = self.fetch16();
+        if condition {
+            self.cpu.pc = target;
+        }
+    }
+
+    fn push_registers_to_stack(
+        &mut self,
+        register: Register,
+        count: u8,
+        remove: bool,
+    ) -> Result<(), Fault> {
+        let values = self.cpu.register(register).clone();
+        if values.len() < count as usize {
+            return Err(Fault::EmptyRegister {
+                address: self.address,
+                register,
+            });
+        }
+        // Deepest value first, so popping them back restores the order
+        for value in &values[values.len() - count as usize..] {
+            self.stack_push(*value)?;
+        }
+        if remove {
+            let len = values.len() - count as usize;
+            self.cpu.register(register).truncate(len);
+        }
+        Ok(())
+    }
+
+    fn pop_stack_to_register(&mut self, register: Register, count: u8) -> Result<(), Fault> {
+        let mut values = vec![];
+        for _ in 0..count {
+            values.push(self.stack_pop()?);
+        }
+        for value in values.into_iter().rev() {
+            self.push(register, value)?;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, target: u16, return_address: u16) -> Result<(), Fault> {
+        if self.cpu.stack_size == 0 {
+            return Err(Fault::CallDisabled {
+                address: self.address,
+            });
+        }
+        let [pc_high, pc_low] = return_address.to_be_bytes();
+        let [size_high, size_low] = self.cpu.stack_size.to_be_bytes();
+        let [address_high, address_low] = self.cpu.stack_address.to_be_bytes();
+        for value in [
+            self.cpu.flags.to_byte(),
+            self.cpu.hi,
+            self.cpu.li,
+            pc_high,
+            pc_low,
+            size_high,
+            size_low,
+            address_high,
+            address_low,
+        ] {
+            self.stack_push(value)?;
+        }
+        let used = self.cpu.stack_pointer.wrapping_sub(self.cpu.stack_address);
+        self.cpu.stack_size -= used;
+        self.cpu.stack_address = self.cpu.stack_pointer;
+        self.cpu.pc = target;
+        Ok(())
+    }
+
+    fn return_(&mut self, error: bool, exit_code: u8) -> Result<Event, Fault> {
+        if self.cpu.stack_size == 0 {
+            return Err(Fault::CallDisabled {
+                address: self.address,
+            });
+        }
+        // The frame pushed by `CALL` is right below the current stack
+        let mut frame = [0u8; CALL_FRAME_SIZE as usize];
+        let base = self.cpu.stack_address.wrapping_sub(CALL_FRAME_SIZE);
+        for (offset, value) in frame.iter_mut().enumerate() {
+            *value = self.bus.read(base.wrapping_add(offset as u16));
+        }
+        let [flags, hi, li, pc_high, pc_low, size_high, size_low, address_high, address_low] =
+            frame;
+        let interrupt = self.cpu.interrupt_frames.last() == Some(&self.cpu.stack_address);
+        if interrupt {
+            self.cpu.interrupt_frames.pop();
+        }
+        self.cpu.stack_address = u16::from_be_bytes([address_high, address_low]);
+        self.cpu.stack_size = u16::from_be_bytes([size_high, size_low]);
+        self.cpu.stack_pointer = base;
+        self.cpu.pc = u16::from_be_bytes([pc_high, pc_low]);
+        self.cpu.hi = hi;
+        self.cpu.li = li;
+        if interrupt {
+            self.cpu.flags = Flags::from_byte(flags);
+        } else {
+            self.cpu.flags = Flags {
+                error,
+                ok: !error,
+                ..Flags::from_byte(flags)
+            };
+            self.cpu.exit_code = exit_code;
+        }
+        Ok(Event::Return { to: self.cpu.pc })
+    }
+
+    fn run(&mut self) -> Result<Step, Fault> {
+        use Register::{A, B};
+
+        let byte = self.fetch8();
+        let opcode = Opcode::from_byte(byte).ok_or(Fault::InvalidOpcode {
+            address: self.address,
+            byte,
+        })?;
+        let mut event = None;
+        match opcode {
+            Opcode::NOOP => (),
+
+            Opcode::PUSH_IMMEDIATE_A => {
+                let value = self.fetch8();
+                self.push(A, value)?
+            }
+            Opcode::PUSH_IMMEDIATE_B => {
+                let value = self.fetch8();
+                self.push(B, value)?
+            }
+            Opcode::PUSH_ABSOLUTE_A => {
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                self.push(A, value)?
+            }
+            Opcode::PUSH_ABSOLUTE_B => {
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                self.push(B, value)?
+            }
+            Opcode::PUSH_INDIRECT_A => {
+                let value = self.bus.read(self.cpu.hli());
+                self.push(A, value)?
+            }
+            Opcode::PUSH_INDIRECT_B => {
+                let value = self.bus.read(self.cpu.hli());
+                self.push(B, value)?
+            }
+            Opcode::PUSH_A_B => {
+                let value = self.peek(A)?;
+                self.push(B, value)?
+            }
+            Opcode::PUSH_B_A => {
+                let value = self.peek(B)?;
+                self.push(A, value)?
+            }
+            Opcode::PUSH_HI_A => self.push(A, self.cpu.hi)?,
+            Opcode::PUSH_LI_A => self.push(A, self.cpu.li)?,
+            Opcode::PUSH_HI_B => self.push(B, self.cpu.hi)?,
+            Opcode::PUSH_LI_B => self.push(B, self.cpu.li)?,
+            Opcode::PUSH_EXIT_CODE_A => self.push(A, self.cpu.exit_code)?,
+            Opcode::PUSH_EXIT_CODE_B => self.push(B, self.cpu.exit_code)?,
+
+            Opcode::POP_A => {
+                self.pop(A)?;
+            }
+            Opcode::POP_B => {
+                self.pop(B)?;
+            }
+            Opcode::POP_A_ABSOLUTE => {
+                let address = self.fetch16();
+                let value = self.pop(A)?;
+                self.bus.write(address, value)
+            }
+            Opcode::POP_B_ABSOLUTE => {
+                let address = self.fetch16();
+                let value = self.pop(B)?;
+                self.bus.write(address, value)
+            }
+            Opcode::POP_A_INDIRECT => {
+                let value = self.pop(A)?;
+                self.bus.write(self.cpu.hli(), value)
+            }
+            Opcode::POP_B_INDIRECT => {
+                let value = self.pop(B)?;
+                self.bus.write(self.cpu.hli(), value)
+            }
+            Opcode::POP_A_B => {
+                let value = self.pop(A)?;
+                self.push(B, value)?
+            }
+            Opcode::POP_B_A => {
+                let value = self.pop(B)?;
+                self.push(A, value)?
+            }
+            Opcode::POP_A_HI => self.cpu.hi = self.pop(A)?,
+            Opcode::POP_A_LI => self.cpu.li = self.pop(A)?,
+            Opcode::POP_B_HI => self.cpu.hi = self.pop(B)?,
+            Opcode::POP_B_LI => self.cpu.li = self.pop(B)?,
+
+            Opcode::PEEK_A_ABSOLUTE => {
+                let address = self.fetch16();
+                let value = self.peek(A)?;
+                self.bus.write(address, value)
+            }
+            Opcode::PEEK_B_ABSOLUTE => {
+                let address = self.fetch16();
+                let value = self.peek(B)?;
+                self.bus.write(address, value)
+            }
+            Opcode::PEEK_A_INDIRECT => {
+                let value = self.peek(A)?;
+                self.bus.write(self.cpu.hli(), value)
+            }
+            Opcode::PEEK_B_INDIRECT => {
+                let value = self.peek(B)?;
+                self.bus.write(self.cpu.hli(), value)
+            }
+
+            Opcode::ADD_A_B | Opcode::ADD_B_A => {
+                let (a, b) = (self.peek(A)?, self.peek(B)?);
+                let result = self.add8(a, b);
+                self.push(if opcode == Opcode::ADD_A_B { B } else { A }, result)?
+            }
+            Opcode::ADD_IMMEDIATE_A | Opcode::ADD_IMMEDIATE_B => {
+                let register = if opcode == Opcode::ADD_IMMEDIATE_A {
+                    A
+                } else {
+                    B
+                };
+                let value = self.fetch8();
+                let x = self.peek(register)?;
+                let result = self.add8(x, value);
+                self.push(register, result)?
+            }
+            Opcode::ADD_ABSOLUTE_A | Opcode::ADD_ABSOLUTE_B => {
+                let register = if opcode == Opcode::ADD_ABSOLUTE_A {
+                    A
+                } else {
+                    B
+                };
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                let x = self.peek(register)?;
+                let result = self.add8(x, value);
+                self.push(register, result)?
+            }
+
+            Opcode::SUB_A_B => {
+                let (a, b) = (self.peek(A)?, self.peek(B)?);
+                let result = self.sub8(a, b);
+                self.push(B, result)?
+            }
+            Opcode::SUB_B_A => {
+                let (a, b) = (self.peek(A)?, self.peek(B)?);
+                let result = self.sub8(b, a);
+                self.push(A, result)?
+            }
+            Opcode::SUB_IMMEDIATE_A | Opcode::SUB_IMMEDIATE_B => {
+                let register = if opcode == Opcode::SUB_IMMEDIATE_A {
+                    A
+                } else {
+                    B
+                };
+                let value = self.fetch8();
+                let x = self.peek(register)?;
+                let result = self.sub8(x, value);
+                self.push(register, result)?
+            }
+            Opcode::SUB_ABSOLUTE_A | Opcode::SUB_ABSOLUTE_B => {
+                let register = if opcode == Opcode::SUB_ABSOLUTE_A {
+                    A
+                } else {
+                    B
+                };
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                let x = self.peek(register)?;
+                let result = self.sub8(x, value);
+                self.push(register, result)?
+            }
+
+            Opcode::SHIFT_LEFT_A => self.shift8(A, true)?,
+            Opcode::SHIFT_RIGHT_A => self.shift8(A, false)?,
+            Opcode::SHIFT_LEFT_B => self.shift8(B, true)?,
+            Opcode::SHIFT_RIGHT_B => self.shift8(B, false)?,
+
+            Opcode::AND_A_B
+            | Opcode::AND_B_A
+            | Opcode::OR_A_B
+            | Opcode::OR_B_A
+            | Opcode::XOR_A_B
+            | Opcode::XOR_B_A => {
+                let (a, b) = (self.peek(A)?, self.peek(B)?);
+                let (result, target) = match opcode {
+                    Opcode::AND_A_B => (a & b, B),
+                    Opcode::AND_B_A => (a & b, A),
+                    Opcode::OR_A_B => (a | b, B),
+                    Opcode::OR_B_A => (a | b, A),
+                    Opcode::XOR_A_B => (a ^ b, B),
+                    _ => (a ^ b, A),
+                };
+                let result = self.logic8(result);
+                self.push(target, result)?
+            }
+            Opcode::NOT_A | Opcode::NOT_B => {
+                let register = if opcode == Opcode::NOT_A { A } else { B };
+                let value = self.peek(register)?;
+                let result = self.logic8(!value);
+                self.push(register, result)?
+            }
+
+            Opcode::CMP_A_B => {
+                let (a, b) = (self.peek(A)?, self.peek(B)?);
+                self.compare(a as u16, b as u16)
+            }
+            Opcode::CMP_IMMEDIATE_A | Opcode::CMP_IMMEDIATE_B => {
+                let value = self.fetch8();
+                let x = self.peek(if opcode == Opcode::CMP_IMMEDIATE_A {
+                    A
+                } else {
+                    B
+                })?;
+                self.compare(x as u16, value as u16)
+            }
+            Opcode::CMP_ABSOLUTE_A | Opcode::CMP_ABSOLUTE_B => {
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                let x = self.peek(if opcode == Opcode::CMP_ABSOLUTE_A {
+                    A
+                } else {
+                    B
+                })?;
+                self.compare(x as u16, value as u16)
+            }
+
+            Opcode::PUSH_IMMEDIATE_HI => self.cpu.hi = self.fetch8(),
+            Opcode::PUSH_IMMEDIATE_LI => self.cpu.li = self.fetch8(),
+            Opcode::PUSH_ABSOLUTE_HI => {
+                let address = self.fetch16();
+                self.cpu.hi = self.bus.read(address)
+            }
+            Opcode::PUSH_ABSOLUTE_LI => {
+                let address = self.fetch16();
+                self.cpu.li = self.bus.read(address)
+            }
+            Opcode::STORE_HI_ABSOLUTE => {
+                let address = self.fetch16();
+                self.bus.write(address, self.cpu.hi)
+            }
+            Opcode::STORE_LI_ABSOLUTE => {
+                let address = self.fetch16();
+                self.bus.write(address, self.cpu.li)
+            }
+            Opcode::CMP_IMMEDIATE_HI => {
+                let value = self.fetch8();
+                self.compare(self.cpu.hi as u16, value as u16)
+            }
+            Opcode::CMP_IMMEDIATE_LI => {
+                let value = self.fetch8();
+                self.compare(self.cpu.li as u16, value as u16)
+            }
+            Opcode::CMP_ABSOLUTE_HI => {
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                self.compare(self.cpu.hi as u16, value as u16)
+            }
+            Opcode::CMP_ABSOLUTE_LI => {
+                let address = self.fetch16();
+                let value = self.bus.read(address);
+                self.compare(self.cpu.li as u16, value as u16)
+            }
+            Opcode::INC_HI => {
+                self.cpu.hi = self.cpu.hi.wrapping_add(1);
+                self.set_result_flags(self.cpu.hi == 0, self.cpu.hi == 0)
+            }
+            Opcode::INC_LI => {
+                self.cpu.li = self.cpu.li.wrapping_add(1);
+                self.set_result_flags(self.cpu.li == 0, self.cpu.li == 0)
+            }
+            Opcode::DEC_HI => {
+                self.cpu.hi = self.cpu.hi.wrapping_sub(1);
+                self.set_result_flags(self.cpu.hi == 0, self.cpu.hi == 0xff)
+            }
+            Opcode::DEC_LI => {
+                self.cpu.li = self.cpu.li.wrapping_sub(1);
+                self.set_result_flags(self.cpu.li == 0, self.cpu.li == 0xff)
+            }
+            Opcode::ZERO_HI => self.cpu.hi = 0,
+            Opcode::ZERO_LI => self.cpu.li = 0,
+            Opcode::SWAP_HI_LI => std::mem::swap(&mut self.cpu.hi, &mut self.cpu.li),
+
+            Opcode::PUSH_IMMEDIATE_AB => {
+                let value = self.fetch16();
+                self.push_ab(value)?
+            }
+            Opcode::PUSH_ABSOLUTE_AB => {
+                let address = self.fetch16();
+                let value = self.read16(address);
+                self.push_ab(value)?
+            }
+            Opcode::PUSH_INDIRECT_AB => {
+                let value = self.read16(self.cpu.hli());
+                self.push_ab(value)?
+            }
+            Opcode::PUSH_HLI_AB => self.push_ab(self.cpu.hli())?,
+            Opcode::POP_AB_ABSOLUTE => {
+                let address = self.fetch16();
+                let value = self.pop_ab()?;
+                self.write16(address, value)
+            }
+            Opcode::POP_AB_INDIRECT => {
+                let value = self.pop_ab()?;
+                self.write16(self.cpu.hli(), value)
+            }
+            Opcode::POP_AB_HLI => {
+                let value = self.pop_ab()?;
+                self.cpu.set_hli(value)
+            }
+            Opcode::PEEK_AB_ABSOLUTE => {
+                let address = self.fetch16();
+                let value = self.peek_ab()?;
+                self.write16(address, value)
+            }
+            Opcode::PEEK_AB_INDIRECT => {
+                let value = self.peek_ab()?;
+                self.write16(self.cpu.hli(), value)
+            }
+            Opcode::ADD_IMMEDIATE_AB
+            | Opcode::ADD_ABSOLUTE_AB
+            | Opcode::SUB_IMMEDIATE_AB
+            | Opcode::SUB_ABSOLUTE_AB
+            | Opcode::AND_IMMEDIATE_AB
+            | Opcode::AND_ABSOLUTE_AB
+            | Opcode::OR_IMMEDIATE_AB
+            | Opcode::OR_ABSOLUTE_AB
+            | Opcode::XOR_IMMEDIATE_AB
+            | Opcode::XOR_ABSOLUTE_AB => {
+                let operand = self.fetch16();
+                let value = match opcode {
+                    Opcode::ADD_ABSOLUTE_AB
+                    | Opcode::SUB_ABSOLUTE_AB
+                    | Opcode::AND_ABSOLUTE_AB
+                    | Opcode::OR_ABSOLUTE_AB
+                    | Opcode::XOR_ABSOLUTE_AB => self.read16(operand),
+                    _ => operand,
+                };
+                let ab = self.peek_ab()?;
+                let result = match opcode {
+                    Opcode::ADD_IMMEDIATE_AB | Opcode::ADD_ABSOLUTE_AB => self.add16(ab, value),
+                    Opcode::SUB_IMMEDIATE_AB | Opcode::SUB_ABSOLUTE_AB => self.sub16(ab, value),
+                    Opcode::AND_IMMEDIATE_AB | Opcode::AND_ABSOLUTE_AB => self.logic16(ab & value),
+                    Opcode::OR_IMMEDIATE_AB | Opcode::OR_ABSOLUTE_AB => self.logic16(ab | value),
+                    _ => self.logic16(ab ^ value),
+                };
+                self.push_ab(result)?
+            }
+            Opcode::SHIFT_LEFT_AB | Opcode::SHIFT_RIGHT_AB => {
+                let value = self.peek_ab()?;
+                let (result, out) = if opcode == Opcode::SHIFT_LEFT_AB {
+                    (value << 1, value & 0x8000 != 0)
+                } else {
+                    (value >> 1, value & 0x0001 != 0)
+                };
+                self.set_result_flags(result == 0, out);
+                self.pop_ab()?;
+                self.push_ab(result)?
+            }
+            Opcode::NOT_AB => {
+                let value = self.peek_ab()?;
+                let result = self.logic16(!value);
+                self.push_ab(result)?
+            }
+
+            Opcode::PUSH_IMMEDIATE_HLI => {
+                let value = self.fetch16();
+                self.cpu.set_hli(value)
+            }
+            Opcode::PUSH_ABSOLUTE_HLI => {
+                let address = self.fetch16();
+                let value = self.read16(address);
+                self.cpu.set_hli(value)
+            }
+            Opcode::STORE_HLI_ABSOLUTE => {
+                let address = self.fetch16();
+                self.write16(address, self.cpu.hli())
+            }
+            Opcode::CMP_IMMEDIATE_AB | Opcode::CMP_ABSOLUTE_AB => {
+                let operand = self.fetch16();
+                let value = if opcode == Opcode::CMP_ABSOLUTE_AB {
+                    self.read16(operand)
+                } else {
+                    operand
+                };
+                let ab = self.peek_ab()?;
+                self.compare(ab, value)
+            }
+            Opcode::CMP_IMMEDIATE_HLI | Opcode::CMP_ABSOLUTE_HLI => {
+                let operand = self.fetch16();
+                let value = if opcode == Opcode::CMP_ABSOLUTE_HLI {
+                    self.read16(operand)
+                } else {
+                    operand
+                };
+                self.compare(self.cpu.hli(), value)
+            }
+            Opcode::INC_HLI => {
+                let value = self.cpu.hli().wrapping_add(1);
+                self.cpu.set_hli(value);
+                self.set_result_flags(value == 0, value == 0)
+            }
+            Opcode::DEC_HLI => {
+                let value = self.cpu.hli().wrapping_sub(1);
+                self.cpu.set_hli(value);
+                self.set_result_flags(value == 0, value == 0xffff)
+            }
+            Opcode::ZERO_HLI => self.cpu.set_hli(0),
+
+            Opcode::JUMP_IF_ZERO => self.jump_if(self.cpu.flags.zero),
+            Opcode::JUMP_IF_OVERFLOW => self.jump_if(self.cpu.flags.overflow),
+            Opcode::JUMP_IF_ERROR => self.jump_if(self.cpu.flags.error),
+            Opcode::JUMP_IF_OK => self.jump_if(self.cpu.flags.ok),
+            Opcode::JUMP => self.jump_if(true),
+
+            Opcode::PUSH_AB_STACK_ADDRESS => {
+                let value = self.cpu.stack_address;
+                self.cpu.flags.zero = value == 0;
+                self.push_ab(value)?
+            }
+            Opcode::PUSH_AB_STACK_SIZE => {
+                let value = self.cpu.stack_size;
+                self.cpu.flags.zero = value == 0;
+                self.push_ab(value)?
+            }
+            Opcode::POP_STACK_ADDRESS_AB => {
+                let value = self.pop_ab()?;
+                self.cpu.stack_address = value;
+                self.cpu.stack_pointer = value;
+            }
+            Opcode::POP_STACK_SIZE_AB => self.cpu.stack_size = self.pop_ab()?,
+            Opcode::POP_AB_IRQ => self.cpu.irq_address = self.pop_ab()?,
+            Opcode::PUSH_A_STACK => {
+                let count = self.fetch8();
+                self.push_registers_to_stack(A, count, false)?
+            }
+            Opcode::POP_A_STACK => {
+                let count = self.fetch8();
+                self.push_registers_to_stack(A, count, true)?
+            }
+            Opcode::POP_STACK_A => {
+                let count = self.fetch8();
+                self.pop_stack_to_register(A, count)?
+            }
+            Opcode::PUSH_B_STACK => {
+                let count = self.fetch8();
+                self.push_registers_to_stack(B, count, false)?
+            }
+            Opcode::POP_B_STACK => {
+                let count = self.fetch8();
+                self.push_registers_to_stack(B, count, true)?
+            }
+            Opcode::POP_STACK_B => {
+                let count = self.fetch8();
+                self.pop_stack_to_register(B, count)?
+            }
+            Opcode::PUSH_HI_STACK => self.stack_push(self.cpu.hi)?,
+            Opcode::POP_STACK_HI => self.cpu.hi = self.stack_pop()?,
+            Opcode::PUSH_LI_STACK => self.stack_push(self.cpu.li)?,
+            Opcode::POP_STACK_LI => self.cpu.li = self.stack_pop()?,
+
+            Opcode::CALL => {
+                let target = self.fetch16();
+                let return_address = self.cpu.pc;
+                self.call(target, return_address)?;
+                event = Some(Event::Call {
+                    target,
+                    return_address,
+                });
+            }
+            Opcode::RETURN_OK => event = Some(self.return_(false, 0)?),
+            Opcode::RETURN_OK_EXIT_CODE => {
+                let exit_code = self.fetch8();
+                event = Some(self.return_(false, exit_code)?)
+            }
+            Opcode::RETURN_ERR => event = Some(self.return_(true, 0)?),
+            Opcode::RETURN_ERR_EXIT_CODE => {
+                let exit_code = self.fetch8();
+                event = Some(self.return_(true, exit_code)?)
+            }
+
+            Opcode::SET_ERR => self.cpu.flags.error = true,
+            Opcode::SET_IRQ => self.cpu.flags.irq = true,
+            Opcode::CLEAR_EXIT_CODE => self.cpu.exit_code = 0,
+            Opcode::CLEAR_ERR => self.cpu.flags.error = false,
+            Opcode::CLEAR_IRQ => self.cpu.flags.irq = false,
+            Opcode::CLEAR_OVF => self.cpu.flags.overflow = false,
+            Opcode::CLEAR_ZERO => self.cpu.flags.zero = false,
+
+            Opcode::HALT => {
+                self.cpu.pc = self.address; // stays on HALT
+                self.cpu.halted = true;
+                event = Some(Event::Halt);
+            }
+        }
+        Ok(Step {
+            address: self.address,
+            opcode: Some(opcode),
+            event,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+//! Built-in memory-mapped devices.
+
+use std::{
+    collections::VecDeque,
+    io::{BufReader, Read, Write},
+    sync::mpsc::{self, Receiver},
+};
+
+use super::Device;
+
+/// UART-like console. Registers, relative to the mapped address:
+/// - `+0` DATA: reading takes the next received byte (0 if there is none), writing sends a byte
+/// - `+1` STATUS: bit 0 - a received byte is waiting, bit 1 - ready to send (always set)
+/// - `+2` CONTROL: bit 0 - raise IRQ while a received byte is waiting
+pub struct Console {
+    input: Option<Receiver<u8>>,
+    received: VecDeque<u8>,
+    output: Box<dyn Write>,
+    control: u8,
+}
+
+impl Console {
+    pub const SIZE: u16 = 3;
+
+    /// Bytes are read from `input` on a background thread, so the program never blocks on it
+    pub fn new(input: impl Read + Send + 'static, output: impl Write + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for byte in BufReader::new(input).bytes() {
+                let Ok(byte) = byte else { break };
+                if sender.send(byte).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            input: Some(receiver),
+            received: VecDeque::new(),
+            output: Box::new(output),
+            control: 0,
+        }
+    }
+
+    /// Console without any input
+    pub fn output_only(output: impl Write + 'static) -> Self {
+        Self {
+            input: None,
+            received: VecDeque::new(),
+            output: Box::new(output),
+            control: 0,
+        }
+    }
+
+    pub fn stdio() -> Self {
+        Self::new(std::io::stdin(), std::io::stdout())
+    }
+
+    fn receive(&mut self) {
+        if let Some(input) = &self.input {
+            self.received.extend(input.try_iter());
+        }
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.receive();
+        match offset {
+            0 => self.received.pop_front().unwrap_or_default(),
+            1 => !self.received.is_empty() as u8 | 0b10,
+            2 => self.control,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0 => {
+                // The program cannot do anything about a closed output, so errors are ignored
+                let _ = self.output.write_all(&[value]);
+                let _ = self.output.flush();
+            }
+            2 => self.control = value,
+            _ => (),
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.control & 1 != 0 && !self.received.is_empty()
+    }
+
+    fn tick(&mut self) {
+        self.receive();
+    }
+
+    /// Interrupts are disabled and bytes received so far are dropped
+    fn reset(&mut self) {
+        self.receive();
+        self.received.clear();
+        self.control = 0;
+    }
+}
+
+/// Down-counting timer clocked by executed instructions. Registers, relative to the mapped address:
+/// - `+0..+1` RELOAD (big endian): value loaded to the counter when it is started or expires
+/// - `+2..+3` COUNTER (big endian, read-only)
+/// - `+4` CONTROL: bit 0 - running, bit 1 - raise IRQ when expired, bit 2 - restart when expired.
+///   Writing it with bit 0 set (re)starts the counter from RELOAD
+/// - `+5` STATUS: bit 0 - expired, writing any value clears it
+#[derive(Debug, Default)]
+pub struct Timer {
+    reload: u16,
+    counter: u16,
+    control: u8,
+    expired: bool,
+}
+
+impl Timer {
+    pub const SIZE: u16 = 6;
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset {
+            0 => self.reload.to_be_bytes()[0],
+            1 => self.reload.to_be_bytes()[1],
+            2 => self.counter.to_be_bytes()[0],
+            3 => self.counter.to_be_bytes()[1],
+            4 => self.control,
+            5 => self.expired as u8,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset {
+            0 => self.reload = u16::from_be_bytes([value, self.reload.to_be_bytes()[1]]),
+            1 => self.reload = u16::from_be_bytes([self.reload.to_be_bytes()[0], value]),
+            4 => {
+                self.control = value;
+                if value & 1 != 0 {
+                    self.counter = self.reload;
+                }
+            }
+            5 => self.expired = false,
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.control & 1 == 0 {
+            return;
+        }
+        self.counter = self.counter.saturating_sub(1);
+        if self.counter == 0 {
+            self.expired = true;
+            if self.control & 0b100 != 0 && self.reload > 0 {
+                self.counter = self.reload;
+            } else {
+                self.control &= !1;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.control & 0b10 != 0 && self.expired
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,235 @@
+//! Source formatter. Lines are split into words the same way the assembler tokenizes them, so
+//! formatting never changes what a program assembles to:
+//!
+//! ```text
+//! main:
+//!     PUSH 0x0100 AB
+//!     POP  AB     timer_reload
+//!     PUSH 0x05   A // running, restart when expired
+//!     POP  A      timer_control
+//! ```
+//!
+//! - mnemonics, registers, flags and `&HLI` are upper case, hex literals and directives lower case
+//! - instructions under a label, `.proc` or `.test` and `.expect` are indented, everything else
+//!   starts at the first column
+//! - operands of consecutive instructions are aligned into columns
+//! - trailing comments of consecutive lines are aligned into a column
+//! - runs of blank lines are collapsed into one
+
+use crate::opcodes::{FLAGS, MNEMONICS, PSEUDO_MNEMONICS, REGISTERS};
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Blank,
+    Comment { indented: bool },
+    Instruction,
+    Other { indented: bool },
+}
+
+struct Line {
+    kind: Kind,
+    words: Vec<String>,
+    comment: Option<String>, // including the `//`
+}
+
+/// Formats a whole source file
+pub fn format(source: &str) -> String {
+    let mut lines: Vec<Line> = source.lines().map(parse_line).collect();
+    // Blank lines at the start and end and more than one in a row are dropped
+    lines.dedup_by(|b, a| a.kind == Kind::Blank && b.kind == Kind::Blank);
+    while lines.first().is_some_and(|l| l.kind == Kind::Blank) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|l| l.kind == Kind::Blank) {
+        lines.pop();
+    }
+
+    let bodies = bodies(&lines);
+    let mut code: Vec<String> = vec![String::new(); lines.len()];
+    for run in runs(&lines, |l| l.kind == Kind::Instruction) {
+        // Every column is as wide as its widest word, the last word of a line is not padded
+        let mut widths = vec![];
+        for line in &lines[run.clone()] {
+            for (n, word) in line.words.iter().enumerate() {
+                if widths.len() == n {
+                    widths.push(0);
+                }
+                widths[n] = widths[n].max(word.len());
+            }
+        }
+        for n in run {
+            let words = &lines[n].words;
+            let mut text = if bodies[n] { INDENT } else { "" }.to_owned();
+            for (column, word) in words.iter().enumerate() {
+                if column + 1 == words.len() {
+                    text.push_str(word);
+                } else {
+                    text.push_str(&format!("{:width$} ", word, width = widths[column]));
+                }
+            }
+            code[n] = text;
+        }
+    }
+    for (n, line) in lines.iter().enumerate() {
+        let indent = match line.kind {
+            Kind::Comment { indented } | Kind::Other { indented } if indented => INDENT,
+            _ => "",
+        };
+        if line.kind != Kind::Instruction {
+            code[n] = format!("{}{}", indent, line.words.join(" "));
+        }
+    }
+    let trailing = |l: &Line| l.comment.is_some() && !matches!(l.kind, Kind::Comment { .. });
+    for run in runs(&lines, trailing) {
+        let width = run.clone().map(|n| code[n].len()).max().unwrap_or_default();
+        for n in run {
+            code[n] = format!("{:width$} ", code[n], width = width);
+        }
+    }
+
+    let mut output = String::new();
+    for (line, code) in lines.iter().zip(code) {
+        output.push_str(&code);
+        output.push_str(line.comment.as_deref().unwrap_or_default());
+        output.push('\n');
+    }
+    output
+}
+
+/// Lines inside the body of a label, `.proc` or `.test`, which runs until the end of the block,
+/// a `.section` or a `>` address
+fn bodies(lines: &[Line]) -> Vec<bool> {
+    let mut body = false;
+    lines
+        .iter()
+        .map(|line| {
+            let inside = body;
+            let first = line.words.first().map(|w| w.to_uppercase());
+            match first.as_deref() {
+                Some(".PROC" | ".TEST") => body = true,
+                Some(".ENDPROC" | ".ENDTEST" | ".SECTION" | ">") => body = false,
+                Some(label) if line.kind != Kind::Instruction && label.ends_with(':') => {
+                    body = true
+                }
+                _ => (),
+            }
+            inside
+        })
+        .collect()
+}
+
+fn parse_line(text: &str) -> Line {
+    // Comments start at a `//` word, like the assembler reads them
+    let mut words = vec![];
+    let mut comment = None;
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..end];
+        if word == "//" {
+            comment = Some(rest.trim_end().to_owned());
+            break;
+        }
+        // `' '` is a single word for the space character
+        if word == "'" && rest[end..].starts_with(" '") {
+            words.push("' '".to_owned());
+            rest = rest[end + 2..].trim_start();
+            continue;
+        }
+        words.push(word.to_owned());
+        rest = rest[end..].trim_start();
+    }
+
+    let indented = text.starts_with(char::is_whitespace);
+    let first = words.first().map(|w| w.to_uppercase()).unwrap_or_default();
+    let kind = if words.is_empty() && comment.is_none() {
+        Kind::Blank
+    } else if words.is_empty() {
+        Kind::Comment { indented }
+    } else if is_mnemonic(&first) {
+        Kind::Instruction
+    } else {
+        Kind::Other {
+            indented: first == ".EXPECT",
+        }
+    };
+    match kind {
+        Kind::Instruction => {
+            words = words.iter().map(|w| format_operand(w)).collect();
+        }
+        // Test names and expectations are free text
+        Kind::Other { .. } if first == ".TEST" || first == ".EXPECT" => {
+            words[0] = words[0].to_lowercase();
+        }
+        Kind::Other { .. } if first.starts_with('.') => {
+            words[0] = words[0].to_lowercase();
+            words[1..].iter_mut().for_each(|w| *w = format_hex(w));
+        }
+        Kind::Other { .. } if first == "$" || first == ">" => {
+            words[1..].iter_mut().for_each(|w| *w = format_hex(w));
+        }
+        _ => (),
+    }
+    Line {
+        kind,
+        words,
+        comment,
+    }
+}
+
+fn format_operand(word: &str) -> String {
+    // Operands of pseudo-instructions can end with a comma
+    if let Some(operand) = word.strip_suffix(',').filter(|o| !o.is_empty()) {
+        return format!("{},", format_operand(operand));
+    }
+    let upper = word.to_uppercase();
+    if is_mnemonic(&upper) || is_register_or_flag(&upper) {
+        upper
+    } else {
+        format_hex(word)
+    }
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    MNEMONICS.contains(&word) || PSEUDO_MNEMONICS.contains(&word)
+}
+
+fn is_register_or_flag(word: &str) -> bool {
+    REGISTERS.contains(&word) || FLAGS.contains(&word) || word == "&HLI"
+}
+
+/// `0xAB` -> `0xab`, `&0X12CD` -> `&0x12cd`, anything else is left as it is
+fn format_hex(word: &str) -> String {
+    let (prefix, hex) = match word.strip_prefix('&') {
+        Some(hex) => ("&", hex),
+        None => ("", word),
+    };
+    match hex.get(..2) {
+        Some("0x" | "0X") if hex[2..].chars().all(|c| c.is_ascii_hexdigit()) => {
+            format!("{}0x{}", prefix, hex[2..].to_lowercase())
+        }
+        _ => word.to_owned(),
+    }
+}
+
+/// Ranges of consecutive lines matching `predicate`
+fn runs(lines: &[Line], predicate: impl Fn(&Line) -> bool) -> Vec<std::ops::Range<usize>> {
+    let mut runs = vec![];
+    let mut start = None;
+    for (n, line) in lines.iter().enumerate() {
+        match (predicate(line), start) {
+            (true, None) => start = Some(n),
+            (false, Some(first)) => {
+                runs.push(first..n);
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(first) = start {
+        runs.push(first..lines.len());
+    }
+    runs
+}
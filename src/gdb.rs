@@ -0,0 +1,407 @@
+//! GDB remote serial protocol server, so GDB compatible front-ends can debug programs
+//! running in the [`Emulator`] over a local TCP connection.
+//!
+//! Registers are described by the `target.xml` sent to the client, in this order:
+//! `pc`, `a`, `b`, `hi`, `li`, `ex`, `flags`, `sa`, `ss`, `sp`, `ira`. 16 bit registers are
+//! sent big endian, like the CPU stores them. `a` and `b` are the last values of the A and B
+//! stacks (0 if the stack is empty), writing them replaces the last value.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use anyhow::{anyhow, Error};
+
+use crate::emulator::{Emulator, Event, Fault, Flags, Register};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>nox</architecture>
+  <feature name="org.nox.core">
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="b" bitsize="8" type="uint8"/>
+    <reg name="hi" bitsize="8" type="uint8"/>
+    <reg name="li" bitsize="8" type="uint8"/>
+    <reg name="ex" bitsize="8" type="uint8"/>
+    <reg name="flags" bitsize="8" type="uint8"/>
+    <reg name="sa" bitsize="16" type="data_ptr"/>
+    <reg name="ss" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="ira" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>"#;
+
+/// Size in bytes of each register, in `g` packet order
+const REGISTER_SIZES: [usize; 11] = [2, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2];
+
+/// How many instructions run between checks for an interrupt (Ctrl-C) from the client
+const INTERRUPT_CHECK_INTERVAL: usize = 4096;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+pub struct GdbServer {
+    emulator: Emulator,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<u16>,
+    verbose: bool,
+}
+
+/// Why execution stopped, sent to the client as a stop reply
+enum Stop {
+    Signal(u8),
+    Breakpoint, // `swbreak` is advertised, so hits are reported with it
+    Watchpoint(u16),
+    Exited(u8),
+}
+
+impl GdbServer {
+    pub fn new(emulator: Emulator, verbose: bool) -> Self {
+        Self {
+            emulator,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            verbose,
+        }
+    }
+
+    /// Waits for a client on `127.0.0.1:port` and serves it until it detaches or kills the program
+    pub fn listen(&mut self, port: u16) -> Result<(), Error> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("> Waiting for GDB on {}...", listener.local_addr()?);
+        let (stream, address) = listener.accept()?;
+        println!("> GDB connected from {}", address);
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> Result<(), Error> {
+        stream.set_nodelay(true)?;
+        while let Some(packet) = read_packet(&mut stream)? {
+            if self.verbose {
+                println!("<- {}", packet);
+            }
+            let (reply, close) = match self.handle(&packet, &mut stream) {
+                Ok(Some(reply)) => (reply, false),
+                Ok(None) => ("OK".to_owned(), true),
+                Err(e) => {
+                    if self.verbose {
+                        println!("   {}", e);
+                    }
+                    ("E01".to_owned(), false)
+                }
+            };
+            if self.verbose {
+                println!("-> {}", reply);
+            }
+            write_packet(&mut stream, &reply)?;
+            if close {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the reply to `packet`, `None` if the session ends
+    fn handle(&mut self, packet: &str, stream: &mut TcpStream) -> Result<Option<String>, Error> {
+        let (command, args) = packet.split_at(1.min(packet.len()));
+        let reply = match command {
+            "?" => stop_reply(&Stop::Signal(SIGTRAP)),
+            "g" => self.read_registers(),
+            "G" => {
+                let bytes = decode_hex(args)?;
+                let mut offset = 0;
+                for (n, size) in REGISTER_SIZES.iter().enumerate() {
+                    let value = bytes
+                        .get(offset..offset + size)
+                        .ok_or_else(|| anyhow!("register data too short"))?;
+                    self.write_register(n, value)?;
+                    offset += size;
+                }
+                "OK".to_owned()
+            }
+            "p" => {
+                let n = usize::from_str_radix(args, 16)?;
+                encode_hex(&self.register(n)?)
+            }
+            "P" => {
+                let (n, value) = args
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("missing value"))?;
+                self.write_register(usize::from_str_radix(n, 16)?, &decode_hex(value)?)?;
+                "OK".to_owned()
+            }
+            "m" => {
+                let (address, len) = parse_range(args)?;
+                let bytes: Vec<u8> = (0..len)
+                    .map(|i| self.emulator.memory()[address.wrapping_add(i) as usize])
+                    .collect();
+                encode_hex(&bytes)
+            }
+            "M" => {
+                let (range, data) = args
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("missing data"))?;
+                let (address, _) = parse_range(range)?;
+                for (i, byte) in decode_hex(data)?.into_iter().enumerate() {
+                    self.emulator.memory_mut()[address.wrapping_add(i as u16) as usize] = byte;
+                }
+                "OK".to_owned()
+            }
+            "c" => {
+                self.jump_to(args)?;
+                stop_reply(&self.resume(stream, false)?)
+            }
+            "s" => {
+                self.jump_to(args)?;
+                stop_reply(&self.resume(stream, true)?)
+            }
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next().unwrap_or_default();
+                let address = u16::from_str_radix(parts.next().unwrap_or_default(), 16)?;
+                let points = match kind {
+                    "0" | "1" => &mut self.breakpoints, // software and hardware breakpoints
+                    "2" => &mut self.watchpoints,
+                    _ => return Ok(Some(String::new())),
+                };
+                if command == "Z" {
+                    points.push(address);
+                } else if let Some(i) = points.iter().position(|p| *p == address) {
+                    points.remove(i);
+                }
+                "OK".to_owned()
+            }
+            "H" | "T" => "OK".to_owned(),
+            "D" | "k" => return Ok(None),
+            "q" => self.query(args),
+            "v" if args == "Cont?" => "vCont;c;s".to_owned(),
+            "v" if args.starts_with("Cont;") => {
+                let step = args["Cont;".len()..].starts_with('s');
+                stop_reply(&self.resume(stream, step)?)
+            }
+            _ => String::new(), // not supported
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+;swbreak+".to_owned()
+        } else if let Some(annex) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, len) = parse_range(annex).unwrap_or((0, 0));
+            let data = TARGET_XML.as_bytes();
+            let start = (offset as usize).min(data.len());
+            let end = (start + len as usize).min(data.len());
+            let prefix = if end == data.len() { "l" } else { "m" };
+            format!("{}{}", prefix, String::from_utf8_lossy(&data[start..end]))
+        } else {
+            match args {
+                "Attached" => "1".to_owned(),
+                "C" => "QC1".to_owned(),
+                "fThreadInfo" => "m1".to_owned(),
+                "sThreadInfo" => "l".to_owned(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    /// `c addr` and `s addr` continue at `addr`
+    fn jump_to(&mut self, address: &str) -> Result<(), Error> {
+        if !address.is_empty() {
+            self.emulator.cpu.pc = u16::from_str_radix(address, 16)?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, stream: &mut TcpStream, single_step: bool) -> Result<Stop, Error> {
+        let mut count = 0;
+        loop {
+            match self.emulator.step() {
+                Ok(step) if step.event == Some(Event::Halt) => {
+                    return Ok(Stop::Exited(self.emulator.cpu.exit_code))
+                }
+                Ok(_) => (),
+                Err(Fault::InvalidOpcode { .. }) => return Ok(Stop::Signal(SIGILL)),
+                Err(Fault::Halted) => return Ok(Stop::Exited(self.emulator.cpu.exit_code)),
+                Err(_) => return Ok(Stop::Signal(SIGSEGV)),
+            }
+            if let Some(write) = self
+                .emulator
+                .last_writes()
+                .iter()
+                .find(|w| self.watchpoints.contains(&w.address))
+            {
+                return Ok(Stop::Watchpoint(write.address));
+            }
+            if self.breakpoints.contains(&self.emulator.cpu.pc) {
+                return Ok(Stop::Breakpoint);
+            }
+            if single_step {
+                return Ok(Stop::Signal(SIGTRAP));
+            }
+            count += 1;
+            if count % INTERRUPT_CHECK_INTERVAL == 0 && interrupted(stream)? {
+                return Ok(Stop::Signal(SIGINT));
+            }
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_SIZES.len())
+            .map(|n| encode_hex(&self.register(n).unwrap()))
+            .collect()
+    }
+
+    fn register(&self, n: usize) -> Result<Vec<u8>, Error> {
+        let cpu = &self.emulator.cpu;
+        let value = match n {
+            0 => cpu.pc.to_be_bytes().to_vec(),
+            1 => vec![cpu.peek(Register::A).unwrap_or_default()],
+            2 => vec![cpu.peek(Register::B).unwrap_or_default()],
+            3 => vec![cpu.hi],
+            4 => vec![cpu.li],
+            5 => vec![cpu.exit_code],
+            6 => vec![cpu.flags.to_byte()],
+            7 => cpu.stack_address.to_be_bytes().to_vec(),
+            8 => cpu.stack_size.to_be_bytes().to_vec(),
+            9 => cpu.stack_pointer.to_be_bytes().to_vec(),
+            10 => cpu.irq_address.to_be_bytes().to_vec(),
+            _ => return Err(anyhow!("invalid register {}", n)),
+        };
+        Ok(value)
+    }
+
+    fn write_register(&mut self, n: usize, value: &[u8]) -> Result<(), Error> {
+        let cpu = &mut self.emulator.cpu;
+        let byte = *value.first().ok_or_else(|| anyhow!("missing value"))?;
+        let word = || match value {
+            [high, low, ..] => Ok(u16::from_be_bytes([*high, *low])),
+            _ => Err(anyhow!("16 bit register needs 2 bytes")),
+        };
+        match n {
+            0 => cpu.pc = word()?,
+            1 | 2 => {
+                let stack = if n == 1 { &mut cpu.a } else { &mut cpu.b };
+                match stack.last_mut() {
+                    Some(last) => *last = byte,
+                    None => stack.push(byte),
+                }
+            }
+            3 => cpu.hi = byte,
+            4 => cpu.li = byte,
+            5 => cpu.exit_code = byte,
+            6 => cpu.flags = Flags::from_byte(byte),
+            7 => cpu.stack_address = word()?,
+            8 => cpu.stack_size = word()?,
+            9 => cpu.stack_pointer = word()?,
+            10 => cpu.irq_address = word()?,
+            _ => return Err(anyhow!("invalid register {}", n)),
+        }
+        Ok(())
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Signal(signal) => format!("S{:02x}", signal),
+        Stop::Breakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+        Stop::Watchpoint(address) => format!("T{:02x}watch:{:04x};", SIGTRAP, address),
+        Stop::Exited(code) => format!("W{:02x}", code),
+    }
+}
+
+/// Checks without blocking if the client sent the interrupt byte
+fn interrupted(stream: &mut TcpStream) -> Result<bool, Error> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0u8];
+    let result = stream.peek(&mut byte);
+    stream.set_nonblocking(false)?;
+    match result {
+        Ok(1) if byte[0] == 0x03 => {
+            stream.read_exact(&mut byte)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Reads `$packet#checksum`, acknowledges it and removes the `}` escapes. Returns `None` when
+/// the client disconnects
+fn read_packet(stream: &mut TcpStream) -> Result<Option<String>, Error> {
+    let mut byte = [0u8];
+    loop {
+        // Skip acks and interrupts outside of `continue`
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut data = vec![];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum)?;
+        let expected = u8::from_str_radix(std::str::from_utf8(&checksum)?, 16)?;
+        if data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == expected {
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&unescape(&data)).into_owned()));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+/// `}` escapes the next byte, which is XORed with 0x20 (`#`, `$`, `}` and `*` are sent this way)
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut bytes = data.iter().peekable();
+    let mut unescaped = vec![];
+    while let Some(&byte) = bytes.next() {
+        match (byte, bytes.next_if(|_| byte == b'}')) {
+            (_, Some(escaped)) => unescaped.push(escaped ^ 0x20),
+            (byte, None) => unescaped.push(byte),
+        }
+    }
+    unescaped
+}
+
+fn write_packet(stream: &mut TcpStream, data: &str) -> Result<(), Error> {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    write!(stream, "${}#{:02x}", data, checksum)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// `addr,len` in hex
+fn parse_range(args: &str) -> Result<(u16, u16), Error> {
+    let (address, len) = args
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected address,length"))?;
+    Ok((
+        u16::from_str_radix(address, 16)?,
+        u16::from_str_radix(len, 16)?,
+    ))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(Error::from))
+        .collect()
+}
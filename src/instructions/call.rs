@@ -6,7 +6,7 @@ pub(crate) fn parse_call(
     tokenised_line: &[Token],
     current_mem_address: &mut u16,
 ) -> Result<Vec<Token>, Error> {
-    let mut instruction = tokenised_line.get(0).unwrap().clone();
+    let mut instruction = tokenised_line.first().unwrap().clone();
     instruction.address = Some(*current_mem_address);
     if let Some(target_token) = tokenised_line.get(1) {
         if target_token._type == TokenType::Address
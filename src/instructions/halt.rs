@@ -6,7 +6,7 @@ pub(crate) fn parse_halt(
     tokenised_line: &[Token],
     current_mem_address: &mut u16,
 ) -> Result<Vec<Token>, Error> {
-    let mut instruction = tokenised_line.get(0).unwrap().clone();
+    let mut instruction = tokenised_line.first().unwrap().clone();
     instruction.address = Some(*current_mem_address);
     if tokenised_line.get(1).is_none() {
         instruction.opcode = Some(Opcode::HALT);
@@ -7,7 +7,7 @@ pub(crate) fn parse_pop(
     current_mem_address: &mut u16,
 ) -> Result<Vec<Token>, Error> {
     match (
-        tokenised_line.get(0),
+        tokenised_line.first(),
         tokenised_line.get(1),
         tokenised_line.get(2),
     ) {
@@ -102,7 +102,7 @@ pub(crate) fn parse_push(
                             ("LI", "B") => Opcode::PUSH_LI_B,
                             ("LI", "S") => Opcode::PUSH_LI_STACK,
                             ("EX", "A") => Opcode::PUSH_EXIT_CODE_A,
-                            ("EX", "B") => Opcode::PUSH_EXIT_CODE_A,
+                            ("EX", "B") => Opcode::PUSH_EXIT_CODE_B,
                             ("SA", "AB") => Opcode::PUSH_AB_STACK_ADDRESS,
                             ("SS", "AB") => Opcode::PUSH_AB_STACK_SIZE,
                             ("HLI", "AB") => Opcode::PUSH_HLI_AB,
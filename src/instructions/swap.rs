@@ -7,7 +7,7 @@ pub(crate) fn parse_swap(
     current_mem_address: &mut u16,
 ) -> Result<Vec<Token>, Error> {
     // ADD <REG> <REG> | ADD <REG> #<8BIT> | ADD <REG> <16BIT>
-    let mut instruction = tokenised_line.get(0).unwrap().clone();
+    let mut instruction = tokenised_line.first().unwrap().clone();
     instruction.address = Some(*current_mem_address);
     if let Some(source_reg_token) = tokenised_line.get(1) {
         if source_reg_token._type == TokenType::Register {
@@ -0,0 +1,265 @@
+use crate::{lint::Rule, opcodes::Opcode, source::Location};
+
+/// Single line of the program after parsing. All passes after parsing (linking, encoding,
+/// objects, listings) work on statements instead of re-interpreting tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub address: u16, // relative to the section fragment until the program is linked
+    pub span: Location,
+    pub(crate) fragment: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    Instruction(Instruction),
+    Label(Label),
+    Data(Data),
+    Directive(Directive),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>, // only the operands encoded after the opcode, registers are part of it
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+}
+
+/// Bytes following `$`, strings are already split into characters and zero-terminated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Data {
+    pub values: Vec<Operand>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    Section(String),
+    Origin(u16), // `>`
+    Reserve(u16),
+    Export(Vec<String>),
+    Test(String), // `.test "name"`, the test starts here and ends with a `HALT` at `.endtest`
+    Expect(Vec<Expectation>),
+    Lint { enable: bool, rules: Vec<Rule> }, // `.lint disable unused-label, unreachable`
+    StackSize(u16), // bytes available to the program, checked against its worst-case stack usage
+    Proc(Proc),
+    EndProc,
+}
+
+/// `.proc name uses A, HLI preserves B returns A` - a routine up to `.endproc`, its labels are
+/// local to it. Without `uses` any register can be written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proc {
+    pub name: String,
+    pub uses: Option<Vec<Register>>,
+    pub preserves: Vec<Register>,
+    pub returns: Vec<Register>, // values left in A and B for the caller, once per value
+}
+
+/// Registers named in `.proc` contracts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Register {
+    A,
+    B,
+    HI,
+    LI,
+}
+
+impl Register {
+    /// `HLI` and `AB` stand for both of their halves
+    pub fn from_name(name: &str) -> Option<&'static [Register]> {
+        match name.to_uppercase().as_str() {
+            "A" => Some(&[Register::A]),
+            "B" => Some(&[Register::B]),
+            "HI" => Some(&[Register::HI]),
+            "LI" => Some(&[Register::LI]),
+            "AB" => Some(&[Register::A, Register::B]),
+            "HLI" => Some(&[Register::HI, Register::LI]),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// `.expect A == 0x00, &result != 0x01, HLI == *buffer` - checked when a test reaches its end
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expectation {
+    pub target: ExpectTarget,
+    pub comparison: Comparison,
+    pub value: Operand, // `Immediate16` or `LabelAddress`
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpectTarget {
+    Register(String), // A and B are their last value, AB and HLI are 16 bit
+    Flag(String),     // 1 if set, 0 otherwise
+    Memory(Operand),  // byte at an `Address` or `Label`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Greater => ">",
+        }
+    }
+
+    /// Splits `<= 120` into the comparison and the rest
+    pub fn split(text: &str) -> Option<(Comparison, &str)> {
+        [
+            Comparison::LessOrEqual,
+            Comparison::GreaterOrEqual,
+            Comparison::Equal,
+            Comparison::NotEqual,
+            Comparison::Less,
+            Comparison::Greater,
+        ]
+        .into_iter()
+        .find_map(|comparison| {
+            text.strip_prefix(comparison.symbol())
+                .map(|rest| (comparison, rest.trim()))
+        })
+    }
+
+    pub fn holds<T: PartialOrd>(&self, value: T, expected: T) -> bool {
+        match self {
+            Comparison::Less => value < expected,
+            Comparison::LessOrEqual => value <= expected,
+            Comparison::Equal => value == expected,
+            Comparison::NotEqual => value != expected,
+            Comparison::GreaterOrEqual => value >= expected,
+            Comparison::Greater => value > expected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Immediate8(u8),
+    Immediate16(u16),
+    Address(u16),         // `&0x1234`
+    Label(String),        // `label`, the address of the label is used as an absolute address
+    LabelAddress(String), // `*label`, the address of the label is used as an immediate value
+}
+
+impl Operand {
+    pub fn size(&self) -> u16 {
+        match self {
+            Operand::Immediate8(_) => 1,
+            _ => 2,
+        }
+    }
+
+    /// Name of the label this operand refers to, if any
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Operand::Label(name) | Operand::LabelAddress(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Appends the encoded operand to `bytes`. 16 bit values are big endian,
+    /// labels that cannot be resolved are encoded as 0x0000
+    pub fn encode(&self, bytes: &mut Vec<u8>, resolve: &impl Fn(&str) -> Option<u16>) {
+        match self {
+            Operand::Immediate8(value) => bytes.push(*value),
+            Operand::Immediate16(value) | Operand::Address(value) => {
+                bytes.extend_from_slice(&value.to_be_bytes())
+            }
+            Operand::Label(name) | Operand::LabelAddress(name) => {
+                bytes.extend_from_slice(&resolve(name).unwrap_or_default().to_be_bytes())
+            }
+        }
+    }
+}
+
+impl Statement {
+    /// Number of bytes the statement takes in memory
+    pub fn size(&self) -> u16 {
+        match &self.kind {
+            StatementKind::Instruction(instruction) => {
+                1 + instruction.operands.iter().map(Operand::size).sum::<u16>()
+            }
+            StatementKind::Data(data) => data.values.iter().map(Operand::size).sum(),
+            StatementKind::Directive(Directive::Reserve(size)) => *size,
+            StatementKind::Label(_) | StatementKind::Directive(_) => 0,
+        }
+    }
+
+    pub fn instruction(&self) -> Option<&Instruction> {
+        match &self.kind {
+            StatementKind::Instruction(instruction) => Some(instruction),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        match &self.kind {
+            StatementKind::Label(label) => Some(&label.name),
+            StatementKind::Directive(Directive::Proc(proc)) => Some(&proc.name),
+            _ => None,
+        }
+    }
+
+    /// Operands along with the address they are encoded at
+    pub fn operands(&self) -> impl Iterator<Item = (u16, &Operand)> {
+        let (mut address, operands) = match &self.kind {
+            StatementKind::Instruction(instruction) => (
+                self.address.wrapping_add(1),
+                instruction.operands.as_slice(),
+            ),
+            StatementKind::Data(data) => (self.address, data.values.as_slice()),
+            _ => (self.address, &[][..]),
+        };
+        operands.iter().map(move |operand| {
+            let operand_address = address;
+            address = address.wrapping_add(operand.size());
+            (operand_address, operand)
+        })
+    }
+
+    /// Encodes the statement, `resolve` returns the address of a label
+    pub fn encode(&self, resolve: &impl Fn(&str) -> Option<u16>) -> Vec<u8> {
+        let mut bytes = vec![];
+        match &self.kind {
+            StatementKind::Instruction(instruction) => {
+                bytes.push(instruction.opcode as u8);
+                for operand in &instruction.operands {
+                    operand.encode(&mut bytes, resolve);
+                }
+            }
+            StatementKind::Data(data) => {
+                for value in &data.values {
+                    value.encode(&mut bytes, resolve);
+                }
+            }
+            StatementKind::Directive(Directive::Reserve(size)) => {
+                bytes.resize(*size as usize, 0);
+            }
+            StatementKind::Label(_) | StatementKind::Directive(_) => (),
+        }
+        bytes
+    }
+}
@@ -1,8 +1,12 @@
-use std::io::Read;
-use std::{fs::OpenOptions, path::Path};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
 
+use anyhow::anyhow;
 use anyhow::Error;
-use anyhow::{anyhow, Context};
+pub use assembly::{Assembly, Diagnostic, Severity, SourceMap, SourceMapEntry, SymbolInfo};
+use cfg::ControlFlowGraph;
+use emulator::cpu::Cpu;
 use instructions::add::parse_add;
 use instructions::and::parse_and;
 use instructions::call::parse_call;
@@ -26,10 +30,66 @@ use instructions::sub::parse_sub;
 use instructions::swap::parse_swap;
 use instructions::xor::parse_xor;
 use instructions::zero::parse_zero;
-use opcodes::Opcode;
+use ir::{
+    Comparison, Data, Directive, ExpectTarget, Expectation, Instruction, Label, Operand, Proc,
+    Register, Statement, StatementKind,
+};
+use linker::Fragment;
+pub use linker::LinkerScript;
+use lint::{LintConfig, Rule};
+use object::{Object, ObjectSection, Relocation, Symbol};
+pub use opcodes::Opcode;
+use opcodes::{MNEMONICS, PSEUDO_MNEMONICS};
+use pseudo::Pseudo;
+use register_stack::RegisterStackAnalysis;
+pub use source::{DiskFileSystem, FileSystem, Location, MemoryFileSystem};
+use stack::StackAnalysis;
+use structured::Structured;
+pub use testing::UnitTest;
 
+pub mod archive;
+mod assembly;
+pub mod cfg;
+mod contract;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
+pub mod emulator;
+pub mod format;
+pub mod gdb;
 mod instructions;
-mod opcodes;
+pub mod ir;
+pub mod linker;
+pub mod lint;
+pub mod lsp;
+pub mod machine;
+pub mod object;
+pub mod opcodes;
+pub mod profile;
+mod pseudo;
+pub mod register_stack;
+mod source;
+pub mod stack;
+mod structured;
+pub mod testing;
+pub mod trace;
+
+/// The Rust examples of the README, run as doctests
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+pub struct ReadmeDoctests;
+
+/// File name used in diagnostics for sources assembled with [`Assembler::from_source`]
+pub const SOURCE_NAME: &str = "<source>";
+
+/// Assembles `source` into a memory image with the default memory layout
+pub fn assemble_str(source: &str) -> Result<Assembly, Error> {
+    Assembler::from_source(source).build(false)
+}
+
+fn error_at(location: &Location, message: impl Display) -> Error {
+    Diagnostic::error(Some(location.clone()), message.to_string()).into()
+}
 
 #[derive(Debug, PartialEq, Clone)]
 enum TokenType {
@@ -45,6 +105,7 @@ enum TokenType {
     AddressDelimiter, // `>`
     CommentStart,    // `//`
     DataStream,      // `$`
+    Directive,       // any token starting with `.`, eg. `.section`
 }
 
 #[derive(Debug, Clone)]
@@ -107,7 +168,7 @@ impl TryFrom<String> for Token {
                 raw: value,
                 ..Default::default()
             }),
-            indirection if indirection == "&HLI" => Ok(Token {
+            "&HLI" => Ok(Token {
                 _type: TokenType::Indirection,
                 raw: value,
                 ..Default::default()
@@ -122,6 +183,11 @@ impl TryFrom<String> for Token {
                     ..Default::default()
                 })
             }
+            directive if directive.starts_with('.') => Ok(Token {
+                _type: TokenType::Directive,
+                raw: value,
+                ..Default::default()
+            }),
             "$" => Ok(Token {
                 _type: TokenType::DataStream,
                 raw: value,
@@ -142,13 +208,13 @@ impl TryFrom<String> for Token {
                 raw: value,
                 ..Default::default()
             }),
-            "NOOP" | "PUSH" | "POP" | "PEEK" | "STO" | "ADD" | "SUB" | "SHL" | "SHR" | "AND"
-            | "OR" | "XOR" | "NOT" | "CMP" | "INC" | "DEC" | "ZERO" | "SWP" | "JZE" | "JOF"
-            | "JER" | "JOK" | "JMP" | "CALL" | "RET" | "SET" | "CLR" | "HALT" => Ok(Token {
-                _type: TokenType::Instruction,
-                raw: value,
-                ..Default::default()
-            }),
+            mnemonic if MNEMONICS.contains(&mnemonic) || PSEUDO_MNEMONICS.contains(&mnemonic) => {
+                Ok(Token {
+                    _type: TokenType::Instruction,
+                    raw: value,
+                    ..Default::default()
+                })
+            }
             label if label.ends_with(':') => Ok(Token {
                 _type: TokenType::Label,
                 raw: value,
@@ -162,7 +228,7 @@ impl TryFrom<String> for Token {
                     ..Default::default()
                 })
             }
-            space if space == "''" => Ok(Token {
+            "''" => Ok(Token {
                 _type: TokenType::ImmediateValue8,
                 raw: value,
                 value: Some(b' ' as usize),
@@ -177,218 +243,877 @@ impl TryFrom<String> for Token {
     }
 }
 
-pub struct Assembler<'a> {
-    input_path: &'a Path,
-    input: String,
+pub struct Assembler {
+    input_path: PathBuf,
+    file_system: Box<dyn FileSystem>,
+    lines: Vec<(Location, String)>, // source location and text of each tokenised line
     tokens: Vec<Vec<Token>>,
-    parsed_tokens: Vec<Token>,
+    statements: Vec<Statement>,
+    linker_script: LinkerScript,
+    fragments: Vec<Fragment>,
+    labels: HashMap<String, u16>, // label addresses, known after linking
+    diagnostics: Vec<Diagnostic>,
+    tests: bool, // `.test` blocks are assembled, otherwise they are skipped
 }
 
-impl<'a> Assembler<'a> {
-    pub fn new(input_path: &'a Path) -> Self {
+impl Assembler {
+    pub fn new(input_path: &Path) -> Self {
+        Self::with_file_system(input_path, DiskFileSystem)
+    }
+
+    /// Reads the input and all included files from `file_system` instead of the disk
+    pub fn with_file_system(input_path: &Path, file_system: impl FileSystem + 'static) -> Self {
         Self {
-            input_path,
-            input: String::new(),
+            input_path: input_path.to_owned(),
+            file_system: Box::new(file_system),
+            lines: vec![],
             tokens: vec![],
-            parsed_tokens: vec![],
+            statements: vec![],
+            linker_script: LinkerScript::default(),
+            fragments: vec![],
+            labels: HashMap::new(),
+            diagnostics: vec![],
+            tests: false,
+        }
+    }
+
+    /// Assembles `source` directly, without any file. It cannot include other files
+    pub fn from_source(source: &str) -> Self {
+        Self::with_file_system(
+            Path::new(SOURCE_NAME),
+            MemoryFileSystem::new().with_file(SOURCE_NAME, source),
+        )
+    }
+
+    pub fn with_linker_script(mut self, linker_script: LinkerScript) -> Self {
+        self.linker_script = linker_script;
+        self
+    }
+
+    /// Assembles `.test` blocks too, they are left out of the program otherwise
+    pub fn with_tests(mut self) -> Self {
+        self.tests = true;
+        self
+    }
+
+    /// Source lines with `.include`s expanded, along with where they come from
+    pub fn source_lines(&self) -> &[(Location, String)] {
+        &self.lines
+    }
+
+    /// Parsed program. After [`Assembler::build`] all addresses are final
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /// Tests found in `.test` blocks, after [`Assembler::build`] with [`Assembler::with_tests`]
+    pub fn tests(&self) -> Vec<UnitTest> {
+        let mut tests: Vec<UnitTest> = vec![];
+        for statement in &self.statements {
+            match &statement.kind {
+                StatementKind::Directive(Directive::Test(name)) => tests.push(UnitTest {
+                    name: name.clone(),
+                    address: statement.address,
+                    location: statement.span.clone(),
+                    expectations: vec![],
+                }),
+                StatementKind::Directive(Directive::Expect(expectations)) => {
+                    // `.expect` is only accepted inside of a test
+                    if let Some(test) = tests.last_mut() {
+                        test.expectations.extend(expectations.iter().cloned());
+                    }
+                }
+                _ => (),
+            }
+        }
+        tests
+    }
+
+    /// Basic blocks grouped into routines and the calls between them, after [`Assembler::build`]
+    pub fn control_flow_graph(&self) -> ControlFlowGraph {
+        ControlFlowGraph::new(&self.statements, |name| self.labels.get(name).copied())
+    }
+
+    /// Worst-case stack usage of the routines in `graph`, after [`Assembler::build`]
+    pub fn stack_analysis<'a>(&self, graph: &'a ControlFlowGraph) -> StackAnalysis<'a> {
+        StackAnalysis::new(graph)
+    }
+
+    /// Warnings of the lint rules enabled by `config` and `.lint` directives, after [`Assembler::build`]
+    pub fn lint(&self, config: &LintConfig) -> Vec<Diagnostic> {
+        lint::lint(
+            &self.statements,
+            |name| self.labels.get(name).copied(),
+            &self.linker_script,
+            config,
+        )
+    }
+
+    /// Source lines with their addresses and bytes
+    pub fn listing(&self) -> String {
+        let resolve = |name: &str| self.labels.get(name).copied();
+        let mut listing = String::new();
+        for (location, text) in &self.lines {
+            let statements: Vec<&Statement> = self
+                .statements
+                .iter()
+                .filter(|s| &s.span == location)
+                .collect();
+            let mut address = String::new();
+            let mut bytes = vec![];
+            for statement in &statements {
+                // Structured directives turn into several statements, the line starts at the first
+                if address.is_empty() && (statement.size() > 0 || statement.label().is_some()) {
+                    address = format!("0x{:04x}", statement.address);
+                }
+                if matches!(
+                    statement.kind,
+                    StatementKind::Instruction(_) | StatementKind::Data(_)
+                ) {
+                    bytes.extend(statement.encode(&resolve));
+                }
+            }
+            let mut bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            if bytes.len() > 8 {
+                bytes.truncate(7);
+                bytes.push("..".to_owned());
+            }
+            let line = format!("{:6}  {:<24} {}", address, bytes.join(" "), text);
+            listing.push_str(line.trim_end());
+            listing.push('\n');
         }
+        listing
     }
 
     pub fn assemble(&mut self, verbose: bool) -> Result<Vec<u8>, Error> {
+        Ok(self.build(verbose)?.bytes)
+    }
+
+    /// Assembles the input into a memory image along with the symbol table, source map and warnings
+    pub fn build(&mut self, verbose: bool) -> Result<Assembly, Error> {
+        self.load_input()?;
+        self.parse_statements(verbose)?;
+        self.link_statements(verbose)?;
+        let graph = self.control_flow_graph();
+        self.check_stack_usage(&graph)?;
+        self.check_proc_contracts(&graph)?;
+        Ok(Assembly {
+            bytes: self.generate_bytes(verbose)?,
+            symbols: self.symbols(),
+            source_map: self.source_map(),
+            diagnostics: std::mem::take(&mut self.diagnostics),
+        })
+    }
+
+    /// Assembles the input into a relocatable object, to be combined with others by [`linker::link`]
+    pub fn assemble_object(&mut self, verbose: bool) -> Result<Object, Error> {
         self.load_input()?;
-        self.parse_tokens(verbose)?;
-        self.generate_bytes(verbose)
+        self.parse_statements(verbose)?;
+        self.generate_object(verbose)
     }
 
     fn load_input(&mut self) -> Result<(), Error> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .open(self.input_path)
-            .map_err(|e| anyhow!(e))?;
-        file.read_to_string(&mut self.input)
-            .map_err(|e| anyhow!(e))?;
+        let lines = source::load_lines(self.file_system.as_ref(), &self.input_path)?;
+        // First pass - convert text to Token structs
+        self.lines = vec![];
+        self.tokens = vec![];
+        self.diagnostics = vec![];
+        for (location, line) in lines {
+            let mut comment = false;
+            let mut tokenised_line = vec![];
+            // Test names and expectations are free text, parsed by their directives
+            let free_text = line
+                .split_whitespace()
+                .next()
+                .is_some_and(|word| [".TEST", ".EXPECT"].contains(&word.to_uppercase().as_str()));
+            // Operands of pseudo-instructions can be separated by commas
+            let pseudo = line
+                .split_whitespace()
+                .next()
+                .is_some_and(|word| PSEUDO_MNEMONICS.contains(&word.to_uppercase().as_str()));
+            for (word_n, word) in line.replace("' '", "''").split_whitespace().enumerate() {
+                let word = match word.strip_suffix(',') {
+                    Some(operand) if pseudo && !comment && !operand.is_empty() => operand,
+                    _ => word,
+                };
+                if !(comment || free_text && word_n > 0) {
+                    let token = Token::try_from(word.to_string()).map_err(|e| {
+                        error_at(
+                            &location,
+                            format!("{} in token {}: {}", e, word_n + 1, word),
+                        )
+                    })?;
+                    comment = token._type == TokenType::CommentStart;
+                    tokenised_line.push(token);
+                } else {
+                    tokenised_line.push(Token {
+                        _type: TokenType::Text,
+                        raw: word.to_owned(),
+                        ..Default::default()
+                    });
+                }
+            }
+            self.lines.push((location, line));
+            self.tokens.push(tokenised_line);
+        }
         Ok(())
     }
 
-    fn parse_tokens(&mut self, verbose: bool) -> Result<(), Error> {
-        // First pass - convert text to Token structs
-        self.tokens = self
-            .input
-            .split('\n')
-            .enumerate()
-            .map(|(line_n, line)| {
-                let mut comment = false;
-                line.replace("' '", "''")
-                    .split_whitespace()
-                    .enumerate()
-                    .map(|(word_n, word)| {
-                        if !comment {
-                            let token = Token::try_from(word.to_string())
-                                .map_err(|e| {
-                                    anyhow!(
-                                        "Error: {} in line {} token {}: {}",
-                                        e,
-                                        line_n + 1,
-                                        word_n + 1,
-                                        word
-                                    )
-                                })
-                                .unwrap();
-                            comment = token._type == TokenType::CommentStart;
-                            if verbose {
-                                println!("Parsed token: {:?}", token);
-                            }
-                            token
-                        } else {
-                            Token {
-                                _type: TokenType::Text,
-                                raw: word.to_owned(),
-                                opcode: None,
-                                value: None,
-                                address: None,
-                            }
-                        }
-                    })
-                    .collect()
-            })
-            .collect();
+    fn parse_statements(&mut self, verbose: bool) -> Result<(), Error> {
+        if verbose {
+            for token in self.tokens.iter().flatten() {
+                println!("Parsed token: {:?}", token);
+            }
+        }
 
-        // Second pass - assign opcodes and remove unnecessary tokens, flatten the structure
-        let mut current_mem_address: u16 = 0x0000;
-        self.parsed_tokens = self
-            .tokens
-            .iter()
-            .enumerate()
-            .filter_map(|(line_n, line)| {
-                // First token on each line can only be Instruction, Label, Comment, DataStream or AddressDelimiter
-                if let Some(first_token) = line.get(0) {
-                    match first_token._type {
-                        TokenType::Instruction => {
-                            let parsed = Some(Self::parse_instruction(line, &mut current_mem_address).context(anyhow!("error in line {}", line_n + 1)));
-                            current_mem_address += 1;
-                            parsed
+        // Second pass - turn each line into a statement.
+        // Addresses are relative to the fragment of the section they are in until linking
+        self.fragments = vec![];
+        let mut current_fragment = Self::switch_section(&mut self.fragments, "code");
+        self.statements = vec![];
+        // Tests are kept in their own fragment of the `code` section, after the program.
+        // While in a test block, this is the fragment to go back to at `.endtest`
+        let mut test_fragment = None;
+        let mut test_block: Option<(usize, &Location)> = None;
+        let mut proc_block: Option<&Location> = None;
+        let mut structured = Structured::default();
+        let mut pseudo = Pseudo::default();
+        for (line_n, line) in self.tokens.iter().enumerate() {
+            // First token on each line can only be Instruction, Label, Comment, DataStream, AddressDelimiter or Directive
+            let Some(first_token) = line.first() else {
+                continue;
+            };
+            let location = &self.lines[line_n].0;
+            let directive =
+                (first_token._type == TokenType::Directive).then(|| first_token.formatted_raw());
+            if !self.tests && test_block.is_some() && directive.as_deref() != Some(".ENDTEST") {
+                continue;
+            }
+            let mut end_of_test = false;
+            let mut lowered = vec![]; // statements after the first one of a line that expands
+            let address = self.fragments[current_fragment].size;
+            let kind = match first_token._type {
+                TokenType::Instruction
+                    if PSEUDO_MNEMONICS.contains(&first_token.formatted_raw().as_str()) =>
+                {
+                    lowered = pseudo.expand(line).map_err(|e| error_at(location, e))?;
+                    lowered.remove(0)
+                }
+                TokenType::Instruction => {
+                    let mut current_mem_address = address;
+                    let parsed = Self::parse_instruction(line, &mut current_mem_address)
+                        .map_err(|e| error_at(location, e))?;
+                    let instruction =
+                        Self::to_instruction(parsed).map_err(|e| error_at(location, e))?;
+                    // The parsers leave the address at the last byte of the instruction
+                    let size = 1 + instruction.operands.iter().map(Operand::size).sum::<u16>();
+                    if current_mem_address.wrapping_sub(address) + 1 != size {
+                        return Err(error_at(location, "invalid operand size"));
+                    }
+                    StatementKind::Instruction(instruction)
+                }
+                TokenType::Label => StatementKind::Label(Label {
+                    name: first_token.raw.trim_end_matches(':').to_owned(),
+                }),
+                TokenType::AddressDelimiter => {
+                    // This opens a new fragment of the current section at a fixed address
+                    let origin = line.get(1).and_then(|t| t.value).ok_or_else(|| {
+                        error_at(
+                            location,
+                            "syntax error - cannot read address after address delimiter",
+                        )
+                    })? as u16;
+                    let section = self.fragments[current_fragment].section.clone();
+                    self.fragments.push(Fragment {
+                        section,
+                        origin: Some(origin),
+                        size: 0,
+                    });
+                    current_fragment = self.fragments.len() - 1;
+                    StatementKind::Directive(Directive::Origin(origin))
+                }
+                TokenType::Directive => match first_token.formatted_raw().as_str() {
+                    ".SECTION" => {
+                        let name = line
+                            .get(1)
+                            .filter(|t| t._type == TokenType::Text)
+                            .ok_or_else(|| {
+                                error_at(
+                                    location,
+                                    "syntax error - .section requires a section name",
+                                )
+                            })?
+                            .raw
+                            .to_lowercase();
+                        current_fragment = Self::switch_section(&mut self.fragments, &name);
+                        StatementKind::Directive(Directive::Section(name))
+                    }
+                    ".EXPORT" => {
+                        // Only matters for objects, in a single file all labels are visible
+                        StatementKind::Directive(Directive::Export(
+                            line.iter()
+                                .skip(1)
+                                .take_while(|t| t._type == TokenType::Text)
+                                .map(|t| t.raw.clone())
+                                .collect(),
+                        ))
+                    }
+                    ".RESERVE" => {
+                        let size = line.get(1).and_then(|t| t.value).ok_or_else(|| {
+                            error_at(location, "syntax error - .reserve requires a byte count")
+                        })?;
+                        StatementKind::Directive(Directive::Reserve(size as u16))
+                    }
+                    ".STACK_SIZE" => {
+                        let size = line.get(1).and_then(|t| t.value).ok_or_else(|| {
+                            error_at(location, "syntax error - .stack_size requires a byte count")
+                        })?;
+                        StatementKind::Directive(Directive::StackSize(size as u16))
+                    }
+                    ".PROC" => {
+                        if proc_block.is_some() {
+                            return Err(error_at(location, "procs cannot be nested"));
                         }
-                        TokenType::Label => {
-                            let mut label = first_token.clone();
-                            label.address = Some(current_mem_address);
-                            Some(Ok(vec![label]))
+                        proc_block = Some(location);
+                        StatementKind::Directive(Directive::Proc(
+                            Self::parse_proc(line).map_err(|e| error_at(location, e))?,
+                        ))
+                    }
+                    ".ENDPROC" => {
+                        if proc_block.take().is_none() {
+                            return Err(error_at(location, ".endproc without .proc"));
                         }
-                        TokenType::AddressDelimiter => {
-                            // This changes `current_mem_address`
-                            if let Some(address_token) = line.get(1) {
-                                current_mem_address = address_token.value.unwrap() as u16;
-                                None
-                            } else {
-                                Some(Err(anyhow!(
-                                    "Syntax error in line {} - cannot read address after address delimiter",
-                                    line_n + 1,
-                                )))
+                        StatementKind::Directive(Directive::EndProc)
+                    }
+                    directive @ (".IF" | ".ELSE" | ".ENDIF" | ".WHILE" | ".ENDWHILE" | ".LOOP"
+                    | ".ENDLOOP" | ".BREAK") => {
+                        lowered = structured
+                            .lower(directive, &Self::free_text(line), location)
+                            .map_err(|e| error_at(location, e))?;
+                        lowered.remove(0)
+                    }
+                    ".LINT" => {
+                        let enable = match line.get(1).map(|t| t.formatted_raw()).as_deref() {
+                            Some("ENABLE") => true,
+                            Some("DISABLE") => false,
+                            _ => {
+                                return Err(error_at(
+                                    location,
+                                    "syntax error - expected .lint enable or .lint disable",
+                                ))
                             }
+                        };
+                        let rules: Vec<&str> = line
+                            .iter()
+                            .skip(2)
+                            .take_while(|t| t._type != TokenType::CommentStart)
+                            .map(|t| t.raw.as_str())
+                            .collect();
+                        let rules = Rule::parse_list(&rules.join(" "))
+                            .map_err(|e| error_at(location, e))?;
+                        StatementKind::Directive(Directive::Lint { enable, rules })
+                    }
+                    ".TEST" => {
+                        if test_block.is_some() {
+                            return Err(error_at(location, "tests cannot be nested"));
+                        }
+                        let name = Self::free_text(line);
+                        let name = name.trim_matches('"');
+                        if name.is_empty() {
+                            return Err(error_at(
+                                location,
+                                "syntax error - .test requires a name, eg. .test \"name\"",
+                            ));
                         }
-                        TokenType::CommentStart => {
-                            // This line is a comment, ignore it
-                            None
+                        test_block = Some((current_fragment, location));
+                        if !self.tests {
+                            continue;
                         }
-                        TokenType::DataStream => {
-                            let mut parsed_data_stream = vec![];
-                            for token in line.iter().skip(1) {
-                                let mut token_clone = token.clone();
-                                match token._type {
-                                    
-                                    TokenType::ImmediateValue8 => {
-                                        token_clone.address = Some(current_mem_address);
-                                        token_clone._type = TokenType::ImmediateValue8;
-                                        token_clone.value = token.value;
-                                        parsed_data_stream.push(token_clone);
-                                    },
-                                    TokenType::ImmediateValue16 => {
-                                        token_clone.address = Some(current_mem_address);
-                                        token_clone._type = TokenType::ImmediateValue16;
-                                        token_clone.value = token.value;
-                                        parsed_data_stream.push(token_clone);
-                                    },
-                                    _ => {  // Any other token is just written as raw.bytes()
-                                        let add_space = token_clone.raw.starts_with('\"') && !token_clone.raw.ends_with('\"') ||
-                                            !token_clone.raw.starts_with('\"') && !token_clone.raw.ends_with('\"') || token_clone.raw == "\"";
-                                        token_clone.raw = token.raw.replace('\"', "");
-                                        for char in token_clone.raw.chars() {
-                                            let char_token = Token {
-                                                _type: TokenType::ImmediateValue8,
-                                                raw: char.to_string(),
-                                                value: Some(char as u8 as usize),
-                                                address: Some(current_mem_address),
-                                                opcode: None
-                                            };
-                                            current_mem_address += 1;
-                                            parsed_data_stream.push(char_token);
-                                        }
-                                        if add_space {
-                                            parsed_data_stream.push(Token {
-                                                _type: TokenType::ImmediateValue8,
-                                                raw: " ".to_string(),
-                                                value: Some(b' ' as usize),
-                                                address: Some(current_mem_address),
-                                                opcode: None
-                                            });
-                                        }
-                                    },
-                                }
-                                current_mem_address += 1;
+                        current_fragment = *test_fragment.get_or_insert_with(|| {
+                            self.fragments.push(Fragment {
+                                section: "code".to_owned(),
+                                origin: None,
+                                size: 0,
+                            });
+                            self.fragments.len() - 1
+                        });
+                        StatementKind::Directive(Directive::Test(name.to_owned()))
+                    }
+                    ".EXPECT" => {
+                        if test_block.is_none() {
+                            return Err(error_at(location, ".expect is only allowed in a test"));
+                        }
+                        StatementKind::Directive(Directive::Expect(
+                            Self::parse_expectations(line).map_err(|e| error_at(location, e))?,
+                        ))
+                    }
+                    ".ENDTEST" => {
+                        if test_block.is_none() {
+                            return Err(error_at(location, ".endtest without .test"));
+                        }
+                        if !self.tests {
+                            test_block = None;
+                            continue;
+                        }
+                        // The test ends by halting, then the expectations are checked
+                        end_of_test = true;
+                        StatementKind::Instruction(Instruction {
+                            opcode: Opcode::HALT,
+                            operands: vec![],
+                        })
+                    }
+                    directive => {
+                        return Err(error_at(
+                            location,
+                            format!("syntax error - unknown directive {}", directive),
+                        ))
+                    }
+                },
+                TokenType::CommentStart => {
+                    // This line is a comment, ignore it
+                    continue;
+                }
+                TokenType::DataStream => {
+                    let mut values = vec![];
+                    for token in line.iter().skip(1) {
+                        match token._type {
+                            TokenType::ImmediateValue8 | TokenType::ImmediateValue16 => {
+                                values.push(Self::to_operand(token)?)
                             }
-                            Some(Ok(parsed_data_stream))
-                    },
-                        _ => Some(Err(anyhow!(
-                            "Syntax error in line {} - line cannot start with {}",
-                            line_n + 1,
-                            first_token.raw
-                        ))),
+                            _ => {
+                                // Any other token is just written as raw.bytes()
+                                let add_space = token.raw.starts_with('\"')
+                                    && !token.raw.ends_with('\"')
+                                    || !token.raw.starts_with('\"') && !token.raw.ends_with('\"')
+                                    || token.raw == "\"";
+                                values.extend(
+                                    token
+                                        .raw
+                                        .replace('\"', "")
+                                        .chars()
+                                        .map(|char| Operand::Immediate8(char as u8)),
+                                );
+                                // Words are separated by spaces, the last one is zero-terminated
+                                values.push(Operand::Immediate8(if add_space { b' ' } else { 0 }));
+                            }
+                        }
                     }
-                } else {
-                    None
+                    StatementKind::Data(Data { values })
+                }
+                _ => {
+                    return Err(error_at(
+                        location,
+                        format!("syntax error - line cannot start with {}", first_token.raw),
+                    ))
                 }
-            }).flat_map(|line| {
+            };
+            for kind in std::iter::once(kind).chain(lowered) {
+                let statement = Statement {
+                    kind,
+                    address: self.fragments[current_fragment].size,
+                    span: location.clone(),
+                    fragment: current_fragment,
+                };
+                let section = &self.fragments[current_fragment].section;
+                if matches!(
+                    statement.kind,
+                    StatementKind::Instruction(_) | StatementKind::Data(_)
+                ) && self
+                    .linker_script
+                    .section(section)
+                    .is_some_and(|rule| rule.noload)
+                {
+                    return Err(error_at(
+                        location,
+                        format!("section `{}` is NOLOAD, use .reserve instead", section),
+                    ));
+                }
+                let size = &mut self.fragments[current_fragment].size;
+                *size = size
+                    .checked_add(statement.size())
+                    .ok_or_else(|| error_at(location, "out of memory"))?;
                 if verbose {
-                    println!("Parsed line: {:?}", line); 
+                    println!("Parsed statement: {:?}", statement);
                 }
-                line.unwrap()
-            })
-            .collect();
+                self.statements.push(statement);
+            }
+            if end_of_test {
+                if let Some((fragment, _)) = test_block.take() {
+                    current_fragment = fragment;
+                }
+            }
+        }
+        if let Some((_, location)) = test_block {
+            return Err(error_at(location, ".test is not closed with .endtest"));
+        }
+        if let Some(location) = proc_block {
+            return Err(error_at(location, ".proc is not closed with .endproc"));
+        }
+        structured
+            .finish()
+            .map_err(|(location, e)| error_at(&location, e))?;
+        self.scope_proc_labels();
+        Ok(())
+    }
 
-        // Third pass: assign values for `Text` or `ImmediateValue16` tokens that are labels
-        let pt_clone = self.parsed_tokens.clone();
-        for token in &mut self.parsed_tokens {
-            match token._type {
-                TokenType::Text => {
-                    let mut labels = pt_clone.iter().filter(|t| t._type == TokenType::Label);
-                    let mut token_val = token.raw.clone();
-                    token_val.push(':');
-                    if let Some(label) = labels.find(|t| t.raw == token_val) {
-                        token._type = TokenType::Label;
-                        token.value = Some(label.address.unwrap() as usize);
-                    }
+    /// Renames the labels defined between `.proc name` and `.endproc` to `name.label`, along with
+    /// the references to them in the same block. Other code can still use the full name
+    fn scope_proc_labels(&mut self) {
+        let mut start = None;
+        for index in 0..self.statements.len() {
+            match &self.statements[index].kind {
+                StatementKind::Directive(Directive::Proc(proc)) => {
+                    start = Some((index, proc.name.clone()));
                 }
-                TokenType::ImmediateValue16 => {
-                    let mut labels = pt_clone.iter().filter(|t| t._type == TokenType::Label);
-                    let mut token_val = token.raw.clone();
-                    token_val.push(':');
-                    if let Some(label_name) = token_val.strip_prefix('*') {
-                        if let Some(label) = labels.find(|t| t.raw == label_name) {
-                            token._type = TokenType::Label;
-                            token.value = Some(label.address.unwrap() as usize);
-                        }
+                StatementKind::Directive(Directive::EndProc) => {
+                    if let Some((first, name)) = start.take() {
+                        Self::scope_labels(&mut self.statements[first + 1..index], &name);
                     }
                 }
                 _ => (),
             }
         }
+    }
+
+    fn scope_labels(statements: &mut [Statement], proc: &str) {
+        let local: HashSet<String> = statements
+            .iter()
+            .filter_map(|s| s.label().map(str::to_owned))
+            .collect();
+        let scope = |name: &mut String| {
+            if local.contains(name) {
+                *name = format!("{}.{}", proc, name);
+            }
+        };
+        for statement in statements {
+            let operands = match &mut statement.kind {
+                StatementKind::Label(label) => {
+                    scope(&mut label.name);
+                    continue;
+                }
+                StatementKind::Instruction(instruction) => &mut instruction.operands,
+                StatementKind::Data(data) => &mut data.values,
+                StatementKind::Directive(_) => continue,
+            };
+            for operand in operands {
+                if let Operand::Label(name) | Operand::LabelAddress(name) = operand {
+                    scope(name);
+                }
+            }
+        }
+    }
+
+    fn link_statements(&mut self, verbose: bool) -> Result<(), Error> {
+        // Place the fragments in memory and turn relative addresses into absolute ones
+        let bases = self.linker_script.place(&self.fragments)?;
+        if verbose {
+            for (fragment, base) in self.fragments.iter().zip(&bases) {
+                println!(
+                    "Placed section `{}`: 0x{:04x} ({} bytes)",
+                    fragment.section, base, fragment.size
+                );
+            }
+        }
+        for statement in &mut self.statements {
+            statement.address = bases[statement.fragment].wrapping_add(statement.address);
+        }
+
+        // Third pass: collect label addresses and check all used labels are defined
+        self.labels = HashMap::new();
+        for statement in &self.statements {
+            if let Some(name) = statement.label() {
+                if self.labels.contains_key(name) {
+                    self.diagnostics.push(Diagnostic::warning(
+                        Some(statement.span.clone()),
+                        format!(
+                            "label `{}` is defined more than once, the first one is used",
+                            name
+                        ),
+                    ));
+                } else {
+                    self.labels.insert(name.to_owned(), statement.address);
+                }
+            }
+        }
+        for statement in &self.statements {
+            for (_, operand) in statement.operands() {
+                if let Some(name) = operand.label().filter(|l| !self.labels.contains_key(*l)) {
+                    self.diagnostics.push(Diagnostic::warning(
+                        Some(statement.span.clone()),
+                        format!("label `{}` is not defined, 0x0000 is used instead", name),
+                    ));
+                }
+            }
+        }
         Ok(())
     }
 
+    fn symbols(&self) -> Vec<SymbolInfo> {
+        self.statements
+            .iter()
+            .filter_map(|s| {
+                s.label().map(|name| SymbolInfo {
+                    name: name.to_owned(),
+                    address: s.address,
+                    location: s.span.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn source_map(&self) -> SourceMap {
+        SourceMap {
+            entries: self
+                .statements
+                .iter()
+                .filter(|s| {
+                    matches!(
+                        s.kind,
+                        StatementKind::Instruction(_) | StatementKind::Data(_)
+                    ) && s.size() > 0
+                })
+                .map(|s| SourceMapEntry {
+                    start: s.address,
+                    size: s.size(),
+                    location: s.span.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the relocatable fragment of `section`, creating it on first use
+    fn switch_section(fragments: &mut Vec<Fragment>, section: &str) -> usize {
+        if let Some(idx) = fragments
+            .iter()
+            .position(|f| f.section == section && f.origin.is_none())
+        {
+            idx
+        } else {
+            fragments.push(Fragment {
+                section: section.to_owned(),
+                origin: None,
+                size: 0,
+            });
+            fragments.len() - 1
+        }
+    }
+
+    /// `.proc name uses A, HLI preserves B returns A`, all lists are optional
+    fn parse_proc(line: &[Token]) -> Result<Proc, Error> {
+        let name = line
+            .get(1)
+            .filter(|t| t._type == TokenType::Text)
+            .ok_or_else(|| anyhow!("syntax error - .proc requires a name"))?;
+        let mut proc = Proc {
+            name: name.raw.clone(),
+            uses: None,
+            preserves: vec![],
+            returns: vec![],
+        };
+        let arguments = Self::free_text(&line[1..]);
+        let mut list = None;
+        for word in arguments.split(|c: char| c == ',' || c.is_whitespace()) {
+            match word.to_uppercase().as_str() {
+                "" => (),
+                "USES" => list = Some(proc.uses.get_or_insert_with(Vec::new)),
+                "PRESERVES" => list = Some(&mut proc.preserves),
+                "RETURNS" => list = Some(&mut proc.returns),
+                _ => {
+                    let registers = Register::from_name(word)
+                        .ok_or_else(|| anyhow!("{} is not A, B, HI, LI, AB or HLI", word))?;
+                    list.as_mut()
+                        .ok_or_else(|| {
+                            anyhow!("syntax error - expected `uses`, `preserves` or `returns`")
+                        })?
+                        .extend(registers);
+                }
+            }
+        }
+        if proc
+            .returns
+            .iter()
+            .any(|r| !matches!(r, Register::A | Register::B))
+        {
+            return Err(anyhow!("`returns` takes A, B or AB"));
+        }
+        Ok(proc)
+    }
+
+    /// Arguments of a directive as written, without a trailing comment
+    fn free_text(line: &[Token]) -> String {
+        let arguments: Vec<&str> = line
+            .iter()
+            .skip(1)
+            .map(|t| t.raw.as_str())
+            .take_while(|raw| *raw != "//")
+            .collect();
+        arguments.join(" ")
+    }
+
+    /// `.expect A == 0x00, EX == 0, ZER == 1, &0x8000 == 'a', &label != 0x00`
+    fn parse_expectations(line: &[Token]) -> Result<Vec<Expectation>, Error> {
+        let mut expectations = vec![];
+        for expectation in Self::free_text(line).split(',') {
+            let expectation = expectation.trim();
+            let syntax_error = || {
+                anyhow!(
+                    "syntax error - expected eg. `A == 0x00`, got `{}`",
+                    expectation
+                )
+            };
+            let split = expectation
+                .find(['=', '!', '<', '>'])
+                .ok_or_else(syntax_error)?;
+            let (target, condition) = expectation.split_at(split);
+            let (comparison, value) = Comparison::split(condition).ok_or_else(syntax_error)?;
+            let target = match target.trim().to_uppercase().as_str() {
+                register @ ("A" | "B" | "HI" | "LI" | "AB" | "HLI" | "EX") => {
+                    ExpectTarget::Register(register.to_owned())
+                }
+                "ZERO" => ExpectTarget::Flag("ZER".to_owned()),
+                flag @ ("ZER" | "OVF" | "ERR" | "OK" | "IRQ") => {
+                    ExpectTarget::Flag(flag.to_owned())
+                }
+                _ => {
+                    let target = target.trim();
+                    let memory = target.strip_prefix('&').ok_or_else(|| {
+                        anyhow!(
+                            "cannot check `{}`, expected a register, a flag or `&address`",
+                            target
+                        )
+                    })?;
+                    ExpectTarget::Memory(match memory.strip_prefix("0x") {
+                        Some(hex) => Operand::Address(
+                            u16::from_str_radix(hex, 16)
+                                .map_err(|_| anyhow!("{} is not a valid address", target))?,
+                        ),
+                        None => Operand::Label(memory.to_owned()),
+                    })
+                }
+            };
+            let value = match value.strip_prefix("0x") {
+                _ if value.starts_with('*') => Ok(Operand::LabelAddress(value[1..].to_owned())),
+                Some(hex) => u16::from_str_radix(hex, 16).map(Operand::Immediate16),
+                None if value.len() == 3 && value.starts_with('\'') && value.ends_with('\'') => {
+                    Ok(Operand::Immediate16(value.as_bytes()[1] as u16))
+                }
+                None => value.parse().map(Operand::Immediate16),
+            }
+            .map_err(|_| anyhow!("{} is not a valid value", value))?;
+            expectations.push(Expectation {
+                target,
+                comparison,
+                value,
+            });
+        }
+        Ok(expectations)
+    }
+
+    /// Checks the worst-case stack usage of every entry point against `.stack_size`, or the stack
+    /// the CPU starts with
+    fn check_stack_usage(&mut self, graph: &ControlFlowGraph) -> Result<(), Error> {
+        let stack_size = self
+            .statements
+            .iter()
+            .rev()
+            .find_map(|s| match s.kind {
+                StatementKind::Directive(Directive::StackSize(size)) => Some(size),
+                _ => None,
+            })
+            .unwrap_or(Cpu::default().stack_size);
+        let analysis = StackAnalysis::new(graph);
+        for routine in analysis.entry_points() {
+            let Some(usage) = analysis.usage(routine.entry) else {
+                continue;
+            };
+            let location = graph
+                .block(routine.entry)
+                .and_then(|b| b.instructions.first())
+                .map(|i| i.location.clone());
+            let chain: Vec<&str> = std::iter::once(routine.entry)
+                .chain(usage.deepest.iter().copied())
+                .filter_map(|entry| graph.routine(entry).map(|r| r.name.as_str()))
+                .collect();
+            match usage.total {
+                Some(total) if total > stack_size as u32 => {
+                    let message = format!(
+                        "stack usage of `{}` can reach {} bytes through {}, more than the stack size of {} bytes",
+                        routine.name,
+                        total,
+                        chain.join(" -> "),
+                        stack_size
+                    );
+                    return Err(match &location {
+                        Some(location) => error_at(location, message),
+                        None => anyhow!(message),
+                    });
+                }
+                Some(_) => (),
+                None => self.diagnostics.push(Diagnostic::warning(
+                    location,
+                    format!(
+                        "stack usage of `{}` has no bound (recursion or pushes in a loop), through {}",
+                        routine.name,
+                        chain.join(" -> ")
+                    ),
+                )),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the registers written by each `.proc` against its `uses` and `preserves`
+    fn check_proc_contracts(&self, graph: &ControlFlowGraph) -> Result<(), Error> {
+        let stacks = RegisterStackAnalysis::new(graph, &register_stack::declared(&self.statements));
+        for statement in &self.statements {
+            let StatementKind::Directive(Directive::Proc(proc)) = &statement.kind else {
+                continue;
+            };
+            let errors = contract::check(proc, statement.address, &statement.span, graph, &stacks);
+            if let Some((location, message)) = errors.into_iter().next() {
+                return Err(error_at(&location, message));
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the tokens returned by the instruction parsers: the first one holds the opcode,
+    /// the rest are the operands encoded after it
+    fn to_instruction(parsed: Vec<Token>) -> Result<Instruction, Error> {
+        let mut parsed = parsed.iter();
+        let opcode = parsed
+            .next()
+            .and_then(|t| t.opcode)
+            .ok_or_else(|| anyhow!("instruction without an opcode"))?;
+        Ok(Instruction {
+            opcode,
+            operands: parsed.map(Self::to_operand).collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn to_operand(token: &Token) -> Result<Operand, Error> {
+        let value = || {
+            token
+                .value
+                .ok_or_else(|| anyhow!("{} is not a valid value", token.raw))
+        };
+        match token._type {
+            TokenType::ImmediateValue8 => Ok(Operand::Immediate8(value()? as u8)),
+            TokenType::ImmediateValue16 => match token.raw.strip_prefix('*') {
+                Some(label) => Ok(Operand::LabelAddress(label.to_owned())),
+                None => Ok(Operand::Immediate16(value()? as u16)),
+            },
+            TokenType::Address => Ok(Operand::Address(value()? as u16)),
+            TokenType::Text | TokenType::Label => Ok(Operand::Label(token.raw.clone())),
+            _ => Err(anyhow!("{} cannot be used as an operand", token.raw)),
+        }
+    }
+
     fn parse_instruction(
         tokenised_line: &[Token],
         current_mem_address: &mut u16,
     ) -> Result<Vec<Token>, Error> {
-        match tokenised_line.get(0).unwrap().formatted_raw().as_str() {
+        match tokenised_line.first().unwrap().formatted_raw().as_str() {
             "NOOP" => parse_noop(tokenised_line, current_mem_address),
             "PUSH" => parse_push(tokenised_line, current_mem_address),
             "POP" => parse_pop(tokenised_line, current_mem_address),
@@ -434,35 +1159,94 @@ impl<'a> Assembler<'a> {
 
     fn generate_bytes(&self, verbose: bool) -> Result<Vec<u8>, Error> {
         let mut result = vec![0; 0xffff + 1];
-        for token in &self.parsed_tokens {
-            let address = token.address.unwrap() as usize;
-            if token.opcode.is_some() {
-                let instruction_val = token.opcode.clone().unwrap() as u8;
-                if verbose {
-                    println!(
-                        "Writing instruction: {:02x?} (0x{:02x})",
-                        token, instruction_val
-                    );
+        let resolve = |name: &str| self.labels.get(name).copied();
+        for statement in &self.statements {
+            let bytes = statement.encode(&resolve);
+            if verbose && !bytes.is_empty() {
+                println!(
+                    "Writing {:02x?} at 0x{:04x}: {:?}",
+                    bytes, statement.address, statement.kind
+                );
+            }
+            let address = statement.address as usize;
+            result[address..address + bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok(result)
+    }
+
+    fn generate_object(&self, verbose: bool) -> Result<Object, Error> {
+        let mut object = Object {
+            source: self.input_path.display().to_string(),
+            ..Default::default()
+        };
+        for fragment in &self.fragments {
+            let noload = self
+                .linker_script
+                .section(&fragment.section)
+                .is_some_and(|rule| rule.noload);
+            object.sections.push(ObjectSection {
+                name: fragment.section.clone(),
+                origin: fragment.origin,
+                size: fragment.size,
+                noload,
+                data: if noload {
+                    vec![]
+                } else {
+                    vec![0; fragment.size as usize]
+                },
+            });
+        }
+        let exports: Vec<&String> = self
+            .statements
+            .iter()
+            .filter_map(|s| match &s.kind {
+                StatementKind::Directive(Directive::Export(names)) => Some(names),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        for statement in &self.statements {
+            if let Some(name) = statement.label() {
+                if object.symbol(name).is_none() {
+                    object.symbols.push(Symbol {
+                        name: name.to_owned(),
+                        section: statement.fragment,
+                        offset: statement.address,
+                        exported: exports.contains(&&name.to_owned()),
+                    });
                 }
-                result[address] = instruction_val;
-            } else if token.value.is_some() {
-                if token._type == TokenType::ImmediateValue8 {
+            }
+        }
+        if let Some(missing) = exports.iter().find(|e| object.symbol(e).is_none()) {
+            return Err(anyhow!("exported label {} is not defined", missing));
+        }
+
+        for statement in &self.statements {
+            // Label references are left for the linker, undefined ones are imported from other objects
+            for (offset, operand) in statement.operands() {
+                if let Some(symbol) = operand.label() {
                     if verbose {
-                        println!("Writing 8 bit data: {:02x?}", token);
+                        println!("Relocation for `{}` at {:?}", symbol, statement.span);
                     }
-                    result[address] = (token.value.unwrap() & 0xff) as u8;
-                } else if token._type == TokenType::ImmediateValue16
-                    || token._type == TokenType::Label
-                    || token._type == TokenType::Address
-                {
-                    if verbose {
-                        println!("Writing 16 bit data: {:04x?}", token);
+                    if object.symbol(symbol).is_none()
+                        && !object.imports.iter().any(|i| i == symbol)
+                    {
+                        object.imports.push(symbol.to_owned());
                     }
-                    result[address] = ((token.value.unwrap() & 0xff00) >> 8) as u8;
-                    result[address + 1] = (token.value.unwrap() & 0xff) as u8;
+                    object.relocations.push(Relocation {
+                        section: statement.fragment,
+                        offset,
+                        symbol: symbol.to_owned(),
+                    });
                 }
             }
+            let section = &mut object.sections[statement.fragment];
+            if !section.noload {
+                let bytes = statement.encode(&|_| None);
+                let offset = statement.address as usize;
+                section.data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            }
         }
-        Ok(result)
+        Ok(object)
     }
 }
@@ -0,0 +1,339 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{anyhow, Error};
+
+use crate::object::Object;
+
+/// Memory layout used when no linker script is given:
+/// code and read-only data in ROM, variables in RAM and the interrupt/reset vectors at the top
+pub const DEFAULT_LINKER_SCRIPT: &str = "\
+MEMORY ROM 0x0000 0x7fff
+MEMORY RAM 0x8000 0xffef
+MEMORY VECTORS 0xfff0 0xffff
+
+SECTION code ROM
+SECTION rodata ROM
+SECTION data RAM
+SECTION bss RAM NOLOAD
+SECTION vectors VECTORS
+";
+
+/// Named, inclusive address range that sections are placed into
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl MemoryRegion {
+    pub fn size(&self) -> usize {
+        self.end as usize - self.start as usize + 1
+    }
+}
+
+/// Placement rule for a single section
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionRule {
+    pub name: String,
+    pub region: String,
+    pub noload: bool, // section only reserves space, nothing is written to the image
+}
+
+/// Small linker description consisting of `MEMORY` and `SECTION` lines:
+///
+/// ```text
+/// MEMORY <name> <start> <end>
+/// SECTION <name> <region> [NOLOAD]
+/// ```
+///
+/// Sections are placed in their region in the order they are declared, each at the lowest address
+/// where it fits around the sections before it and the data placed with `>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkerScript {
+    regions: Vec<MemoryRegion>,
+    sections: Vec<SectionRule>,
+}
+
+impl LinkerScript {
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+
+    pub fn sections(&self) -> &[SectionRule] {
+        &self.sections
+    }
+
+    pub fn region(&self, name: &str) -> Option<&MemoryRegion> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&SectionRule> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Returns the region containing `address`, if any
+    pub fn region_of(&self, address: u16) -> Option<&MemoryRegion> {
+        self.regions
+            .iter()
+            .find(|r| r.start <= address && address <= r.end)
+    }
+
+    /// Computes the base address of every fragment.
+    /// Fragments with an `origin` (placed with `>`) stay where they are, the rest are placed
+    /// section by section at the lowest address of their region where they fit between the
+    /// fragments placed before them. Overlapping origins are an error.
+    pub(crate) fn place(&self, fragments: &[Fragment]) -> Result<Vec<u16>, Error> {
+        let mut bases = vec![0u16; fragments.len()];
+        let mut occupied: Vec<(usize, usize, usize)> = vec![]; // (start, end exclusive, fragment)
+
+        for (idx, fragment) in fragments.iter().enumerate() {
+            if self.section(&fragment.section).is_none() {
+                return Err(anyhow!(
+                    "section `{}` is not placed by the linker script",
+                    fragment.section
+                ));
+            }
+            if let Some(origin) = fragment.origin {
+                let end = origin as usize + fragment.size as usize;
+                if end > 0x10000 {
+                    return Err(anyhow!(
+                        "data placed at 0x{:04x} ({} bytes) does not fit in memory",
+                        origin,
+                        fragment.size
+                    ));
+                }
+                bases[idx] = origin;
+                occupied.push((origin as usize, end, idx));
+            }
+        }
+
+        for region in &self.regions {
+            for rule in self.sections.iter().filter(|s| s.region == region.name) {
+                for (idx, fragment) in fragments.iter().enumerate() {
+                    if fragment.origin.is_some() || fragment.section != rule.name {
+                        continue;
+                    }
+                    // Lowest address of the region where the fragment fits between the ones
+                    // already placed
+                    let size = fragment.size as usize;
+                    let mut start = region.start as usize;
+                    while let Some(&(_, end, _)) = occupied
+                        .iter()
+                        .find(|(s, e, _)| *s < start + size && start < *e)
+                    {
+                        start = end;
+                    }
+                    let end = start + size;
+                    if end > region.end as usize + 1 {
+                        return Err(anyhow!(
+                            "section `{}` overflows region {} (0x{:04x}-0x{:04x}) by {} bytes",
+                            rule.name,
+                            region.name,
+                            region.start,
+                            region.end,
+                            end - (region.end as usize + 1)
+                        ));
+                    }
+                    bases[idx] = start as u16;
+                    occupied.push((start, end, idx));
+                }
+            }
+        }
+
+        occupied.sort();
+        for pair in occupied.windows(2) {
+            let ((_, first_end, first), (second_start, _, second)) = (pair[0], pair[1]);
+            if second_start < first_end {
+                return Err(anyhow!(
+                    "section `{}` at 0x{:04x} overlaps section `{}` at 0x{:04x}",
+                    fragments[second].section,
+                    second_start,
+                    fragments[first].section,
+                    bases[first]
+                ));
+            }
+        }
+        Ok(bases)
+    }
+}
+
+impl Default for LinkerScript {
+    fn default() -> Self {
+        DEFAULT_LINKER_SCRIPT
+            .parse()
+            .expect("default linker script is valid")
+    }
+}
+
+impl FromStr for LinkerScript {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut script = LinkerScript {
+            regions: vec![],
+            sections: vec![],
+        };
+        for (line_n, line) in input.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                [keyword, name, start, end] if keyword.eq_ignore_ascii_case("MEMORY") => {
+                    let start = parse_address(start)
+                        .map_err(|e| anyhow!("linker script line {}: {}", line_n + 1, e))?;
+                    let end = parse_address(end)
+                        .map_err(|e| anyhow!("linker script line {}: {}", line_n + 1, e))?;
+                    if end < start {
+                        return Err(anyhow!(
+                            "linker script line {}: region {} ends before it starts",
+                            line_n + 1,
+                            name
+                        ));
+                    }
+                    script.regions.push(MemoryRegion {
+                        name: name.to_uppercase(),
+                        start,
+                        end,
+                    });
+                }
+                [keyword, name, region, rest @ ..] if keyword.eq_ignore_ascii_case("SECTION") => {
+                    let noload = match rest {
+                        [] => false,
+                        [flag] if flag.eq_ignore_ascii_case("NOLOAD") => true,
+                        _ => {
+                            return Err(anyhow!(
+                                "linker script line {}: unknown section flags: {}",
+                                line_n + 1,
+                                rest.join(" ")
+                            ))
+                        }
+                    };
+                    let region = region.to_uppercase();
+                    if script.region(&region).is_none() {
+                        return Err(anyhow!(
+                            "linker script line {}: unknown memory region {}",
+                            line_n + 1,
+                            region
+                        ));
+                    }
+                    script.sections.push(SectionRule {
+                        name: name.to_lowercase(),
+                        region,
+                        noload,
+                    });
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "linker script line {}: syntax error: {}",
+                        line_n + 1,
+                        line.trim()
+                    ))
+                }
+            }
+        }
+        Ok(script)
+    }
+}
+
+/// Places the sections of all `objects`, resolves their relocations and returns the memory image.
+/// Relocations are resolved against the labels of their own object first, then against the
+/// symbols exported by any of the objects.
+pub fn link(objects: &[Object], script: &LinkerScript, verbose: bool) -> Result<Vec<u8>, Error> {
+    let mut fragments = vec![];
+    let mut first_fragment = vec![]; // index of the first fragment of each object
+    for object in objects {
+        first_fragment.push(fragments.len());
+        fragments.extend(object.sections.iter().map(|section| Fragment {
+            section: section.name.clone(),
+            origin: section.origin,
+            size: section.size,
+        }));
+    }
+    let bases = script.place(&fragments)?;
+
+    let mut exports: HashMap<&str, (u16, &str)> = HashMap::new();
+    for (object_n, object) in objects.iter().enumerate() {
+        for symbol in object.exports() {
+            let address =
+                bases[first_fragment[object_n] + symbol.section].wrapping_add(symbol.offset);
+            if let Some((_, other)) = exports.insert(&symbol.name, (address, &object.source)) {
+                return Err(anyhow!(
+                    "symbol `{}` is exported by both {} and {}",
+                    symbol.name,
+                    other,
+                    object.source
+                ));
+            }
+        }
+    }
+
+    let mut image = vec![0; 0xffff + 1];
+    for (object_n, object) in objects.iter().enumerate() {
+        for (section_n, section) in object.sections.iter().enumerate() {
+            let base = bases[first_fragment[object_n] + section_n] as usize;
+            if verbose {
+                println!(
+                    "Placed section `{}` of {}: 0x{:04x} ({} bytes)",
+                    section.name, object.source, base, section.size
+                );
+            }
+            image[base..base + section.data.len()].copy_from_slice(&section.data);
+        }
+        for relocation in &object.relocations {
+            let address = match object.symbol(&relocation.symbol) {
+                Some(symbol) => {
+                    bases[first_fragment[object_n] + symbol.section].wrapping_add(symbol.offset)
+                }
+                None => {
+                    exports
+                        .get(relocation.symbol.as_str())
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "undefined symbol `{}` referenced in {}",
+                                relocation.symbol,
+                                object.source
+                            )
+                        })?
+                        .0
+                }
+            };
+            let target = bases[first_fragment[object_n] + relocation.section] as usize
+                + relocation.offset as usize;
+            if target >= 0xffff {
+                return Err(anyhow!(
+                    "relocation of `{}` at 0x{:04x} in {} does not fit in memory",
+                    relocation.symbol,
+                    target,
+                    object.source
+                ));
+            }
+            if verbose {
+                println!(
+                    "Relocating `{}` at 0x{:04x} to 0x{:04x}",
+                    relocation.symbol, target, address
+                );
+            }
+            image[target] = ((address & 0xff00) >> 8) as u8;
+            image[target + 1] = (address & 0xff) as u8;
+        }
+    }
+    Ok(image)
+}
+
+fn parse_address(raw: &str) -> Result<u16, Error> {
+    let digits = raw
+        .strip_prefix("0x")
+        .or_else(|| raw.strip_prefix("0X"))
+        .ok_or_else(|| anyhow!("address {} must start with 0x", raw))?;
+    u16::from_str_radix(digits, 16).map_err(|e| anyhow!("invalid address {}: {}", raw, e))
+}
+
+/// Continuous piece of a section. Every section has one relocatable fragment,
+/// each `>` opens a new fragment with a fixed `origin`.
+#[derive(Debug, Clone)]
+pub(crate) struct Fragment {
+    pub section: String,
+    pub origin: Option<u16>,
+    pub size: u16,
+}
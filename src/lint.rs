@@ -0,0 +1,567 @@
+//! Checks for suspicious but valid code, run on the linked program by `nox_asm lint`.
+//!
+//! Every rule can be turned off for the whole program in a config file, or for the lines that
+//! follow a directive until it is turned back on:
+//!
+//! ```text
+//! .lint disable unused-label, unreachable
+//! .lint enable unreachable
+//! ```
+//!
+//! The config file has one setting per line, `//` starts a comment:
+//!
+//! ```text
+//! disable unused-label     // rules that are off unless a `.lint enable` turns them on
+//! rom 0xc000-0xcfff        // read-only ranges in addition to the ROM region of the linker script
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Error};
+
+use crate::{
+    assembly::Diagnostic,
+    cfg::{Block, ControlFlowGraph, Terminator},
+    emulator::Cpu,
+    ir::{Directive, ExpectTarget, Operand, Statement, StatementKind},
+    linker::LinkerScript,
+    opcodes::Opcode,
+    register_stack::{self, Depth, Imbalance, RegisterStackAnalysis},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    Unreachable,         // instructions after `JMP`, `RET` or `HALT` that nothing jumps to
+    UnusedLabel,         // labels that are never referenced
+    CallWithoutReturn,   // `CALL` of code that never reaches `RET`
+    RomWrite,            // absolute writes to read-only memory
+    EmptyStackMove,      // `PUSH A S 0x00` and the like
+    UnsetFlag,           // `JZE`/`JOF` reachable without an instruction setting the flag
+    FallthroughIntoData, // code running into `$` or `.reserve`
+    UnbalancedMerge,     // paths meeting with different A/B stack depths
+    UnbalancedReturn,    // `RET` with A/B stack depths other than at the routine's entry
+}
+
+impl Rule {
+    pub const ALL: [Rule; 9] = [
+        Rule::Unreachable,
+        Rule::UnusedLabel,
+        Rule::CallWithoutReturn,
+        Rule::RomWrite,
+        Rule::EmptyStackMove,
+        Rule::UnsetFlag,
+        Rule::FallthroughIntoData,
+        Rule::UnbalancedMerge,
+        Rule::UnbalancedReturn,
+    ];
+
+    /// Name used in directives, config files and warnings
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::Unreachable => "unreachable",
+            Rule::UnusedLabel => "unused-label",
+            Rule::CallWithoutReturn => "call-without-ret",
+            Rule::RomWrite => "rom-write",
+            Rule::EmptyStackMove => "empty-stack-move",
+            Rule::UnsetFlag => "unset-flag",
+            Rule::FallthroughIntoData => "fallthrough-into-data",
+            Rule::UnbalancedMerge => "unbalanced-merge",
+            Rule::UnbalancedReturn => "unbalanced-return",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Self::ALL
+            .into_iter()
+            .find(|rule| rule.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Parses a list of rule names separated by commas or spaces
+    pub fn parse_list(list: &str) -> Result<Vec<Rule>, Error> {
+        list.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                Self::from_name(name).ok_or_else(|| anyhow!("unknown lint rule `{}`", name))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    pub disabled: HashSet<Rule>,
+    pub rom: Vec<RangeInclusive<u16>>,
+}
+
+impl FromStr for LintConfig {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut config = LintConfig::default();
+        for (line_n, line) in input.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or_default().trim();
+            let error = |e: Error| anyhow!("lint config line {}: {}", line_n + 1, e);
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword.to_lowercase().as_str() {
+                "" => (),
+                "disable" => config
+                    .disabled
+                    .extend(Rule::parse_list(rest).map_err(error)?),
+                "enable" => {
+                    for rule in Rule::parse_list(rest).map_err(error)? {
+                        config.disabled.remove(&rule);
+                    }
+                }
+                "rom" => {
+                    let range = parse_range(rest.trim()).map_err(error)?;
+                    config.rom.push(range);
+                }
+                _ => return Err(error(anyhow!("syntax error: {}", line))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Runs the enabled rules over linked `statements`, `resolve` returns label addresses
+pub fn lint(
+    statements: &[Statement],
+    resolve: impl Fn(&str) -> Option<u16>,
+    script: &LinkerScript,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let mut linter = Linter::new(statements, config);
+    let graph = ControlFlowGraph::new(statements, &resolve);
+    let value = |operand: &Operand| match operand {
+        Operand::Immediate8(value) => Some(*value as u16),
+        Operand::Immediate16(value) | Operand::Address(value) => Some(*value),
+        Operand::Label(name) | Operand::LabelAddress(name) => resolve(name),
+    };
+    // Instructions by address, with the value of their operand
+    let mut instructions: BTreeMap<u16, (usize, Opcode, Option<u16>)> = BTreeMap::new();
+    for (index, statement) in statements.iter().enumerate() {
+        if let Some(instruction) = statement.instruction() {
+            let operand = instruction.operands.first().and_then(value);
+            instructions.insert(statement.address, (index, instruction.opcode, operand));
+        }
+    }
+    let targets: HashSet<u16> = instructions
+        .values()
+        .filter(|(_, opcode, _)| {
+            opcode.is_conditional_jump() || matches!(opcode, Opcode::JUMP | Opcode::CALL)
+        })
+        .filter_map(|(_, _, operand)| *operand)
+        .collect();
+
+    linter.check_unreachable(&targets);
+    linter.check_fallthrough();
+    linter.check_labels();
+    linter.check_register_stacks(&graph, &instructions);
+
+    let mut rom = config.rom.clone();
+    rom.extend(
+        script
+            .regions()
+            .iter()
+            .filter(|r| r.name == "ROM")
+            .map(|r| r.start..=r.end),
+    );
+    let mut predecessors: HashMap<u16, Vec<u16>> = HashMap::new();
+    for block in graph.blocks() {
+        for successor in block.successors() {
+            predecessors.entry(successor).or_default().push(block.start);
+        }
+    }
+    let mut returns: HashMap<u16, bool> = HashMap::new();
+    for (&address, &(index, opcode, operand)) in &instructions {
+        let operand = operand.unwrap_or_default();
+        match opcode {
+            Opcode::CALL => {
+                let Some(entry) = graph.block(operand) else {
+                    continue;
+                };
+                let returns = *returns
+                    .entry(operand)
+                    .or_insert_with(|| reaches_return(&graph, entry.start));
+                if !returns {
+                    let name = label_name(statements, operand);
+                    linter.warn(
+                        index,
+                        Rule::CallWithoutReturn,
+                        format!("{} never reaches RET", name),
+                    );
+                }
+            }
+            Opcode::JUMP_IF_ZERO | Opcode::JUMP_IF_OVERFLOW
+                if !flag_set_before(&graph, &predecessors, address) =>
+            {
+                let flag = if opcode == Opcode::JUMP_IF_ZERO {
+                    "ZER"
+                } else {
+                    "OVF"
+                };
+                linter.warn(
+                    index,
+                    Rule::UnsetFlag,
+                    format!(
+                        "{} can be reached without an instruction setting {} before it",
+                        opcode.mnemonic(),
+                        flag
+                    ),
+                );
+            }
+            Opcode::PUSH_A_STACK
+            | Opcode::POP_A_STACK
+            | Opcode::POP_STACK_A
+            | Opcode::PUSH_B_STACK
+            | Opcode::POP_B_STACK
+            | Opcode::POP_STACK_B
+                if operand == 0 =>
+            {
+                let syntax = opcode.syntax().replace("{}", "0x00");
+                linter.warn(
+                    index,
+                    Rule::EmptyStackMove,
+                    format!("`{}` moves no bytes", syntax),
+                );
+            }
+            _ => (),
+        }
+        if let Some(size) = memory_write(opcode) {
+            let written = [operand, operand.wrapping_add(size - 1)];
+            if written.iter().any(|a| rom.iter().any(|r| r.contains(a))) {
+                let name = label_name(statements, operand);
+                linter.warn(index, Rule::RomWrite, format!("writes to ROM at {}", name));
+            }
+        }
+    }
+    linter.finish()
+}
+
+struct Linter<'a> {
+    statements: &'a [Statement],
+    disabled: Vec<HashSet<Rule>>, // rules turned off at each statement
+    warnings: Vec<(usize, Diagnostic)>,
+}
+
+impl<'a> Linter<'a> {
+    fn new(statements: &'a [Statement], config: &LintConfig) -> Self {
+        let mut current = config.disabled.clone();
+        let mut disabled = vec![];
+        for statement in statements {
+            if let StatementKind::Directive(Directive::Lint { enable, rules }) = &statement.kind {
+                for rule in rules {
+                    if *enable {
+                        current.remove(rule);
+                    } else {
+                        current.insert(*rule);
+                    }
+                }
+            }
+            disabled.push(current.clone());
+        }
+        Self {
+            statements,
+            disabled,
+            warnings: vec![],
+        }
+    }
+
+    fn warn(&mut self, index: usize, rule: Rule, message: String) {
+        if self.disabled[index].contains(&rule) {
+            return;
+        }
+        let location = Some(self.statements[index].span.clone());
+        let message = format!("{} [{}]", message, rule.name());
+        self.warnings
+            .push((index, Diagnostic::warning(location, message)));
+    }
+
+    /// Warnings in the order of the source
+    fn finish(mut self) -> Vec<Diagnostic> {
+        self.warnings.sort_by_key(|(index, _)| *index);
+        self.warnings.into_iter().map(|(_, d)| d).collect()
+    }
+
+    /// Instructions after `JMP`, `RET` or `HALT`, up to the next label or jump target
+    fn check_unreachable(&mut self, targets: &HashSet<u16>) {
+        let fragments: HashSet<usize> = self.statements.iter().map(|s| s.fragment).collect();
+        for fragment in fragments {
+            let mut after_exit: Option<&'static str> = None; // mnemonic ending the reachable code
+            let mut reported = false;
+            for index in 0..self.statements.len() {
+                let statement = &self.statements[index];
+                if statement.fragment != fragment {
+                    continue;
+                }
+                let starts_code = match &statement.kind {
+                    StatementKind::Label(_)
+                    | StatementKind::Directive(Directive::Test(_) | Directive::Proc(_)) => true,
+                    StatementKind::Instruction(_) => targets.contains(&statement.address),
+                    _ => false,
+                };
+                if starts_code {
+                    after_exit = None;
+                    reported = false;
+                }
+                let Some(instruction) = statement.instruction() else {
+                    continue;
+                };
+                if let (Some(mnemonic), false) = (after_exit, reported) {
+                    reported = true;
+                    self.warn(
+                        index,
+                        Rule::Unreachable,
+                        format!("unreachable code after {}", mnemonic),
+                    );
+                }
+                let opcode = instruction.opcode;
+                if opcode.is_return() || matches!(opcode, Opcode::JUMP | Opcode::HALT) {
+                    after_exit = Some(opcode.mnemonic());
+                }
+            }
+        }
+    }
+
+    /// Instructions that continue into `$` or `.reserve` in memory, sections are placed one
+    /// after another so this can happen across them
+    fn check_fallthrough(&mut self) {
+        let mut data: HashMap<u16, &str> = HashMap::new(); // address -> label before it, if any
+        let mut label = "";
+        for statement in self.statements {
+            match &statement.kind {
+                StatementKind::Label(_) | StatementKind::Directive(Directive::Proc(_)) => {
+                    label = statement.label().unwrap_or_default()
+                }
+                StatementKind::Data(_) | StatementKind::Directive(Directive::Reserve(_)) => {
+                    data.entry(statement.address).or_insert(label);
+                }
+                _ => label = "",
+            }
+        }
+        for index in 0..self.statements.len() {
+            let statement = &self.statements[index];
+            let Some(instruction) = statement.instruction() else {
+                continue;
+            };
+            let opcode = instruction.opcode;
+            if opcode.is_return() || matches!(opcode, Opcode::JUMP | Opcode::HALT) {
+                continue;
+            }
+            let next = statement.address.wrapping_add(statement.size());
+            if let Some(label) = data.get(&next) {
+                let at = match *label {
+                    "" => format!("0x{:04x}", next),
+                    label => format!("`{}`", label),
+                };
+                self.warn(
+                    index,
+                    Rule::FallthroughIntoData,
+                    format!("execution falls through into data at {}", at),
+                );
+            }
+        }
+    }
+
+    fn check_labels(&mut self) {
+        let mut referenced: HashSet<&str> = HashSet::new();
+        for statement in self.statements {
+            match &statement.kind {
+                StatementKind::Instruction(_) | StatementKind::Data(_) => {
+                    referenced.extend(statement.operands().filter_map(|(_, o)| o.label()));
+                }
+                StatementKind::Directive(Directive::Export(names)) => {
+                    referenced.extend(names.iter().map(String::as_str));
+                }
+                StatementKind::Directive(Directive::Expect(expectations)) => {
+                    for expectation in expectations {
+                        referenced.extend(expectation.value.label());
+                        if let ExpectTarget::Memory(operand) = &expectation.target {
+                            referenced.extend(operand.label());
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        // The program starts at the reset address without any reference to it
+        let reset = Cpu::default().pc;
+        for (index, statement) in self.statements.iter().enumerate() {
+            let Some(name) = statement.label() else {
+                continue;
+            };
+            if !referenced.contains(name) && statement.address != reset {
+                self.warn(
+                    index,
+                    Rule::UnusedLabel,
+                    format!("label `{}` is never referenced", name),
+                );
+            }
+        }
+    }
+
+    /// A/B stack depths that differ where paths meet or at `RET`
+    fn check_register_stacks(
+        &mut self,
+        graph: &ControlFlowGraph,
+        instructions: &BTreeMap<u16, (usize, Opcode, Option<u16>)>,
+    ) {
+        let analysis =
+            RegisterStackAnalysis::new(graph, &register_stack::declared(self.statements));
+        for routine in graph.routines() {
+            for imbalance in analysis.imbalances(routine.entry) {
+                let (address, rule, message) = match imbalance {
+                    Imbalance::Merge { address, depths } => (
+                        address,
+                        Rule::UnbalancedMerge,
+                        format!(
+                            "paths meet with different A/B stack depths in `{}`: {} and {}",
+                            routine.name, depths[0], depths[1]
+                        ),
+                    ),
+                    Imbalance::Return {
+                        address,
+                        depth,
+                        expected,
+                    } if *expected == Depth::default() => (
+                        address,
+                        Rule::UnbalancedReturn,
+                        format!("`{}` returns with {} since its entry", routine.name, depth),
+                    ),
+                    Imbalance::Return {
+                        address,
+                        depth,
+                        expected,
+                    } => (
+                        address,
+                        Rule::UnbalancedReturn,
+                        format!(
+                            "`{}` returns with {} since its entry, but declares {}",
+                            routine.name, depth, expected
+                        ),
+                    ),
+                };
+                if let Some((index, _, _)) = instructions.get(address) {
+                    self.warn(*index, rule, message);
+                }
+            }
+        }
+    }
+}
+
+/// `RET` can be reached from the block at `start`
+fn reaches_return(graph: &ControlFlowGraph, start: u16) -> bool {
+    let mut seen = HashSet::new();
+    let mut queue = vec![start];
+    while let Some(address) = queue.pop() {
+        if !seen.insert(address) {
+            continue;
+        }
+        let Some(block) = graph.block(address) else {
+            continue;
+        };
+        if let Terminator::Return(_) = block.terminator {
+            return true;
+        }
+        queue.extend(block.successors());
+    }
+    false
+}
+
+/// Every path to the instruction at `address` has an instruction setting ZER and OVF before it.
+/// `CALL` does not count, `RET` restores the flags of the caller
+fn flag_set_before(
+    graph: &ControlFlowGraph,
+    predecessors: &HashMap<u16, Vec<u16>>,
+    address: u16,
+) -> bool {
+    let Some(block) = graph
+        .blocks()
+        .find(|b| b.start <= address && address < b.end)
+    else {
+        return true;
+    };
+    let sets_flags = |block: &Block, end: u16| {
+        block
+            .instructions
+            .iter()
+            .any(|i| i.address < end && sets_result_flags(i.opcode))
+    };
+    if sets_flags(block, address) {
+        return true;
+    }
+    let mut seen = HashSet::new();
+    let mut queue = vec![block.start];
+    while let Some(start) = queue.pop() {
+        if !seen.insert(start) {
+            continue;
+        }
+        let Some(sources) = predecessors.get(&start) else {
+            return false; // entry of the code without any flag set on the way
+        };
+        for source in sources {
+            let source = graph.block(*source).unwrap();
+            if !sets_flags(source, source.end) {
+                queue.push(source.start);
+            }
+        }
+    }
+    true
+}
+
+/// `label+0x2`, or the address if there is no label before it
+fn label_name(statements: &[Statement], address: u16) -> String {
+    statements
+        .iter()
+        .filter_map(|s| s.label().map(|name| (s.address, name)))
+        .filter(|(start, _)| *start <= address)
+        .max_by_key(|(start, _)| *start)
+        .map(|(start, name)| match address - start {
+            0 => format!("`{}`", name),
+            offset => format!("`{}+0x{:x}`", name, offset),
+        })
+        .unwrap_or_else(|| format!("0x{:04x}", address))
+}
+
+fn parse_range(range: &str) -> Result<RangeInclusive<u16>, Error> {
+    let parse = |address: &str| {
+        u16::from_str_radix(address.trim().trim_start_matches("0x"), 16)
+            .map_err(|_| anyhow!("`{}` is not a hex address", address))
+    };
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| anyhow!("expected a range like 0x0000-0x7fff"))?;
+    Ok(parse(start)?..=parse(end)?)
+}
+
+/// Sets ZER and OVF from its result
+fn sets_result_flags(opcode: Opcode) -> bool {
+    matches!(
+        opcode.mnemonic(),
+        "ADD" | "SUB" | "SHL" | "SHR" | "AND" | "OR" | "XOR" | "NOT" | "CMP" | "INC" | "DEC"
+    ) || matches!(
+        opcode,
+        Opcode::PUSH_AB_STACK_ADDRESS
+            | Opcode::PUSH_AB_STACK_SIZE
+            | Opcode::CLEAR_OVF
+            | Opcode::CLEAR_ZERO
+    )
+}
+
+/// Bytes written to the absolute address operand of the instruction
+fn memory_write(opcode: Opcode) -> Option<u16> {
+    match opcode {
+        Opcode::POP_A_ABSOLUTE
+        | Opcode::POP_B_ABSOLUTE
+        | Opcode::PEEK_A_ABSOLUTE
+        | Opcode::PEEK_B_ABSOLUTE
+        | Opcode::STORE_HI_ABSOLUTE
+        | Opcode::STORE_LI_ABSOLUTE => Some(1),
+        Opcode::POP_AB_ABSOLUTE | Opcode::PEEK_AB_ABSOLUTE | Opcode::STORE_HLI_ABSOLUTE => Some(2),
+        _ => None,
+    }
+}
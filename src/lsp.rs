@@ -0,0 +1,602 @@
+//! Language server for `.nox` files, speaking the Language Server Protocol over stdio.
+//!
+//! Diagnostics come from assembling the document (open documents are read from the editor,
+//! everything else from disk). Labels are looked up in the text of the document and the files
+//! it includes, so go-to-definition, references and symbols work while the file has errors.
+//!
+//! Columns are byte offsets inside the server and UTF-16 code units (`character`) on the wire.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use serde_json::{json, Value};
+
+use crate::{
+    opcodes::{
+        flag_description, mnemonic_description, register_description, Opcode, FLAGS, MNEMONICS,
+        PSEUDO_MNEMONICS, REGISTERS,
+    },
+    source::{load_lines, Location},
+    Assembler, Assembly, Diagnostic, DiskFileSystem, FileSystem, Severity,
+};
+
+const DIRECTIVES: [&str; 20] = [
+    ".section",
+    ".reserve",
+    ".include",
+    ".export",
+    ".stack_size",
+    ".test",
+    ".expect",
+    ".endtest",
+    ".lint",
+    ".proc",
+    ".endproc",
+    ".if",
+    ".else",
+    ".endif",
+    ".while",
+    ".endwhile",
+    ".loop",
+    ".endloop",
+    ".break",
+    "$",
+];
+
+/// Open documents override the files on disk
+struct Documents(HashMap<PathBuf, String>);
+
+impl FileSystem for Documents {
+    fn read(&self, path: &Path) -> Result<String, Error> {
+        match self.0.get(path) {
+            Some(text) => Ok(text.clone()),
+            None => DiskFileSystem.read(path),
+        }
+    }
+}
+
+/// Label defined with `name:`
+struct Definition {
+    name: String,
+    location: Location,
+    character: usize, // UTF-16 column
+}
+
+/// Word of a source line, comments are left out
+struct Word<'a> {
+    text: &'a str,
+    start: usize, // column
+}
+
+#[derive(Default)]
+pub struct LanguageServer {
+    documents: HashMap<PathBuf, String>,
+    assemblies: HashMap<PathBuf, Assembly>, // last successful assembly of each document
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves requests until the client sends `exit` or closes the input
+    pub fn run(&mut self, mut input: impl BufRead, output: &mut impl Write) -> Result<(), Error> {
+        while let Some(message) = read_message(&mut input)? {
+            if message["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&message) {
+                write_message(output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request or notification, returning the response and notifications to send
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let mut replies = vec![];
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1, // full text on every change
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "nox_asm", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                let text = document["text"].as_str().unwrap_or_default();
+                replies.extend(self.update(&document["uri"], text));
+                return replies;
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    replies.extend(self.update(&params["textDocument"]["uri"], text));
+                }
+                return replies;
+            }
+            "textDocument/didClose" => {
+                let uri = &params["textDocument"]["uri"];
+                if let Some(path) = uri.as_str().and_then(uri_to_path) {
+                    self.documents.remove(&path);
+                    self.assemblies.remove(&path);
+                }
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/documentSymbol" => self.symbols(params),
+            _ if message.get("id").is_none() => return replies, // notifications we don't need
+            _ => Err(anyhow!("unsupported method `{}`", method)),
+        };
+        if let Some(id) = message.get("id") {
+            replies.push(match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(e) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": e.to_string() },
+                }),
+            });
+        }
+        replies
+    }
+
+    /// Stores the new text of a document and publishes its diagnostics
+    fn update(&mut self, uri: &Value, text: &str) -> Vec<Value> {
+        let Some(path) = uri.as_str().and_then(uri_to_path) else {
+            return vec![];
+        };
+        self.documents.insert(path.clone(), text.to_owned());
+        let files = Documents(self.documents.clone());
+        let mut diagnostics = vec![];
+        match Assembler::with_file_system(&path, files).build(false) {
+            Ok(assembly) => {
+                diagnostics.extend(assembly.diagnostics.iter().cloned());
+                self.assemblies.insert(path.clone(), assembly);
+            }
+            Err(e) => diagnostics.push(match e.downcast_ref::<Diagnostic>() {
+                Some(diagnostic) => diagnostic.clone(),
+                None => Diagnostic::error(None, e.to_string()),
+            }),
+        }
+        let diagnostics: Vec<Value> = diagnostics
+            .iter()
+            .map(|d| {
+                // Problems in included files are shown on the first line
+                let (line, message) = match &d.location {
+                    Some(location) if location.file == path => {
+                        (location.line - 1, d.message.clone())
+                    }
+                    Some(location) => (0, format!("{}: {}", location, d.message)),
+                    None => (0, d.message.clone()),
+                };
+                let length = text.lines().nth(line).map(utf16_len).unwrap_or_default();
+                json!({
+                    "range": range(line, 0, length),
+                    "severity": if d.severity == Severity::Error { 1 } else { 2 },
+                    "source": "nox_asm",
+                    "message": message,
+                })
+            })
+            .collect();
+        vec![notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )]
+    }
+
+    /// Lines of the document with its includes expanded, or just its own lines if they cannot be read
+    fn lines(&self, path: &Path) -> Vec<(Location, String)> {
+        let files = Documents(self.documents.clone());
+        load_lines(&files, path).unwrap_or_else(|_| {
+            let text = self.documents.get(path).cloned().unwrap_or_default();
+            text.lines()
+                .enumerate()
+                .map(|(n, line)| {
+                    let location = Location {
+                        file: path.to_owned(),
+                        line: n + 1,
+                    };
+                    (location, line.to_owned())
+                })
+                .collect()
+        })
+    }
+
+    fn definitions(lines: &[(Location, String)]) -> Vec<Definition> {
+        lines
+            .iter()
+            .filter_map(|(location, text)| {
+                // `label:` or `.proc label`
+                let mut words = words(text).into_iter();
+                let first = words.next()?;
+                let word = match first.text.strip_suffix(':') {
+                    Some(name) => Word {
+                        text: name,
+                        start: first.start,
+                    },
+                    None if first.text.eq_ignore_ascii_case(".proc") => words.next()?,
+                    None => return None,
+                };
+                Some(Definition {
+                    name: word.text.to_owned(),
+                    location: location.clone(),
+                    character: character(text, word.start),
+                })
+            })
+            .collect()
+    }
+
+    /// Document path, its lines and the word at the position of a request
+    fn position<'a>(
+        &self,
+        params: &Value,
+        lines: &'a [(Location, String)],
+    ) -> Option<(PathBuf, &'a str, Word<'a>)> {
+        let path = uri_to_path(params["textDocument"]["uri"].as_str()?)?;
+        let line = params["position"]["line"].as_u64()? as usize + 1;
+        let character = params["position"]["character"].as_u64()? as usize;
+        let (_, text) = lines
+            .iter()
+            .find(|(location, _)| location.file == path && location.line == line)?;
+        let column = column(text, character);
+        let word = words(text)
+            .into_iter()
+            .find(|w| w.start <= column && column <= w.start + w.text.len())?;
+        Some((path, text, word))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value, Error> {
+        let Some(path) = document_path(params) else {
+            return Ok(Value::Null);
+        };
+        let lines = self.lines(&path);
+        let Some((_, _, word)) = self.position(params, &lines) else {
+            return Ok(Value::Null);
+        };
+        let name = label_name(word.text);
+        Ok(Self::definitions(&lines)
+            .iter()
+            .find(|d| d.name == name)
+            .map(|d| {
+                location(
+                    &d.location.file,
+                    d.location.line - 1,
+                    d.character,
+                    d.character + utf16_len(&d.name),
+                )
+            })
+            .unwrap_or(Value::Null))
+    }
+
+    fn references(&self, params: &Value) -> Result<Value, Error> {
+        let Some(path) = document_path(params) else {
+            return Ok(Value::Null);
+        };
+        let lines = self.lines(&path);
+        let Some((_, _, word)) = self.position(params, &lines) else {
+            return Ok(Value::Null);
+        };
+        let name = label_name(word.text);
+        let declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+        let mut references = vec![];
+        for (location, text) in &lines {
+            for (n, word) in words(text).iter().enumerate() {
+                let is_definition = n == 0 && word.text.ends_with(':');
+                if label_name(word.text) != name || (is_definition && !declaration) {
+                    continue;
+                }
+                // Skip `*` and `&` before the name
+                let start = word.start + word.text.find(name).unwrap_or_default();
+                references.push(self::location(
+                    &location.file,
+                    location.line - 1,
+                    character(text, start),
+                    character(text, start) + utf16_len(name),
+                ));
+            }
+        }
+        Ok(Value::Array(references))
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value, Error> {
+        let Some(path) = document_path(params) else {
+            return Ok(Value::Null);
+        };
+        let lines = self.lines(&path);
+        let Some((path, text, word)) = self.position(params, &lines) else {
+            return Ok(Value::Null);
+        };
+        let upper = word.text.to_uppercase();
+        let contents = if let Some(description) = mnemonic_description(&upper) {
+            let mut contents = format!("**{}** - {}", upper, description);
+            if let Some(opcode) = line_opcode(text) {
+                contents.push_str(&format!(
+                    "\n\n`{}` - opcode 0x{:02x}, {} bytes",
+                    opcode.syntax().replace("{}", "x"),
+                    opcode as u8,
+                    opcode.size()
+                ));
+            } else {
+                let variants: Vec<String> = Opcode::ALL
+                    .iter()
+                    .filter(|o| o.mnemonic() == upper)
+                    .map(|o| {
+                        format!(
+                            "- `{}` - opcode 0x{:02x}, {} bytes",
+                            o.syntax().replace("{}", "x"),
+                            *o as u8,
+                            o.size()
+                        )
+                    })
+                    .collect();
+                contents.push_str("\n\n");
+                contents.push_str(&variants.join("\n"));
+            }
+            contents
+        } else if let Some(description) = register_description(&upper) {
+            format!("**{}** register - {}", upper, description)
+        } else if let Some(description) = flag_description(&upper) {
+            let name = if upper == "ZERO" { "ZER" } else { &upper };
+            format!("**{}** flag - {}", name, description)
+        } else {
+            let name = label_name(word.text);
+            let Some(definition) = Self::definitions(&lines)
+                .into_iter()
+                .find(|d| d.name == name)
+            else {
+                return Ok(Value::Null);
+            };
+            let mut contents = format!("**{}** - label at {}", name, definition.location);
+            if let Some(symbol) = self.assemblies.get(&path).and_then(|a| a.symbol(name)) {
+                contents.push_str(&format!(", address 0x{:04x}", symbol.address));
+            }
+            contents
+        };
+        Ok(json!({ "contents": { "kind": "markdown", "value": contents } }))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value, Error> {
+        // LSP completion item kinds
+        const KEYWORD: u32 = 14;
+        const VARIABLE: u32 = 6;
+        const CONSTANT: u32 = 21;
+        const FUNCTION: u32 = 3;
+        let mut items = vec![];
+        for mnemonic in MNEMONICS.into_iter().chain(PSEUDO_MNEMONICS) {
+            let detail = mnemonic_description(mnemonic).unwrap_or_default();
+            items.push(json!({ "label": mnemonic, "kind": KEYWORD, "detail": detail }));
+        }
+        for name in REGISTERS {
+            let detail = register_description(name).unwrap_or_default();
+            items.push(json!({ "label": name, "kind": VARIABLE, "detail": detail }));
+        }
+        for name in FLAGS {
+            let detail = flag_description(name).unwrap_or_default();
+            items.push(json!({ "label": name, "kind": CONSTANT, "detail": detail }));
+        }
+        for directive in DIRECTIVES {
+            items.push(json!({ "label": directive, "kind": KEYWORD }));
+        }
+        if let Some(path) = document_path(params) {
+            for definition in Self::definitions(&self.lines(&path)) {
+                items.push(json!({
+                    "label": definition.name,
+                    "kind": FUNCTION,
+                    "detail": definition.location.to_string(),
+                }));
+            }
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn symbols(&self, params: &Value) -> Result<Value, Error> {
+        // LSP symbol kinds
+        const FUNCTION: u32 = 12;
+        const VARIABLE: u32 = 13;
+        const EVENT: u32 = 24;
+        let Some(path) = document_path(params) else {
+            return Ok(Value::Null);
+        };
+        let text = self.documents.get(&path).cloned().unwrap_or_default();
+        let mut symbols = vec![];
+        let mut section = "code".to_owned();
+        for (n, line) in text.lines().enumerate() {
+            let words = words(line);
+            let Some(first) = words.first() else {
+                continue;
+            };
+            let (name, kind) = match first.text.to_lowercase().as_str() {
+                ".section" => {
+                    section = words
+                        .get(1)
+                        .map(|w| w.text.to_lowercase())
+                        .unwrap_or_default();
+                    continue;
+                }
+                ".test" => {
+                    let name = line.trim().trim_start_matches(first.text).trim();
+                    (name.trim_matches('"').to_owned(), EVENT)
+                }
+                label if label.ends_with(':') => {
+                    let kind = if section == "code" {
+                        FUNCTION
+                    } else {
+                        VARIABLE
+                    };
+                    (first.text.trim_end_matches(':').to_owned(), kind)
+                }
+                _ => continue,
+            };
+            let range = range(n, character(line, first.start), utf16_len(line.trim_end()));
+            symbols.push(json!({
+                "name": name,
+                "kind": kind,
+                "range": range,
+                "selectionRange": range,
+            }));
+        }
+        Ok(Value::Array(symbols))
+    }
+}
+
+/// Reads a message with its `Content-Length` header, `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| anyhow!("message without Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<(), Error> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// Range on one line, `start` and `end` are UTF-16 columns
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+fn location(file: &Path, line: usize, start: usize, end: usize) -> Value {
+    json!({ "uri": path_to_uri(file), "range": range(line, start, end) })
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// UTF-16 column of a byte column
+fn character(line: &str, column: usize) -> usize {
+    utf16_len(&line[..column])
+}
+
+/// Byte column of a UTF-16 column, the end of the line if it is past it
+fn column(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (index, char) in line.char_indices() {
+        if units >= character {
+            return index;
+        }
+        units += char.len_utf16();
+    }
+    line.len()
+}
+
+fn document_path(params: &Value) -> Option<PathBuf> {
+    uri_to_path(params["textDocument"]["uri"].as_str()?)
+}
+
+/// `file:///home/user/a%20b.nox` -> `/home/user/a b.nox`
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut chars = path.bytes();
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex: String = chars.by_ref().take(2).map(char::from).collect();
+            bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_owned();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn words(line: &str) -> Vec<Word<'_>> {
+    let mut words = vec![];
+    let mut start = None;
+    for (index, char) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (char.is_whitespace(), start) {
+            (false, None) => start = Some(index),
+            (true, Some(begin)) => {
+                let text = &line[begin..index];
+                if text.starts_with("//") {
+                    break;
+                }
+                words.push(Word { text, start: begin });
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    words
+}
+
+/// `*label`, `&label`, `label:` and `label,` all refer to `label`
+fn label_name(word: &str) -> &str {
+    word.trim_start_matches(['*', '&'])
+        .trim_end_matches([':', ','])
+}
+
+/// Opcode of the instruction on a line, if it assembles on its own
+fn line_opcode(text: &str) -> Option<Opcode> {
+    let mut assembler = Assembler::from_source(text.trim());
+    assembler.build(false).ok()?;
+    let instruction = assembler
+        .statements()
+        .iter()
+        .find_map(|s| s.instruction())?;
+    Some(instruction.opcode)
+}
@@ -0,0 +1,182 @@
+//! Harness for calling routines of a program from Rust, eg. in integration tests:
+//!
+//! ```
+//! use nox_asm::machine::{Machine, Registers};
+//!
+//! let mut machine = Machine::from_source(include_str!("../test/strlen.nox"))?;
+//! machine.set_memory(0x8100, b"hello\0");
+//! let registers = Registers { hli: 0x8100, ..Default::default() };
+//! assert_eq!(machine.call("strlen", registers)?.registers().a, [5]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::ops::Range;
+
+use anyhow::{anyhow, Error};
+
+use crate::{
+    assembly::Assembly,
+    emulator::{Emulator, Event, Flags, MEMORY_SIZE},
+    Assembler,
+};
+
+/// Limit of instructions executed by a single call
+pub const MAX_STEPS: u64 = 10_000_000;
+
+/// Registers passed to a routine and returned from it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub a: Vec<u8>, // the last value is the top of the stack
+    pub b: Vec<u8>,
+    pub hli: u16,
+    pub exit_code: u8,
+    pub flags: Flags,
+}
+
+/// Assembled program loaded into an emulator
+pub struct Machine {
+    assembly: Assembly,
+    emulator: Emulator,
+    max_steps: u64,
+    steps: u64, // instructions executed by the last call
+}
+
+impl Machine {
+    pub fn new(assembly: Assembly) -> Self {
+        let emulator = Emulator::new(&assembly.bytes);
+        Self::with_emulator(assembly, emulator)
+    }
+
+    /// Calls routines of `assembly` in an emulator with devices mapped
+    pub fn with_emulator(assembly: Assembly, emulator: Emulator) -> Self {
+        Self {
+            assembly,
+            emulator,
+            max_steps: MAX_STEPS,
+            steps: 0,
+        }
+    }
+
+    /// Assembles `source` with the default memory layout
+    pub fn from_source(source: &str) -> Result<Self, Error> {
+        Ok(Self::new(Assembler::from_source(source).build(false)?))
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn assembly(&self) -> &Assembly {
+        &self.assembly
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    /// Reloads the program and resets the CPU, memory written since is lost
+    pub fn reset(&mut self) -> &mut Self {
+        self.emulator.reset();
+        self
+    }
+
+    /// Address of a label
+    pub fn address_of(&self, label: &str) -> Result<u16, Error> {
+        self.assembly
+            .symbol(label)
+            .map(|s| s.address)
+            .ok_or_else(|| anyhow!("label `{}` is not defined", label))
+    }
+
+    pub fn set_memory(&mut self, address: u16, bytes: &[u8]) -> &mut Self {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.emulator.memory_mut()[address.wrapping_add(offset as u16) as usize] = *byte;
+        }
+        self
+    }
+
+    /// Moves the memory stack of the caller, routines get the rest of it after the call frame
+    pub fn set_stack(&mut self, address: u16, size: u16) -> &mut Self {
+        let cpu = &mut self.emulator.cpu;
+        cpu.stack_address = address;
+        cpu.stack_size = size;
+        cpu.stack_pointer = address;
+        self
+    }
+
+    /// Calls the routine at `label` with `registers` and runs it until it returns.
+    /// Halting, a fault or running for more than the step limit is an error
+    pub fn call(&mut self, label: &str, registers: Registers) -> Result<&mut Self, Error> {
+        let target = self.address_of(label)?;
+        let cpu = &mut self.emulator.cpu;
+        cpu.a = registers.a;
+        cpu.b = registers.b;
+        cpu.set_hli(registers.hli);
+        cpu.exit_code = registers.exit_code;
+        cpu.flags = registers.flags;
+        cpu.halted = false;
+
+        let depth = self.emulator.call_depth();
+        self.emulator.call(target)?;
+        let mut steps = 0;
+        while self.emulator.call_depth() > depth {
+            if steps == self.max_steps {
+                return Err(anyhow!(
+                    "`{}` did not return after {} instructions",
+                    label,
+                    steps
+                ));
+            }
+            let step = self.emulator.step()?;
+            if step.event == Some(Event::Halt) {
+                return Err(anyhow!(
+                    "`{}` halted at {}",
+                    label,
+                    self.describe(step.address)
+                ));
+            }
+            steps += 1;
+        }
+        self.steps = steps;
+        Ok(self)
+    }
+
+    pub fn registers(&self) -> Registers {
+        let cpu = &self.emulator.cpu;
+        Registers {
+            a: cpu.a.clone(),
+            b: cpu.b.clone(),
+            hli: cpu.hli(),
+            exit_code: cpu.exit_code,
+            flags: cpu.flags,
+        }
+    }
+
+    pub fn memory(&self, range: Range<u16>) -> &[u8] {
+        &self.emulator.memory()[range.start as usize..range.end as usize]
+    }
+
+    /// Memory starting at a label
+    pub fn memory_at(&self, label: &str, len: u16) -> Result<&[u8], Error> {
+        let start = self.address_of(label)? as usize;
+        let end = (start + len as usize).min(MEMORY_SIZE);
+        Ok(&self.emulator.memory()[start..end])
+    }
+
+    /// Instructions executed by the last call, including its `RET`
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn describe(&self, address: u16) -> String {
+        match self.assembly.symbolized(address) {
+            Some(symbol) => format!("0x{:04x} <{}>", address, symbol),
+            None => format!("0x{:04x}", address),
+        }
+    }
+}
@@ -1,42 +1,678 @@
-use std::{fs::OpenOptions, io::Write, path::Path};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
 
-use clap::Parser;
-use nox_asm::Assembler;
+use clap::{Parser, Subcommand};
+use nox_asm::{
+    archive::Archive,
+    coverage::Coverage,
+    debugger::Debugger,
+    emulator::{
+        devices::{Console, Timer},
+        Emulator, Event,
+    },
+    format,
+    gdb::GdbServer,
+    linker,
+    lint::LintConfig,
+    object::Object,
+    profile::Profiler,
+    testing,
+    trace::{TraceFilter, Tracer},
+    Assembler, Assembly, LinkerScript,
+};
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input file
-    #[arg(short)]
-    input_file: String,
+    #[arg(short, required = true)]
+    input_file: Option<String>,
 
     /// Output file
+    #[arg(short, required = true)]
+    output_file: Option<String>,
+
+    /// Linker script describing the memory layout (defaults to the built-in ROM/RAM/vectors layout)
     #[arg(short)]
-    output_file: String,
+    linker_script: Option<String>,
+
+    /// Write a listing with the addresses and bytes of every line to this file
+    #[arg(long)]
+    listing: Option<String>,
+
+    /// Output a relocatable object instead of a memory image
+    #[arg(short = 'c')]
+    object: bool,
 
     /// Verbose
     #[arg(short)]
     verbose: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Link relocatable objects into a memory image
+    Link {
+        /// Object files and library archives. Archive members are only linked if needed
+        #[arg(required = true)]
+        objects: Vec<String>,
+
+        /// Output file
+        #[arg(short)]
+        output_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        /// Verbose
+        #[arg(short)]
+        verbose: bool,
+    },
+    /// Bundle relocatable objects into a library archive
+    Archive {
+        /// Object files
+        #[arg(required = true)]
+        objects: Vec<String>,
+
+        /// Output file
+        #[arg(short)]
+        output_file: String,
+    },
+    /// Run a program in the emulator until HALT
+    Run {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        /// Write an execution trace to this file
+        #[arg(short, long)]
+        trace: Option<String>,
+
+        /// Only trace instructions in this address range, eg. `0x0100-0x01ff`
+        #[arg(long, value_parser = parse_address_range)]
+        trace_range: Vec<RangeInclusive<u16>>,
+
+        /// Only trace this routine and everything it calls
+        #[arg(long)]
+        trace_routine: Vec<String>,
+
+        /// Stop after this many instructions
+        #[arg(long, default_value_t = 100_000_000)]
+        max_steps: u64,
+
+        /// Write executed instructions per call stack to this file, as folded stacks for flame-graph tools
+        #[arg(long)]
+        profile: Option<String>,
+
+        #[command(flatten)]
+        coverage: CoverageArgs,
+
+        #[command(flatten)]
+        machine: MachineArgs,
+    },
+    /// Run the `.test` blocks of a program in the emulator
+    Test {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        /// Only run tests whose name contains this text
+        filter: Option<String>,
+
+        /// Fail a test after this many instructions
+        #[arg(long, default_value_t = testing::MAX_STEPS)]
+        max_steps: u64,
+
+        #[command(flatten)]
+        coverage: CoverageArgs,
+
+        #[command(flatten)]
+        machine: MachineArgs,
+    },
+    /// Check a program for suspicious code
+    Lint {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        /// File enabling or disabling rules and adding read-only ranges
+        #[arg(long)]
+        config: Option<String>,
+    },
+    /// Export the control-flow graph of each routine or the call graph as Graphviz DOT or JSON
+    Graph {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        /// Output file (defaults to stdout)
+        #[arg(short)]
+        output_file: Option<String>,
+
+        /// Only export these routines
+        #[arg(long)]
+        routine: Vec<String>,
+
+        /// Export the call graph instead of the control-flow graphs
+        #[arg(long)]
+        call_graph: bool,
+
+        /// Write JSON instead of DOT
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the worst-case stack usage of each entry point
+    Stack {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+    },
+    /// Format source files in place
+    Fmt {
+        /// Source files
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Only list the files that are not formatted, failing if there are any
+        #[arg(long)]
+        check: bool,
+    },
+    /// Run a program in the emulator with an interactive step debugger
+    Debug {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        #[command(flatten)]
+        machine: MachineArgs,
+    },
+    /// Run a program in the emulator and serve it to GDB over a local TCP port
+    Gdb {
+        /// Input file
+        #[arg(short)]
+        input_file: String,
+
+        /// Linker script describing the memory layout
+        #[arg(short)]
+        linker_script: Option<String>,
+
+        /// Port to listen on (127.0.0.1 only)
+        #[arg(short, default_value_t = 1234)]
+        port: u16,
+
+        /// Print the packets exchanged with GDB
+        #[arg(short)]
+        verbose: bool,
+
+        #[command(flatten)]
+        machine: MachineArgs,
+    },
+}
+
+/// Devices and memory protection of the emulated machine
+#[derive(clap::Args)]
+struct MachineArgs {
+    /// Make an address range read-only, eg. `0x0000-0x7fff`
+    #[arg(long, value_parser = parse_address_range)]
+    rom: Vec<RangeInclusive<u16>>,
+
+    /// Map a console (stdin/stdout) at this address, eg. `0xfe00`
+    #[arg(long, value_parser = parse_address)]
+    uart: Option<u16>,
+
+    /// Map a timer at this address
+    #[arg(long, value_parser = parse_address)]
+    timer: Option<u16>,
+}
+
+/// Where to write the coverage of the executed code
+#[derive(clap::Args)]
+struct CoverageArgs {
+    /// Write line and branch coverage in the lcov format to this file
+    #[arg(long)]
+    lcov: Option<String>,
+
+    /// Write the sources annotated with how many times each line was executed to this file
+    #[arg(long)]
+    coverage: Option<String>,
+}
+
+impl CoverageArgs {
+    fn write(&self, assembler: &Assembler, emulator: &Emulator) {
+        if self.lcov.is_none() && self.coverage.is_none() {
+            return;
+        }
+        let coverage = Coverage::new(assembler.statements(), emulator);
+        if let Some(path) = &self.lcov {
+            write_output(Path::new(path), coverage.to_lcov().as_bytes());
+        }
+        if let Some(path) = &self.coverage {
+            let annotated = coverage.annotate(assembler.source_lines());
+            write_output(Path::new(path), annotated.as_bytes());
+        }
+    }
+}
+
+impl MachineArgs {
+    /// `input` tells if the console can read stdin, which the debugger uses for its commands.
+    /// Exits if a device does not fit in memory at its address
+    fn emulator(&self, image: &[u8], input: bool) -> Emulator {
+        let mut emulator = Emulator::new(image);
+        for range in &self.rom {
+            emulator.protect(range.clone());
+        }
+        if let Some(address) = self.uart {
+            let range = device_range("--uart", address, Console::SIZE);
+            let console = if input {
+                Console::stdio()
+            } else {
+                Console::output_only(std::io::stdout())
+            };
+            emulator.map_device(range, console);
+        }
+        if let Some(address) = self.timer {
+            let range = device_range("--timer", address, Timer::SIZE);
+            emulator.map_device(range, Timer::default());
+        }
+        emulator
+    }
+}
+
+/// Addresses taken by a device of `size` bytes mapped at `address`
+fn device_range(option: &str, address: u16, size: u16) -> RangeInclusive<u16> {
+    match address.checked_add(size - 1) {
+        Some(end) => address..=end,
+        None => {
+            eprintln!(
+                "> {} 0x{:04x}: the device takes {} bytes and does not fit in memory",
+                option, address, size
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn routine_address(option: &str, assembly: &Assembly, name: &str) -> u16 {
+    match assembly.symbol(name) {
+        Some(symbol) => symbol.address,
+        None => {
+            eprintln!("> {} {}: the routine is not defined", option, name);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
     let args = Args::parse();
 
-    let input_path = Path::new(&args.input_file);
-    let output_path = Path::new(&args.output_file);
-    let verbose = args.verbose;
+    match args.command {
+        Some(Command::Link {
+            objects,
+            output_file,
+            linker_script,
+            verbose,
+        }) => {
+            let mut archives = vec![];
+            let mut objects: Vec<Object> = objects
+                .iter()
+                .filter_map(|path| {
+                    let bytes = std::fs::read(path).unwrap();
+                    if Archive::is_archive(&bytes) {
+                        archives.push(Archive::from_bytes(&bytes).unwrap());
+                        None
+                    } else {
+                        Some(Object::from_bytes(&bytes).unwrap())
+                    }
+                })
+                .collect();
+            Archive::resolve(&archives, &mut objects, verbose);
+            let linker_script = load_linker_script(linker_script.as_deref());
+
+            println!("> Linking {} objects...", objects.len());
+            let bytes = linker::link(&objects, &linker_script, verbose).unwrap();
+            write_output(Path::new(&output_file), &bytes);
+            println!("> Linked to {:?}", output_file);
+        }
+        Some(Command::Archive {
+            objects,
+            output_file,
+        }) => {
+            let archive = Archive {
+                members: objects
+                    .iter()
+                    .map(|path| Object::from_bytes(&std::fs::read(path).unwrap()).unwrap())
+                    .collect(),
+            };
+            write_output(Path::new(&output_file), &archive.to_bytes());
+            println!(
+                "> Archived {} objects to {:?}",
+                archive.members.len(),
+                output_file
+            );
+        }
+        Some(Command::Run {
+            input_file,
+            linker_script,
+            trace,
+            trace_range,
+            trace_routine,
+            max_steps,
+            profile,
+            coverage,
+            machine,
+        }) => {
+            let (assembler, assembly) =
+                assemble_for_emulator(&input_file, linker_script.as_deref());
+            let filter = TraceFilter {
+                ranges: trace_range,
+                routines: trace_routine
+                    .iter()
+                    .map(|name| routine_address("--trace-routine", &assembly, name))
+                    .collect(),
+            };
+            let mut tracer = trace.map(|path| {
+                let file = BufWriter::new(File::create(path).unwrap());
+                Tracer::new(&assembly, filter, file)
+            });
+            let mut emulator = machine.emulator(&assembly.bytes, true);
+            let mut profiler = profile.as_ref().map(|_| Profiler::new());
+
+            let mut steps = 0;
+            let result = loop {
+                if steps == max_steps {
+                    break Err(format!("stopped after {} instructions", steps));
+                }
+                let step = match &mut tracer {
+                    Some(tracer) => tracer.step(&mut emulator).unwrap(),
+                    None => emulator.step(),
+                };
+                steps += 1;
+                if let (Some(profiler), Ok(step)) = (&mut profiler, &step) {
+                    profiler.record(&emulator, step);
+                }
+                match step {
+                    Ok(step) if step.event == Some(Event::Halt) => break Ok(()),
+                    Ok(_) => (),
+                    Err(fault) => break Err(fault.to_string()),
+                }
+            };
+            coverage.write(&assembler, &emulator);
+            if let (Some(path), Some(profiler)) = (&profile, &profiler) {
+                write_output(Path::new(path), profiler.folded(&assembly).as_bytes());
+            }
+            match result {
+                Ok(()) => println!(
+                    "> Halted after {} instructions, exit code 0x{:02x}",
+                    steps, emulator.cpu.exit_code
+                ),
+                Err(e) => {
+                    eprintln!("> {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::Test {
+            input_file,
+            linker_script,
+            filter,
+            max_steps,
+            coverage,
+            machine,
+        }) => {
+            let linker_script = load_linker_script(linker_script.as_deref());
+            let mut assembler = Assembler::new(Path::new(&input_file))
+                .with_linker_script(linker_script)
+                .with_tests();
+            let assembly = assembler.build(false).unwrap();
+            for diagnostic in &assembly.diagnostics {
+                eprintln!("{}", diagnostic);
+            }
+            let mut emulator = machine.emulator(&assembly.bytes, false);
+            let passed = testing::run_tests(
+                &assembler.tests(),
+                filter.as_deref(),
+                &assembly,
+                &mut emulator,
+                max_steps,
+                &mut std::io::stdout(),
+            )
+            .unwrap();
+            coverage.write(&assembler, &emulator);
+            if !passed {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Lint {
+            input_file,
+            linker_script,
+            config,
+        }) => {
+            let config = match config {
+                Some(path) => std::fs::read_to_string(path)
+                    .unwrap()
+                    .parse::<LintConfig>()
+                    .unwrap(),
+                None => LintConfig::default(),
+            };
+            // Tests are linted too, and labels they use count as referenced
+            let linker_script = load_linker_script(linker_script.as_deref());
+            let mut assembler = Assembler::new(Path::new(&input_file))
+                .with_linker_script(linker_script)
+                .with_tests();
+            let mut warnings = assembler.build(false).unwrap().diagnostics;
+            warnings.extend(assembler.lint(&config));
+            for warning in &warnings {
+                eprintln!("{}", warning);
+            }
+            if !warnings.is_empty() {
+                eprintln!("> {} lint warnings", warnings.len());
+                std::process::exit(1);
+            }
+            println!("> No lint warnings");
+        }
+        Some(Command::Graph {
+            input_file,
+            linker_script,
+            output_file,
+            routine,
+            call_graph,
+            json,
+        }) => {
+            let (assembler, assembly) =
+                assemble_for_emulator(&input_file, linker_script.as_deref());
+            let graph = assembler.control_flow_graph();
+            let entries: Vec<u16> = routine
+                .iter()
+                .map(|name| routine_address("--routine", &assembly, name))
+                .collect();
+            let output = match (call_graph, json) {
+                (true, true) => format!("{:#}\n", graph.call_graph_to_json()),
+                (true, false) => graph.call_graph_to_dot(),
+                (false, true) => format!("{:#}\n", graph.cfg_to_json(&assembly, &entries)),
+                (false, false) => graph.cfg_to_dot(&assembly, &entries),
+            };
+            match output_file {
+                Some(path) => write_output(Path::new(&path), output.as_bytes()),
+                None => print!("{}", output),
+            }
+        }
+        Some(Command::Stack {
+            input_file,
+            linker_script,
+        }) => {
+            let (assembler, _) = assemble_for_emulator(&input_file, linker_script.as_deref());
+            let graph = assembler.control_flow_graph();
+            let analysis = assembler.stack_analysis(&graph);
+            let entries = analysis.entry_points();
+            let width = entries
+                .iter()
+                .map(|r| r.name.len())
+                .max()
+                .unwrap_or_default();
+            for routine in entries {
+                let usage = analysis.usage(routine.entry).unwrap();
+                let chain: Vec<&str> = usage
+                    .deepest
+                    .iter()
+                    .filter_map(|entry| graph.routine(*entry).map(|r| r.name.as_str()))
+                    .collect();
+                let usage = format!("{:>10}", usage.to_string());
+                if chain.is_empty() {
+                    println!("{:width$}  {}", routine.name, usage);
+                } else {
+                    println!(
+                        "{:width$}  {}  via {}",
+                        routine.name,
+                        usage,
+                        chain.join(" -> ")
+                    );
+                }
+            }
+        }
+        Some(Command::Fmt { files, check }) => {
+            let mut unformatted = 0;
+            for file in &files {
+                let source = std::fs::read_to_string(file).unwrap();
+                let formatted = format::format(&source);
+                if formatted == source {
+                    continue;
+                }
+                unformatted += 1;
+                if check {
+                    println!("> {:?} is not formatted", file);
+                } else {
+                    write_output(Path::new(file), formatted.as_bytes());
+                    println!("> Formatted {:?}", file);
+                }
+            }
+            if check && unformatted > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Debug {
+            input_file,
+            linker_script,
+            machine,
+        }) => {
+            let (_, assembly) = assemble_for_emulator(&input_file, linker_script.as_deref());
+            let emulator = machine.emulator(&assembly.bytes, false);
+            let mut debugger = Debugger::with_emulator(assembly, emulator);
+            debugger
+                .run(std::io::stdin().lock(), &mut std::io::stdout())
+                .unwrap();
+        }
+        Some(Command::Gdb {
+            input_file,
+            linker_script,
+            port,
+            verbose,
+            machine,
+        }) => {
+            let (_, assembly) = assemble_for_emulator(&input_file, linker_script.as_deref());
+            GdbServer::new(machine.emulator(&assembly.bytes, true), verbose)
+                .listen(port)
+                .unwrap();
+        }
+        None => {
+            let input_path = Path::new(args.input_file.as_deref().unwrap());
+            let output_path = Path::new(args.output_file.as_deref().unwrap());
+            let verbose = args.verbose;
+
+            let linker_script = load_linker_script(args.linker_script.as_deref());
+            let mut assembler = Assembler::new(input_path).with_linker_script(linker_script);
+
+            println!("> Assembling {:?}...", input_path);
+            let bytes = if args.object {
+                assembler.assemble_object(verbose).unwrap().to_bytes()
+            } else {
+                let assembly = assembler.build(verbose).unwrap();
+                for diagnostic in &assembly.diagnostics {
+                    eprintln!("{}", diagnostic);
+                }
+                if let Some(listing) = &args.listing {
+                    write_output(Path::new(listing), assembler.listing().as_bytes());
+                }
+                assembly.bytes
+            };
 
-    let mut assembler = Assembler::new(input_path);
+            write_output(output_path, &bytes);
+            println!("> {:?} assembled to {:?}", input_path, output_path);
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<u16, String> {
+    u16::from_str_radix(address.trim().trim_start_matches("0x"), 16)
+        .map_err(|_| format!("`{}` is not a hex address", address))
+}
+
+fn parse_address_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or("expected a range like 0x0100-0x01ff")?;
+    Ok(parse_address(start)?..=parse_address(end)?)
+}
+
+fn assemble_for_emulator(input_file: &str, linker_script: Option<&str>) -> (Assembler, Assembly) {
+    let linker_script = load_linker_script(linker_script);
+    let mut assembler = Assembler::new(Path::new(input_file)).with_linker_script(linker_script);
+    let assembly = assembler.build(false).unwrap();
+    for diagnostic in &assembly.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    (assembler, assembly)
+}
 
-    println!("> Assembling {:?}...", input_path);
-    let bytes = assembler.assemble(verbose).unwrap();
+fn load_linker_script(path: Option<&str>) -> LinkerScript {
+    match path {
+        Some(path) => std::fs::read_to_string(path)
+            .unwrap()
+            .parse::<LinkerScript>()
+            .unwrap(),
+        None => LinkerScript::default(),
+    }
+}
 
+fn write_output(output_path: &Path, bytes: &[u8]) {
     let mut file = OpenOptions::new()
         .write(true)
         .append(false)
         .create(true)
+        .truncate(true)
         .open(output_path)
         .unwrap();
 
-    file.write_all(&bytes).unwrap();
-    println!("> {:?} assembled to {:?}", input_path, output_path);
+    file.write_all(bytes).unwrap();
 }
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Error};
+
+const MAGIC: &[u8; 4] = b"NOXO";
+const VERSION: u8 = 1;
+
+/// Relocatable output of assembling a single source file.
+/// Addresses inside an object are relative to the start of the section they are in,
+/// the final ones are only known after linking.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Object {
+    pub source: String,
+    pub sections: Vec<ObjectSection>,
+    pub symbols: Vec<Symbol>,
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Single fragment of a section, `origin` is set if it was placed with `>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectSection {
+    pub name: String,
+    pub origin: Option<u16>,
+    pub size: u16,
+    pub noload: bool, // only reserves space, like sections marked NOLOAD in the linker script
+    pub data: Vec<u8>, // empty for NOLOAD sections
+}
+
+/// Label defined in the object. Only exported symbols are visible to other objects
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: usize,
+    pub offset: u16,
+    pub exported: bool,
+}
+
+/// 16 bit address of `symbol` that has to be written at `offset` of `section` by the linker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub section: usize,
+    pub offset: u16,
+    pub symbol: String,
+}
+
+impl Object {
+    pub fn exports(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter().filter(|s| s.exported)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// Serializes the object. All numbers are big endian, same as in the memory image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        write_string(&mut bytes, &self.source);
+
+        write_u16(&mut bytes, self.sections.len() as u16);
+        for section in &self.sections {
+            write_string(&mut bytes, &section.name);
+            bytes.push(section.origin.is_some() as u8 | ((section.noload as u8) << 1));
+            write_u16(&mut bytes, section.origin.unwrap_or_default());
+            write_u16(&mut bytes, section.size);
+            if !section.noload {
+                bytes.extend_from_slice(&section.data);
+            }
+        }
+
+        write_u16(&mut bytes, self.symbols.len() as u16);
+        for symbol in &self.symbols {
+            write_string(&mut bytes, &symbol.name);
+            write_u16(&mut bytes, symbol.section as u16);
+            write_u16(&mut bytes, symbol.offset);
+            bytes.push(symbol.exported as u8);
+        }
+
+        write_u16(&mut bytes, self.imports.len() as u16);
+        for import in &self.imports {
+            write_string(&mut bytes, import);
+        }
+
+        write_u16(&mut bytes, self.relocations.len() as u16);
+        for relocation in &self.relocations {
+            write_u16(&mut bytes, relocation.section as u16);
+            write_u16(&mut bytes, relocation.offset);
+            write_string(&mut bytes, &relocation.symbol);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(anyhow!("not a Nox object file"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(anyhow!("unsupported object file version {}", version));
+        }
+        let mut object = Object {
+            source: reader.string()?,
+            ..Default::default()
+        };
+
+        for _ in 0..reader.u16()? {
+            let name = reader.string()?;
+            let flags = reader.u8()?;
+            let origin = reader.u16()?;
+            let size = reader.u16()?;
+            let noload = flags & 0b10 != 0;
+            let data = if noload {
+                vec![]
+            } else {
+                reader.take(size as usize)?.to_vec()
+            };
+            object.sections.push(ObjectSection {
+                name,
+                origin: (flags & 0b01 != 0).then_some(origin),
+                size,
+                noload,
+                data,
+            });
+        }
+
+        for _ in 0..reader.u16()? {
+            let symbol = Symbol {
+                name: reader.string()?,
+                section: reader.u16()? as usize,
+                offset: reader.u16()?,
+                exported: reader.u8()? != 0,
+            };
+            if symbol.section >= object.sections.len() {
+                return Err(anyhow!("symbol {} is in a missing section", symbol.name));
+            }
+            object.symbols.push(symbol);
+        }
+
+        for _ in 0..reader.u16()? {
+            object.imports.push(reader.string()?);
+        }
+
+        for _ in 0..reader.u16()? {
+            let relocation = Relocation {
+                section: reader.u16()? as usize,
+                offset: reader.u16()?,
+                symbol: reader.string()?,
+            };
+            if relocation.section >= object.sections.len() {
+                return Err(anyhow!(
+                    "relocation of {} is in a missing section",
+                    relocation.symbol
+                ));
+            }
+            object.relocations.push(relocation);
+        }
+
+        if reader.position != bytes.len() {
+            return Err(anyhow!("trailing data after the object"));
+        }
+        Ok(object)
+    }
+}
+
+fn write_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    write_u16(bytes, value.len() as u16);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let slice = self
+            .bytes
+            .get(self.position..self.position + n)
+            .ok_or_else(|| anyhow!("unexpected end of object file"))?;
+        self.position += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| anyhow!(e))
+    }
+}
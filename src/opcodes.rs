@@ -1,10 +1,10 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(clippy::upper_case_acronyms)]
 #[allow(non_camel_case_types)]
 #[allow(unused)]
 /// @brief All opcodes follow the convention: OPERATION SOURCE TARGET
 /// Example: PUSH_A_B = PUSH value from A to B
-pub(crate) enum Opcode
+pub enum Opcode
 // IM = immediate byte value following the opcode
 {
     NOOP,
@@ -201,3 +201,444 @@ pub(crate) enum Opcode
 
     HALT, // halt execution
 }
+
+/// Operand encoded in the bytes following the opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Immediate8,
+    Immediate16,
+    Address, // 16 bit address, written as `&0x1234` or a label
+}
+
+impl OperandKind {
+    pub fn size(&self) -> u16 {
+        match self {
+            OperandKind::Immediate8 => 1,
+            OperandKind::Immediate16 | OperandKind::Address => 2,
+        }
+    }
+}
+
+impl Opcode {
+    /// All opcodes, indexed by their encoded value
+    pub const ALL: [Opcode; 142] = [
+        Opcode::NOOP,
+        Opcode::PUSH_IMMEDIATE_A,
+        Opcode::PUSH_IMMEDIATE_B,
+        Opcode::PUSH_ABSOLUTE_A,
+        Opcode::PUSH_ABSOLUTE_B,
+        Opcode::PUSH_INDIRECT_A,
+        Opcode::PUSH_INDIRECT_B,
+        Opcode::PUSH_A_B,
+        Opcode::PUSH_B_A,
+        Opcode::PUSH_HI_A,
+        Opcode::PUSH_LI_A,
+        Opcode::PUSH_HI_B,
+        Opcode::PUSH_LI_B,
+        Opcode::PUSH_EXIT_CODE_A,
+        Opcode::PUSH_EXIT_CODE_B,
+        Opcode::POP_A,
+        Opcode::POP_B,
+        Opcode::POP_A_ABSOLUTE,
+        Opcode::POP_B_ABSOLUTE,
+        Opcode::POP_A_INDIRECT,
+        Opcode::POP_B_INDIRECT,
+        Opcode::POP_A_B,
+        Opcode::POP_B_A,
+        Opcode::POP_A_HI,
+        Opcode::POP_A_LI,
+        Opcode::POP_B_HI,
+        Opcode::POP_B_LI,
+        Opcode::PEEK_A_ABSOLUTE,
+        Opcode::PEEK_B_ABSOLUTE,
+        Opcode::PEEK_A_INDIRECT,
+        Opcode::PEEK_B_INDIRECT,
+        Opcode::ADD_A_B,
+        Opcode::ADD_B_A,
+        Opcode::ADD_IMMEDIATE_A,
+        Opcode::ADD_IMMEDIATE_B,
+        Opcode::ADD_ABSOLUTE_A,
+        Opcode::ADD_ABSOLUTE_B,
+        Opcode::SUB_A_B,
+        Opcode::SUB_B_A,
+        Opcode::SUB_IMMEDIATE_A,
+        Opcode::SUB_IMMEDIATE_B,
+        Opcode::SUB_ABSOLUTE_A,
+        Opcode::SUB_ABSOLUTE_B,
+        Opcode::SHIFT_LEFT_A,
+        Opcode::SHIFT_RIGHT_A,
+        Opcode::SHIFT_LEFT_B,
+        Opcode::SHIFT_RIGHT_B,
+        Opcode::AND_A_B,
+        Opcode::AND_B_A,
+        Opcode::OR_A_B,
+        Opcode::OR_B_A,
+        Opcode::XOR_A_B,
+        Opcode::XOR_B_A,
+        Opcode::NOT_A,
+        Opcode::NOT_B,
+        Opcode::CMP_A_B,
+        Opcode::CMP_IMMEDIATE_A,
+        Opcode::CMP_IMMEDIATE_B,
+        Opcode::CMP_ABSOLUTE_A,
+        Opcode::CMP_ABSOLUTE_B,
+        Opcode::PUSH_IMMEDIATE_HI,
+        Opcode::PUSH_IMMEDIATE_LI,
+        Opcode::PUSH_ABSOLUTE_HI,
+        Opcode::PUSH_ABSOLUTE_LI,
+        Opcode::STORE_HI_ABSOLUTE,
+        Opcode::STORE_LI_ABSOLUTE,
+        Opcode::CMP_IMMEDIATE_HI,
+        Opcode::CMP_IMMEDIATE_LI,
+        Opcode::CMP_ABSOLUTE_HI,
+        Opcode::CMP_ABSOLUTE_LI,
+        Opcode::INC_HI,
+        Opcode::INC_LI,
+        Opcode::DEC_HI,
+        Opcode::DEC_LI,
+        Opcode::ZERO_HI,
+        Opcode::ZERO_LI,
+        Opcode::SWAP_HI_LI,
+        Opcode::PUSH_IMMEDIATE_AB,
+        Opcode::PUSH_ABSOLUTE_AB,
+        Opcode::PUSH_INDIRECT_AB,
+        Opcode::PUSH_HLI_AB,
+        Opcode::POP_AB_ABSOLUTE,
+        Opcode::POP_AB_INDIRECT,
+        Opcode::POP_AB_HLI,
+        Opcode::PEEK_AB_ABSOLUTE,
+        Opcode::PEEK_AB_INDIRECT,
+        Opcode::ADD_IMMEDIATE_AB,
+        Opcode::ADD_ABSOLUTE_AB,
+        Opcode::SUB_IMMEDIATE_AB,
+        Opcode::SUB_ABSOLUTE_AB,
+        Opcode::SHIFT_LEFT_AB,
+        Opcode::SHIFT_RIGHT_AB,
+        Opcode::AND_IMMEDIATE_AB,
+        Opcode::AND_ABSOLUTE_AB,
+        Opcode::OR_IMMEDIATE_AB,
+        Opcode::OR_ABSOLUTE_AB,
+        Opcode::XOR_IMMEDIATE_AB,
+        Opcode::XOR_ABSOLUTE_AB,
+        Opcode::NOT_AB,
+        Opcode::PUSH_IMMEDIATE_HLI,
+        Opcode::PUSH_ABSOLUTE_HLI,
+        Opcode::STORE_HLI_ABSOLUTE,
+        Opcode::CMP_IMMEDIATE_AB,
+        Opcode::CMP_ABSOLUTE_AB,
+        Opcode::CMP_IMMEDIATE_HLI,
+        Opcode::CMP_ABSOLUTE_HLI,
+        Opcode::INC_HLI,
+        Opcode::DEC_HLI,
+        Opcode::ZERO_HLI,
+        Opcode::JUMP_IF_ZERO,
+        Opcode::JUMP_IF_OVERFLOW,
+        Opcode::JUMP_IF_ERROR,
+        Opcode::JUMP_IF_OK,
+        Opcode::JUMP,
+        Opcode::PUSH_AB_STACK_ADDRESS,
+        Opcode::PUSH_AB_STACK_SIZE,
+        Opcode::POP_STACK_ADDRESS_AB,
+        Opcode::POP_STACK_SIZE_AB,
+        Opcode::POP_AB_IRQ,
+        Opcode::PUSH_A_STACK,
+        Opcode::POP_A_STACK,
+        Opcode::POP_STACK_A,
+        Opcode::PUSH_B_STACK,
+        Opcode::POP_B_STACK,
+        Opcode::POP_STACK_B,
+        Opcode::PUSH_HI_STACK,
+        Opcode::POP_STACK_HI,
+        Opcode::PUSH_LI_STACK,
+        Opcode::POP_STACK_LI,
+        Opcode::CALL,
+        Opcode::RETURN_OK,
+        Opcode::RETURN_OK_EXIT_CODE,
+        Opcode::RETURN_ERR,
+        Opcode::RETURN_ERR_EXIT_CODE,
+        Opcode::SET_ERR,
+        Opcode::SET_IRQ,
+        Opcode::CLEAR_EXIT_CODE,
+        Opcode::CLEAR_ERR,
+        Opcode::CLEAR_IRQ,
+        Opcode::CLEAR_OVF,
+        Opcode::CLEAR_ZERO,
+        Opcode::HALT,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    /// Assembly syntax of the instruction, `{}` stands for the operand
+    pub fn syntax(&self) -> &'static str {
+        self.info().0
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.syntax().split(' ').next().unwrap_or_default()
+    }
+
+    pub fn operand(&self) -> Option<OperandKind> {
+        self.info().1
+    }
+
+    /// Size of the whole instruction in bytes
+    pub fn size(&self) -> u16 {
+        1 + self.operand().map(|o| o.size()).unwrap_or_default()
+    }
+
+    /// `JZE`, `JOF`, `JER` or `JOK`
+    pub fn is_conditional_jump(&self) -> bool {
+        matches!(
+            self,
+            Opcode::JUMP_IF_ZERO
+                | Opcode::JUMP_IF_OVERFLOW
+                | Opcode::JUMP_IF_ERROR
+                | Opcode::JUMP_IF_OK
+        )
+    }
+
+    /// `RET` with or without an exit code
+    pub fn is_return(&self) -> bool {
+        matches!(
+            self,
+            Opcode::RETURN_OK
+                | Opcode::RETURN_OK_EXIT_CODE
+                | Opcode::RETURN_ERR
+                | Opcode::RETURN_ERR_EXIT_CODE
+        )
+    }
+
+    fn info(&self) -> (&'static str, Option<OperandKind>) {
+        match self {
+            Opcode::NOOP => ("NOOP", None),
+            Opcode::PUSH_IMMEDIATE_A => ("PUSH {} A", Some(OperandKind::Immediate8)),
+            Opcode::PUSH_IMMEDIATE_B => ("PUSH {} B", Some(OperandKind::Immediate8)),
+            Opcode::PUSH_ABSOLUTE_A => ("PUSH {} A", Some(OperandKind::Address)),
+            Opcode::PUSH_ABSOLUTE_B => ("PUSH {} B", Some(OperandKind::Address)),
+            Opcode::PUSH_INDIRECT_A => ("PUSH &HLI A", None),
+            Opcode::PUSH_INDIRECT_B => ("PUSH &HLI B", None),
+            Opcode::PUSH_A_B => ("PUSH A B", None),
+            Opcode::PUSH_B_A => ("PUSH B A", None),
+            Opcode::PUSH_HI_A => ("PUSH HI A", None),
+            Opcode::PUSH_LI_A => ("PUSH LI A", None),
+            Opcode::PUSH_HI_B => ("PUSH HI B", None),
+            Opcode::PUSH_LI_B => ("PUSH LI B", None),
+            Opcode::PUSH_EXIT_CODE_A => ("PUSH EX A", None),
+            Opcode::PUSH_EXIT_CODE_B => ("PUSH EX B", None),
+            Opcode::POP_A => ("POP A", None),
+            Opcode::POP_B => ("POP B", None),
+            Opcode::POP_A_ABSOLUTE => ("POP A {}", Some(OperandKind::Address)),
+            Opcode::POP_B_ABSOLUTE => ("POP B {}", Some(OperandKind::Address)),
+            Opcode::POP_A_INDIRECT => ("POP A &HLI", None),
+            Opcode::POP_B_INDIRECT => ("POP B &HLI", None),
+            Opcode::POP_A_B => ("POP A B", None),
+            Opcode::POP_B_A => ("POP B A", None),
+            Opcode::POP_A_HI => ("POP A HI", None),
+            Opcode::POP_A_LI => ("POP A LI", None),
+            Opcode::POP_B_HI => ("POP B HI", None),
+            Opcode::POP_B_LI => ("POP B LI", None),
+            Opcode::PEEK_A_ABSOLUTE => ("PEEK A {}", Some(OperandKind::Address)),
+            Opcode::PEEK_B_ABSOLUTE => ("PEEK B {}", Some(OperandKind::Address)),
+            Opcode::PEEK_A_INDIRECT => ("PEEK A &HLI", None),
+            Opcode::PEEK_B_INDIRECT => ("PEEK B &HLI", None),
+            Opcode::ADD_A_B => ("ADD A B", None),
+            Opcode::ADD_B_A => ("ADD B A", None),
+            Opcode::ADD_IMMEDIATE_A => ("ADD {} A", Some(OperandKind::Immediate8)),
+            Opcode::ADD_IMMEDIATE_B => ("ADD {} B", Some(OperandKind::Immediate8)),
+            Opcode::ADD_ABSOLUTE_A => ("ADD {} A", Some(OperandKind::Address)),
+            Opcode::ADD_ABSOLUTE_B => ("ADD {} B", Some(OperandKind::Address)),
+            Opcode::SUB_A_B => ("SUB A B", None),
+            Opcode::SUB_B_A => ("SUB B A", None),
+            Opcode::SUB_IMMEDIATE_A => ("SUB {} A", Some(OperandKind::Immediate8)),
+            Opcode::SUB_IMMEDIATE_B => ("SUB {} B", Some(OperandKind::Immediate8)),
+            Opcode::SUB_ABSOLUTE_A => ("SUB {} A", Some(OperandKind::Address)),
+            Opcode::SUB_ABSOLUTE_B => ("SUB {} B", Some(OperandKind::Address)),
+            Opcode::SHIFT_LEFT_A => ("SHL A", None),
+            Opcode::SHIFT_RIGHT_A => ("SHR A", None),
+            Opcode::SHIFT_LEFT_B => ("SHL B", None),
+            Opcode::SHIFT_RIGHT_B => ("SHR B", None),
+            Opcode::AND_A_B => ("AND A B", None),
+            Opcode::AND_B_A => ("AND B A", None),
+            Opcode::OR_A_B => ("OR A B", None),
+            Opcode::OR_B_A => ("OR B A", None),
+            Opcode::XOR_A_B => ("XOR A B", None),
+            Opcode::XOR_B_A => ("XOR B A", None),
+            Opcode::NOT_A => ("NOT A", None),
+            Opcode::NOT_B => ("NOT B", None),
+            Opcode::CMP_A_B => ("CMP A B", None),
+            Opcode::CMP_IMMEDIATE_A => ("CMP {} A", Some(OperandKind::Immediate8)),
+            Opcode::CMP_IMMEDIATE_B => ("CMP {} B", Some(OperandKind::Immediate8)),
+            Opcode::CMP_ABSOLUTE_A => ("CMP {} A", Some(OperandKind::Address)),
+            Opcode::CMP_ABSOLUTE_B => ("CMP {} B", Some(OperandKind::Address)),
+            Opcode::PUSH_IMMEDIATE_HI => ("PUSH {} HI", Some(OperandKind::Immediate8)),
+            Opcode::PUSH_IMMEDIATE_LI => ("PUSH {} LI", Some(OperandKind::Immediate8)),
+            Opcode::PUSH_ABSOLUTE_HI => ("PUSH {} HI", Some(OperandKind::Address)),
+            Opcode::PUSH_ABSOLUTE_LI => ("PUSH {} LI", Some(OperandKind::Address)),
+            Opcode::STORE_HI_ABSOLUTE => ("STO HI {}", Some(OperandKind::Address)),
+            Opcode::STORE_LI_ABSOLUTE => ("STO LI {}", Some(OperandKind::Address)),
+            Opcode::CMP_IMMEDIATE_HI => ("CMP {} HI", Some(OperandKind::Immediate8)),
+            Opcode::CMP_IMMEDIATE_LI => ("CMP {} LI", Some(OperandKind::Immediate8)),
+            Opcode::CMP_ABSOLUTE_HI => ("CMP {} HI", Some(OperandKind::Address)),
+            Opcode::CMP_ABSOLUTE_LI => ("CMP {} LI", Some(OperandKind::Address)),
+            Opcode::INC_HI => ("INC HI", None),
+            Opcode::INC_LI => ("INC LI", None),
+            Opcode::DEC_HI => ("DEC HI", None),
+            Opcode::DEC_LI => ("DEC LI", None),
+            Opcode::ZERO_HI => ("ZERO HI", None),
+            Opcode::ZERO_LI => ("ZERO LI", None),
+            Opcode::SWAP_HI_LI => ("SWP HI LI", None),
+            Opcode::PUSH_IMMEDIATE_AB => ("PUSH {} AB", Some(OperandKind::Immediate16)),
+            Opcode::PUSH_ABSOLUTE_AB => ("PUSH {} AB", Some(OperandKind::Address)),
+            Opcode::PUSH_INDIRECT_AB => ("PUSH &HLI AB", None),
+            Opcode::PUSH_HLI_AB => ("PUSH HLI AB", None),
+            Opcode::POP_AB_ABSOLUTE => ("POP AB {}", Some(OperandKind::Address)),
+            Opcode::POP_AB_INDIRECT => ("POP AB &HLI", None),
+            Opcode::POP_AB_HLI => ("POP AB HLI", None),
+            Opcode::PEEK_AB_ABSOLUTE => ("PEEK AB {}", Some(OperandKind::Address)),
+            Opcode::PEEK_AB_INDIRECT => ("PEEK AB &HLI", None),
+            Opcode::ADD_IMMEDIATE_AB => ("ADD {} AB", Some(OperandKind::Immediate16)),
+            Opcode::ADD_ABSOLUTE_AB => ("ADD {} AB", Some(OperandKind::Address)),
+            Opcode::SUB_IMMEDIATE_AB => ("SUB {} AB", Some(OperandKind::Immediate16)),
+            Opcode::SUB_ABSOLUTE_AB => ("SUB {} AB", Some(OperandKind::Address)),
+            Opcode::SHIFT_LEFT_AB => ("SHL AB", None),
+            Opcode::SHIFT_RIGHT_AB => ("SHR AB", None),
+            Opcode::AND_IMMEDIATE_AB => ("AND {} AB", Some(OperandKind::Immediate16)),
+            Opcode::AND_ABSOLUTE_AB => ("AND {} AB", Some(OperandKind::Address)),
+            Opcode::OR_IMMEDIATE_AB => ("OR {} AB", Some(OperandKind::Immediate16)),
+            Opcode::OR_ABSOLUTE_AB => ("OR {} AB", Some(OperandKind::Address)),
+            Opcode::XOR_IMMEDIATE_AB => ("XOR {} AB", Some(OperandKind::Immediate16)),
+            Opcode::XOR_ABSOLUTE_AB => ("XOR {} AB", Some(OperandKind::Address)),
+            Opcode::NOT_AB => ("NOT AB", None),
+            Opcode::PUSH_IMMEDIATE_HLI => ("PUSH {} HLI", Some(OperandKind::Immediate16)),
+            Opcode::PUSH_ABSOLUTE_HLI => ("PUSH {} HLI", Some(OperandKind::Address)),
+            Opcode::STORE_HLI_ABSOLUTE => ("STO HLI {}", Some(OperandKind::Address)),
+            Opcode::CMP_IMMEDIATE_AB => ("CMP {} AB", Some(OperandKind::Immediate16)),
+            Opcode::CMP_ABSOLUTE_AB => ("CMP {} AB", Some(OperandKind::Address)),
+            Opcode::CMP_IMMEDIATE_HLI => ("CMP {} HLI", Some(OperandKind::Immediate16)),
+            Opcode::CMP_ABSOLUTE_HLI => ("CMP {} HLI", Some(OperandKind::Address)),
+            Opcode::INC_HLI => ("INC HLI", None),
+            Opcode::DEC_HLI => ("DEC HLI", None),
+            Opcode::ZERO_HLI => ("ZERO HLI", None),
+            Opcode::JUMP_IF_ZERO => ("JZE {}", Some(OperandKind::Address)),
+            Opcode::JUMP_IF_OVERFLOW => ("JOF {}", Some(OperandKind::Address)),
+            Opcode::JUMP_IF_ERROR => ("JER {}", Some(OperandKind::Address)),
+            Opcode::JUMP_IF_OK => ("JOK {}", Some(OperandKind::Address)),
+            Opcode::JUMP => ("JMP {}", Some(OperandKind::Address)),
+            Opcode::PUSH_AB_STACK_ADDRESS => ("PUSH SA AB", None),
+            Opcode::PUSH_AB_STACK_SIZE => ("PUSH SS AB", None),
+            Opcode::POP_STACK_ADDRESS_AB => ("POP AB SA", None),
+            Opcode::POP_STACK_SIZE_AB => ("POP AB SS", None),
+            Opcode::POP_AB_IRQ => ("POP AB IRA", None),
+            Opcode::PUSH_A_STACK => ("PUSH A S {}", Some(OperandKind::Immediate8)),
+            Opcode::POP_A_STACK => ("POP A S {}", Some(OperandKind::Immediate8)),
+            Opcode::POP_STACK_A => ("POP S A {}", Some(OperandKind::Immediate8)),
+            Opcode::PUSH_B_STACK => ("PUSH B S {}", Some(OperandKind::Immediate8)),
+            Opcode::POP_B_STACK => ("POP B S {}", Some(OperandKind::Immediate8)),
+            Opcode::POP_STACK_B => ("POP S B {}", Some(OperandKind::Immediate8)),
+            Opcode::PUSH_HI_STACK => ("PUSH HI S", None),
+            Opcode::POP_STACK_HI => ("POP S HI", None),
+            Opcode::PUSH_LI_STACK => ("PUSH LI S", None),
+            Opcode::POP_STACK_LI => ("POP S LI", None),
+            Opcode::CALL => ("CALL {}", Some(OperandKind::Address)),
+            Opcode::RETURN_OK => ("RET OK", None),
+            Opcode::RETURN_OK_EXIT_CODE => ("RET OK {}", Some(OperandKind::Immediate8)),
+            Opcode::RETURN_ERR => ("RET ERR", None),
+            Opcode::RETURN_ERR_EXIT_CODE => ("RET ERR {}", Some(OperandKind::Immediate8)),
+            Opcode::SET_ERR => ("SET ERR", None),
+            Opcode::SET_IRQ => ("SET IRQ", None),
+            Opcode::CLEAR_EXIT_CODE => ("CLR EX", None),
+            Opcode::CLEAR_ERR => ("CLR ERR", None),
+            Opcode::CLEAR_IRQ => ("CLR IRQ", None),
+            Opcode::CLEAR_OVF => ("CLR OVF", None),
+            Opcode::CLEAR_ZERO => ("CLR ZER", None),
+            Opcode::HALT => ("HALT", None),
+        }
+    }
+}
+
+/// Mnemonics of the instructions
+pub const MNEMONICS: [&str; 28] = [
+    "NOOP", "PUSH", "POP", "PEEK", "STO", "ADD", "SUB", "SHL", "SHR", "AND", "OR", "XOR", "NOT",
+    "CMP", "INC", "DEC", "ZERO", "SWP", "JZE", "JOF", "JER", "JOK", "JMP", "CALL", "RET", "SET",
+    "CLR", "HALT",
+];
+
+/// Mnemonics of the pseudo-instructions, expanded to instructions while parsing
+pub const PSEUDO_MNEMONICS: [&str; 5] = ["MOV16", "LOADI", "JNZ", "CALL_IF_ZERO", "NEG"];
+
+/// Registers that can be operands, `&HLI` is the memory HLI points to
+pub const REGISTERS: [&str; 11] = [
+    "A", "B", "AB", "HI", "LI", "HLI", "EX", "IRA", "S", "SA", "SS",
+];
+
+/// Flags that can be operands, `ZERO` is also accepted for ZER
+pub const FLAGS: [&str; 5] = ["ZER", "OVF", "ERR", "OK", "IRQ"];
+
+/// What the instructions with `mnemonic` do and which flags they change
+pub fn mnemonic_description(mnemonic: &str) -> Option<&'static str> {
+    Some(match mnemonic.to_uppercase().as_str() {
+        "NOOP" => "Does nothing.",
+        "PUSH" => "Copies a value to the target register. A and B are stacks, the value is pushed on top. `PUSH A S n` copies n values of A to the memory stack. Flags are not changed.",
+        "POP" => "Moves the last value of a register out, to another register or memory, or just drops it. `POP S A n` moves n bytes of the memory stack to A. Flags are not changed.",
+        "PEEK" => "Copies the last value of A, B or AB to memory without removing it. Flags are not changed.",
+        "STO" => "Stores HI, LI or HLI to memory. Flags are not changed.",
+        "ADD" => "Adds the values and pushes the result to the target register. Sets ZERO if the result is 0 and OVF on carry.",
+        "SUB" => "Subtracts the values and pushes the result to the target register. Sets ZERO if the result is 0 and OVF on borrow.",
+        "SHL" => "Shifts the last value left. Sets ZERO if the result is 0 and OVF to the bit shifted out.",
+        "SHR" => "Shifts the last value right. Sets ZERO if the result is 0 and OVF to the bit shifted out.",
+        "AND" | "OR" | "XOR" => "Bitwise operation on the last values, the result is pushed to the target register. Sets ZERO if the result is 0, clears OVF.",
+        "NOT" => "Inverts the bits of the last value. Sets ZERO if the result is 0, clears OVF.",
+        "CMP" => "Compares a register with a value without changing the registers. Sets ZERO if they are equal and OVF if the register is greater: `CMP 0x03 A` if A > 3, `CMP A B` if A > B.",
+        "INC" => "Increments HI, LI or HLI. Sets ZERO and OVF when it wraps around to 0.",
+        "DEC" => "Decrements HI, LI or HLI. Sets ZERO if the result is 0 and OVF when it wraps around.",
+        "ZERO" => "Sets HI, LI or HLI to 0. Flags are not changed.",
+        "SWP" => "Swaps HI and LI. Flags are not changed.",
+        "JZE" => "Jumps if ZERO is set.",
+        "JOF" => "Jumps if OVF is set.",
+        "JER" => "Jumps if ERR is set.",
+        "JOK" => "Jumps if OK is set.",
+        "JMP" => "Jumps unconditionally.",
+        "CALL" => "Pushes flags, HI, LI, the return address, stack size and stack address to the memory stack and jumps. The callee gets the rest of the stack as its frame.",
+        "RET" => "Returns from a `CALL`, restoring the caller's registers and stack. Sets OK or ERR and the exit code (0 if not given).",
+        "SET" => "Sets ERR, or IRQ to enable interrupts.",
+        "CLR" => "Clears a flag or the exit code. `CLR IRQ` masks interrupts.",
+        "HALT" => "Stops the CPU.",
+        "MOV16" => "Pseudo-instruction: copies a 16 bit value, address or HLI to HLI or memory. `PUSH x HLI` or `STO HLI x` (3 bytes), or `PUSH x AB` and `POP AB y` between values and memory (6 bytes).",
+        "LOADI" => "Pseudo-instruction: pushes the byte at an address to A or B, leaving the address in HLI. `PUSH *x HLI`, `PUSH &HLI A` (4 bytes).",
+        "JNZ" => "Pseudo-instruction: jumps if ZERO is clear. `JZE` over a `JMP` (6 bytes).",
+        "CALL_IF_ZERO" => "Pseudo-instruction: calls if ZERO is set. `JZE` to a `CALL`, over a `JMP` past it (9 bytes).",
+        "NEG" => "Pseudo-instruction: pushes 0 minus the last value of A (or B). `PUSH 0x00 B`, `SUB B A`, `POP B` (4 bytes). Flags are set as by `SUB`.",
+        _ => return None,
+    })
+}
+
+/// What the register `name` of [`REGISTERS`] holds
+pub fn register_description(name: &str) -> Option<&'static str> {
+    Some(match name.to_uppercase().as_str() {
+        "A" | "B" => "8 bit stack register, instructions use its last value",
+        "AB" => "16 bit pair of the last values of A (high byte) and B (low byte)",
+        "HI" => "High byte of HLI",
+        "LI" => "Low byte of HLI",
+        "HLI" => "16 bit index register, `&HLI` reads or writes the memory it points to",
+        "EX" => "Exit code set by `RET`",
+        "IRA" => "Address of the interrupt handler, set with `POP AB IRA`",
+        "S" => "Memory stack",
+        "SA" => "Memory stack address of the current routine",
+        "SS" => "Memory stack size of the current routine",
+        _ => return None,
+    })
+}
+
+/// When the flag `name` of [`FLAGS`] is set
+pub fn flag_description(name: &str) -> Option<&'static str> {
+    Some(match name.to_uppercase().as_str() {
+        "ZER" | "ZERO" => "Set when a result is 0 or compared values are equal",
+        "OVF" => "Set on carry, borrow or when `CMP` finds the register greater than the value",
+        "ERR" => "Set by `RET ERR` and `SET ERR`",
+        "OK" => "Set by `RET OK`",
+        "IRQ" => "Interrupts are enabled while set",
+        _ => return None,
+    })
+}
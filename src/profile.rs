@@ -0,0 +1,104 @@
+//! Instruction profiler for programs running in the [`Emulator`]. Every executed instruction is
+//! counted for the call stack it ran in, and the counts are written in the folded-stack format
+//! read by flame-graph tools (eg. `flamegraph.pl` or `inferno-flamegraph`):
+//!
+//! ```text
+//! main 14
+//! main;draw 120
+//! main;draw;plot 610
+//! ```
+//!
+//! Code outside of any `CALL` belongs to the routine at the reset address, `CALL` itself to the
+//! caller and `RET` to the routine it returns from. Interrupt handlers show up where they
+//! interrupted the program.
+
+use std::collections::HashMap;
+
+use crate::{
+    assembly::Assembly,
+    emulator::{Cpu, Emulator, Event, Step},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    stack: Vec<u16>, // routine addresses, outermost first
+    instructions: HashMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a step just executed by `emulator`
+    pub fn record(&mut self, emulator: &Emulator, step: &Step) {
+        let enters_or_leaves = matches!(
+            step.event,
+            Some(Event::Call { .. } | Event::Interrupt { .. } | Event::Return { .. })
+        );
+        // Other instructions ran in the call stack the emulator has now, which differs from
+        // ours if it was reset since the last step
+        if !enters_or_leaves {
+            self.sync(emulator);
+        }
+        // Entering an interrupt handler is not an instruction
+        if step.opcode.is_some() {
+            match self.instructions.get_mut(self.stack.as_slice()) {
+                Some(count) => *count += 1,
+                None => {
+                    self.instructions.insert(self.stack.clone(), 1);
+                }
+            }
+        }
+        match step.event {
+            Some(Event::Call { target, .. }) | Some(Event::Interrupt { target, .. }) => {
+                self.stack.push(target)
+            }
+            Some(Event::Return { .. }) => {
+                self.stack.pop();
+            }
+            _ => (),
+        }
+        // The emulator was reset or entered a routine without stepping
+        self.sync(emulator);
+    }
+
+    fn sync(&mut self, emulator: &Emulator) {
+        if self.stack.len() != emulator.call_depth() {
+            self.stack = emulator.call_stack().iter().map(|f| f.target).collect();
+        }
+    }
+
+    /// Instructions by call stack, the routine addresses are outermost first without the reset
+    /// address
+    pub fn stacks(&self) -> &HashMap<Vec<u16>, u64> {
+        &self.instructions
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.instructions.values().sum()
+    }
+
+    /// Folded stacks, one `main;caller;callee instructions` line per call stack
+    pub fn folded(&self, assembly: &Assembly) -> String {
+        let name = |address: u16| {
+            assembly
+                .symbolized(address)
+                .unwrap_or_else(|| format!("0x{:04x}", address))
+        };
+        let root = name(Cpu::default().pc);
+        let mut lines: Vec<String> = self
+            .instructions
+            .iter()
+            .map(|(stack, count)| {
+                let mut frames = vec![root.clone()];
+                frames.extend(stack.iter().map(|address| name(*address)));
+                format!("{} {}", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        let mut folded = lines.join("\n");
+        folded.push('\n');
+        folded
+    }
+}
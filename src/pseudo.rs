@@ -0,0 +1,179 @@
+//! Pseudo-instructions, expanded to existing opcodes while parsing:
+//!
+//! ```text
+//! MOV16 0x1234, HLI      LOADI message, A      JNZ loop      CALL_IF_ZERO flush      NEG A
+//! ```
+//!
+//! Operands can be separated by a comma or a space. The expansions are what they would be if
+//! written by hand, so the listing, the analyses and the lints see the real instructions.
+//! `JNZ` and `CALL_IF_ZERO` jump over a `JMP`, since the CPU only jumps when a flag is set, with
+//! generated labels starting with a `.` (eg. `.jnz1.skip`).
+
+use anyhow::{anyhow, Error};
+
+use crate::{
+    ir::{Instruction, Label, Operand, StatementKind},
+    opcodes::Opcode,
+    Token, TokenType,
+};
+
+/// Pseudo-instructions of one source while it is being parsed
+#[derive(Default)]
+pub(crate) struct Pseudo {
+    count: usize, // expansions with labels so far, numbers the labels
+}
+
+impl Pseudo {
+    /// Statements of the pseudo-instruction on `line`
+    pub(crate) fn expand(&mut self, line: &[Token]) -> Result<Vec<StatementKind>, Error> {
+        let mnemonic = line[0].formatted_raw();
+        let operands: Vec<&Token> = line[1..]
+            .iter()
+            .take_while(|t| t._type != TokenType::CommentStart)
+            .collect();
+        let statements = match (mnemonic.as_str(), operands.as_slice()) {
+            ("MOV16", [source, target]) => {
+                let source = match source._type {
+                    TokenType::Register if source.formatted_raw() == "HLI" => None,
+                    TokenType::ImmediateValue8 | TokenType::ImmediateValue16 => {
+                        Some((Opcode::PUSH_IMMEDIATE_HLI, immediate(source)?))
+                    }
+                    TokenType::Address | TokenType::Text => {
+                        Some((Opcode::PUSH_ABSOLUTE_HLI, address(source)?))
+                    }
+                    _ => return Err(syntax(&mnemonic)),
+                };
+                let target = match target._type {
+                    TokenType::Register if target.formatted_raw() == "HLI" => None,
+                    TokenType::Address | TokenType::Text => Some(address(target)?),
+                    _ => return Err(syntax(&mnemonic)),
+                };
+                match (source, target) {
+                    (Some((opcode, source)), None) => vec![instruction(opcode, Some(source))],
+                    (None, Some(target)) => {
+                        vec![instruction(Opcode::STORE_HLI_ABSOLUTE, Some(target))]
+                    }
+                    // Through AB, which is left as it was
+                    (Some((opcode, source)), Some(target)) => {
+                        let push = match opcode {
+                            Opcode::PUSH_IMMEDIATE_HLI => Opcode::PUSH_IMMEDIATE_AB,
+                            _ => Opcode::PUSH_ABSOLUTE_AB,
+                        };
+                        vec![
+                            instruction(push, Some(source)),
+                            instruction(Opcode::POP_AB_ABSOLUTE, Some(target)),
+                        ]
+                    }
+                    (None, None) => return Err(anyhow!("MOV16 HLI, HLI does nothing")),
+                }
+            }
+            ("LOADI", [source, target]) => {
+                let source = match source._type {
+                    TokenType::ImmediateValue8 | TokenType::ImmediateValue16 => immediate(source)?,
+                    TokenType::Address => Operand::Immediate16(value(source)?),
+                    TokenType::Text => Operand::LabelAddress(source.raw.clone()),
+                    _ => return Err(syntax(&mnemonic)),
+                };
+                let load = match target.formatted_raw().as_str() {
+                    "A" => Opcode::PUSH_INDIRECT_A,
+                    "B" => Opcode::PUSH_INDIRECT_B,
+                    _ => return Err(syntax(&mnemonic)),
+                };
+                vec![
+                    instruction(Opcode::PUSH_IMMEDIATE_HLI, Some(source)),
+                    instruction(load, None),
+                ]
+            }
+            ("JNZ", [target]) if target._type == TokenType::Text => {
+                let skip = self.label("jnz", "skip");
+                vec![
+                    instruction(Opcode::JUMP_IF_ZERO, Some(Operand::Label(skip.clone()))),
+                    instruction(Opcode::JUMP, Some(Operand::Label(target.raw.clone()))),
+                    label(skip),
+                ]
+            }
+            ("CALL_IF_ZERO", [target]) if target._type == TokenType::Text => {
+                let call = self.label("call_if_zero", "call");
+                let skip = self.label_of_last("call_if_zero", "skip");
+                vec![
+                    instruction(Opcode::JUMP_IF_ZERO, Some(Operand::Label(call.clone()))),
+                    instruction(Opcode::JUMP, Some(Operand::Label(skip.clone()))),
+                    label(call),
+                    instruction(Opcode::CALL, Some(Operand::Label(target.raw.clone()))),
+                    label(skip),
+                ]
+            }
+            // 0 - the value, with the 0 in the other register, which is left as it was
+            ("NEG", [register]) => {
+                let (zero, sub, pop) = match register.formatted_raw().as_str() {
+                    "A" => (Opcode::PUSH_IMMEDIATE_B, Opcode::SUB_B_A, Opcode::POP_B),
+                    "B" => (Opcode::PUSH_IMMEDIATE_A, Opcode::SUB_A_B, Opcode::POP_A),
+                    _ => return Err(syntax(&mnemonic)),
+                };
+                vec![
+                    instruction(zero, Some(Operand::Immediate8(0))),
+                    instruction(sub, None),
+                    instruction(pop, None),
+                ]
+            }
+            _ => return Err(syntax(&mnemonic)),
+        };
+        Ok(statements)
+    }
+
+    /// Name of a new generated label
+    fn label(&mut self, prefix: &str, name: &str) -> String {
+        self.count += 1;
+        self.label_of_last(prefix, name)
+    }
+
+    /// Name of another label of the last expansion
+    fn label_of_last(&self, prefix: &str, name: &str) -> String {
+        format!(".{}{}.{}", prefix, self.count, name)
+    }
+}
+
+fn syntax(mnemonic: &str) -> Error {
+    let expected = match mnemonic {
+        "MOV16" => "MOV16 <value, address or HLI>, <address or HLI>",
+        "LOADI" => "LOADI <address>, <A or B>",
+        "JNZ" => "JNZ <label>",
+        "CALL_IF_ZERO" => "CALL_IF_ZERO <label>",
+        _ => "NEG <A or B>",
+    };
+    anyhow!("syntax error - expected {}", expected)
+}
+
+fn value(token: &Token) -> Result<u16, Error> {
+    token
+        .value
+        .map(|v| v as u16)
+        .ok_or_else(|| anyhow!("{} is not a valid value", token.raw))
+}
+
+/// 16 bit immediate value, `*label` for the address of a label
+fn immediate(token: &Token) -> Result<Operand, Error> {
+    match token.raw.strip_prefix('*') {
+        Some(label) => Ok(Operand::LabelAddress(label.to_owned())),
+        None => Ok(Operand::Immediate16(value(token)?)),
+    }
+}
+
+/// Memory at `&0x1234` or at a label
+fn address(token: &Token) -> Result<Operand, Error> {
+    match token._type {
+        TokenType::Text => Ok(Operand::Label(token.raw.clone())),
+        _ => Ok(Operand::Address(value(token)?)),
+    }
+}
+
+fn instruction(opcode: Opcode, operand: Option<Operand>) -> StatementKind {
+    StatementKind::Instruction(Instruction {
+        opcode,
+        operands: operand.into_iter().collect(),
+    })
+}
+
+fn label(name: String) -> StatementKind {
+    StatementKind::Label(Label { name })
+}
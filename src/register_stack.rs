@@ -0,0 +1,287 @@
+//! Depths of the A and B register stacks through each routine of the [`ControlFlowGraph`].
+//!
+//! Depths are counted from the entry of the routine, so values passed in A or B can be used
+//! without knowing the caller. Every path reaching a block should leave the same depth behind,
+//! and `RET` should leave the depth the routine was entered with, plus the values a `.proc`
+//! declares with `returns`: anything else means a loop that grows the stack or a branch that
+//! forgets a `POP`, and the caller finds its values moved. A `CALL` changes the depths by
+//! whatever the called routine returns with.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::{
+    cfg::{ControlFlowGraph, Routine, Terminator},
+    ir::{Directive, Register, Statement, StatementKind},
+    opcodes::Opcode,
+};
+
+/// Values pushed to A and B since the entry of a routine, negative if it took more than it pushed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Depth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Display for Depth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A {:+}, B {:+}", self.a, self.b)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Imbalance {
+    Merge {
+        address: u16,
+        depths: [Depth; 2],
+    }, // block reached with different depths
+    Return {
+        address: u16,
+        depth: Depth,
+        expected: Depth, // declared with `returns`, none unless the routine is a `.proc`
+    }, // `RET` at a depth other than the expected one
+}
+
+#[derive(Default)]
+struct Walk {
+    imbalances: Vec<Imbalance>,
+    returns: Vec<Depth>,
+    lowest: Depth, // below zero if the routine takes or changes values of its caller
+}
+
+pub struct RegisterStackAnalysis {
+    walks: HashMap<u16, Walk>,    // by routine entry
+    returns: HashMap<u16, Depth>, // declared by `.proc`s, by entry
+}
+
+/// Depths the `.proc`s in `statements` declare to return with, by entry
+pub fn declared(statements: &[Statement]) -> HashMap<u16, Depth> {
+    statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::Directive(Directive::Proc(proc)) => {
+                let count = |register| proc.returns.iter().filter(|r| **r == register).count();
+                let depth = Depth {
+                    a: count(Register::A) as i32,
+                    b: count(Register::B) as i32,
+                };
+                Some((statement.address, depth))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl RegisterStackAnalysis {
+    /// Follows the routines of `graph`, `returns` are the depths declared by [`declared`]
+    pub fn new(graph: &ControlFlowGraph, returns: &HashMap<u16, Depth>) -> Self {
+        let mut analysis = Self {
+            walks: HashMap::new(),
+            returns: returns.clone(),
+        };
+        let mut walking = HashSet::new();
+        for routine in graph.routines() {
+            analysis.walk(graph, routine, &mut walking);
+        }
+        analysis
+    }
+
+    /// Problems found in the routine at `entry`, in the order the paths were followed
+    pub fn imbalances(&self, entry: u16) -> &[Imbalance] {
+        self.walks
+            .get(&entry)
+            .map(|w| w.imbalances.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Change of the depths a call of the routine at `entry` makes. Routines returning with
+    /// different depths, recursion and code outside of the program count as no change
+    pub fn effect(&self, entry: u16) -> Depth {
+        match self.walks.get(&entry).map(|w| w.returns.as_slice()) {
+            Some([first, rest @ ..]) if rest.iter().all(|d| d == first) => *first,
+            _ => Depth::default(),
+        }
+    }
+
+    /// The routine at `entry` leaves the values of `register` as its caller had them. HI and LI
+    /// are always restored by `RET`, values declared with `returns` do not count
+    pub fn preserves(&self, entry: u16, register: Register) -> bool {
+        let Some(walk) = self.walks.get(&entry) else {
+            return true;
+        };
+        let depth = |depth: &Depth| match register {
+            Register::A => depth.a,
+            Register::B => depth.b,
+            Register::HI | Register::LI => 0,
+        };
+        let expected = depth(&self.returns.get(&entry).copied().unwrap_or_default());
+        depth(&walk.lowest) >= 0 && walk.returns.iter().all(|d| depth(d) == expected)
+    }
+
+    fn walk(&mut self, graph: &ControlFlowGraph, routine: &Routine, walking: &mut HashSet<u16>) {
+        if self.walks.contains_key(&routine.entry) || !walking.insert(routine.entry) {
+            return;
+        }
+        // Called routines first, for their effects
+        for callee in &routine.calls {
+            if let Some(callee) = graph.routine(*callee) {
+                self.walk(graph, callee, walking);
+            }
+        }
+        let mut walk = Walk::default();
+        let mut depths: HashMap<u16, Depth> = HashMap::new();
+        let mut reported = HashSet::new();
+        let mut queue = vec![(routine.entry, Depth::default())];
+        while let Some((address, depth)) = queue.pop() {
+            let Some(block) = graph.block(address) else {
+                continue;
+            };
+            if let Some(first) = depths.get(&address) {
+                if *first != depth && reported.insert(address) {
+                    walk.imbalances.push(Imbalance::Merge {
+                        address,
+                        depths: [*first, depth],
+                    });
+                }
+                continue;
+            }
+            depths.insert(address, depth);
+            let mut depth = depth;
+            for instruction in &block.instructions {
+                // Shifts replace the last value
+                let (a, b) = replaces(instruction.opcode);
+                walk.lowest.a = walk.lowest.a.min(depth.a - a);
+                walk.lowest.b = walk.lowest.b.min(depth.b - b);
+                let (a, b) = effect(instruction.opcode, instruction.operand);
+                depth.a += a;
+                depth.b += b;
+                walk.lowest.a = walk.lowest.a.min(depth.a);
+                walk.lowest.b = walk.lowest.b.min(depth.b);
+            }
+            match block.terminator {
+                Terminator::Call { target, return_to } => {
+                    if let Some(called) = self.walks.get(&target) {
+                        walk.lowest.a = walk.lowest.a.min(depth.a + called.lowest.a);
+                        walk.lowest.b = walk.lowest.b.min(depth.b + called.lowest.b);
+                    }
+                    let called = self.effect(target);
+                    depth.a += called.a;
+                    depth.b += called.b;
+                    queue.push((return_to, depth));
+                }
+                Terminator::Return(_) => {
+                    walk.returns.push(depth);
+                    let expected = self
+                        .returns
+                        .get(&routine.entry)
+                        .copied()
+                        .unwrap_or_default();
+                    if depth != expected {
+                        let address = block.instructions.last().map_or(block.start, |i| i.address);
+                        walk.imbalances.push(Imbalance::Return {
+                            address,
+                            depth,
+                            expected,
+                        });
+                    }
+                }
+                _ => queue.extend(block.successors().into_iter().map(|s| (s, depth))),
+            }
+        }
+        walking.remove(&routine.entry);
+        self.walks.insert(routine.entry, walk);
+    }
+}
+
+/// Values the instruction pushes to (positive) or pops from (negative) A and B
+pub(crate) fn effect(opcode: Opcode, operand: Option<u16>) -> (i32, i32) {
+    let count = operand.unwrap_or_default() as i32;
+    match opcode {
+        Opcode::PUSH_IMMEDIATE_A
+        | Opcode::PUSH_ABSOLUTE_A
+        | Opcode::PUSH_INDIRECT_A
+        | Opcode::PUSH_B_A
+        | Opcode::PUSH_HI_A
+        | Opcode::PUSH_LI_A
+        | Opcode::PUSH_EXIT_CODE_A
+        | Opcode::ADD_B_A
+        | Opcode::ADD_IMMEDIATE_A
+        | Opcode::ADD_ABSOLUTE_A
+        | Opcode::SUB_B_A
+        | Opcode::SUB_IMMEDIATE_A
+        | Opcode::SUB_ABSOLUTE_A
+        | Opcode::AND_B_A
+        | Opcode::OR_B_A
+        | Opcode::XOR_B_A
+        | Opcode::NOT_A => (1, 0),
+        Opcode::PUSH_IMMEDIATE_B
+        | Opcode::PUSH_ABSOLUTE_B
+        | Opcode::PUSH_INDIRECT_B
+        | Opcode::PUSH_A_B
+        | Opcode::PUSH_HI_B
+        | Opcode::PUSH_LI_B
+        | Opcode::PUSH_EXIT_CODE_B
+        | Opcode::ADD_A_B
+        | Opcode::ADD_IMMEDIATE_B
+        | Opcode::ADD_ABSOLUTE_B
+        | Opcode::SUB_A_B
+        | Opcode::SUB_IMMEDIATE_B
+        | Opcode::SUB_ABSOLUTE_B
+        | Opcode::AND_A_B
+        | Opcode::OR_A_B
+        | Opcode::XOR_A_B
+        | Opcode::NOT_B => (0, 1),
+        Opcode::POP_A
+        | Opcode::POP_A_ABSOLUTE
+        | Opcode::POP_A_INDIRECT
+        | Opcode::POP_A_HI
+        | Opcode::POP_A_LI => (-1, 0),
+        Opcode::POP_B
+        | Opcode::POP_B_ABSOLUTE
+        | Opcode::POP_B_INDIRECT
+        | Opcode::POP_B_HI
+        | Opcode::POP_B_LI => (0, -1),
+        Opcode::POP_A_B => (-1, 1),
+        Opcode::POP_B_A => (1, -1),
+        Opcode::PUSH_IMMEDIATE_AB
+        | Opcode::PUSH_ABSOLUTE_AB
+        | Opcode::PUSH_INDIRECT_AB
+        | Opcode::PUSH_HLI_AB
+        | Opcode::ADD_IMMEDIATE_AB
+        | Opcode::ADD_ABSOLUTE_AB
+        | Opcode::SUB_IMMEDIATE_AB
+        | Opcode::SUB_ABSOLUTE_AB
+        | Opcode::AND_IMMEDIATE_AB
+        | Opcode::AND_ABSOLUTE_AB
+        | Opcode::OR_IMMEDIATE_AB
+        | Opcode::OR_ABSOLUTE_AB
+        | Opcode::XOR_IMMEDIATE_AB
+        | Opcode::XOR_ABSOLUTE_AB
+        | Opcode::NOT_AB
+        | Opcode::PUSH_AB_STACK_ADDRESS
+        | Opcode::PUSH_AB_STACK_SIZE => (1, 1),
+        Opcode::POP_AB_ABSOLUTE
+        | Opcode::POP_AB_INDIRECT
+        | Opcode::POP_AB_HLI
+        | Opcode::POP_STACK_ADDRESS_AB
+        | Opcode::POP_STACK_SIZE_AB
+        | Opcode::POP_AB_IRQ => (-1, -1),
+        Opcode::POP_A_STACK => (-count, 0),
+        Opcode::POP_STACK_A => (count, 0),
+        Opcode::POP_B_STACK => (0, -count),
+        Opcode::POP_STACK_B => (0, count),
+        _ => (0, 0),
+    }
+}
+
+/// Last values of A and B the instruction changes in place
+fn replaces(opcode: Opcode) -> (i32, i32) {
+    match opcode {
+        Opcode::SHIFT_LEFT_A | Opcode::SHIFT_RIGHT_A => (1, 0),
+        Opcode::SHIFT_LEFT_B | Opcode::SHIFT_RIGHT_B => (0, 1),
+        Opcode::SHIFT_LEFT_AB | Opcode::SHIFT_RIGHT_AB => (1, 1),
+        _ => (0, 0),
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::OpenOptions,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+
+use crate::assembly::Diagnostic;
+
+/// Where the assembler reads the input file and any `.include`d files from
+pub trait FileSystem {
+    fn read(&self, path: &Path) -> Result<String, Error>;
+}
+
+/// Reads files from disk
+#[derive(Debug, Default, Clone)]
+pub struct DiskFileSystem;
+
+impl FileSystem for DiskFileSystem {
+    fn read(&self, path: &Path) -> Result<String, Error> {
+        let mut input = String::new();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|e| anyhow!("cannot open {}: {}", path.display(), e))?;
+        file.read_to_string(&mut input).map_err(|e| anyhow!(e))?;
+        Ok(input)
+    }
+}
+
+/// In-memory files, eg. for assembling snippets in tests or editor buffers
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFileSystem {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(mut self, path: impl Into<PathBuf>, source: impl Into<String>) -> Self {
+        self.insert(path, source);
+        self
+    }
+
+    pub fn insert(&mut self, path: impl Into<PathBuf>, source: impl Into<String>) {
+        self.files.insert(path.into(), source.into());
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> Result<String, Error> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("file {} does not exist", path.display()))
+    }
+}
+
+/// Line in one of the source files, counted from 1
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.line)
+    }
+}
+
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Reads `path` and returns all of its lines with `.include "file"` lines replaced by the
+/// lines of the included file. Included paths are relative to the including file.
+pub(crate) fn load_lines(
+    file_system: &dyn FileSystem,
+    path: &Path,
+) -> Result<Vec<(Location, String)>, Error> {
+    let mut lines = vec![];
+    load_lines_into(file_system, path, &mut vec![], &mut lines)?;
+    Ok(lines)
+}
+
+fn load_lines_into(
+    file_system: &dyn FileSystem,
+    path: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<(Location, String)>,
+) -> Result<(), Error> {
+    if include_stack.iter().any(|p| p == path) {
+        return Err(anyhow!("{} includes itself", path.display()));
+    }
+    if include_stack.len() > MAX_INCLUDE_DEPTH {
+        return Err(anyhow!("includes nested too deeply in {}", path.display()));
+    }
+    let input = file_system.read(path)?;
+    include_stack.push(path.to_owned());
+    for (line_n, line) in input.split('\n').enumerate() {
+        let location = Location {
+            file: path.to_owned(),
+            line: line_n + 1,
+        };
+        let mut words = line.split_whitespace();
+        if words
+            .next()
+            .is_some_and(|w| w.eq_ignore_ascii_case(".include"))
+        {
+            let included = line
+                .split('"')
+                .nth(1)
+                .filter(|_| line.matches('"').count() == 2)
+                .ok_or_else(|| {
+                    Diagnostic::error(
+                        Some(location.clone()),
+                        "syntax error - .include requires a quoted path",
+                    )
+                })?;
+            let included = path
+                .parent()
+                .map(|dir| dir.join(included))
+                .unwrap_or_else(|| PathBuf::from(included));
+            load_lines_into(file_system, &included, include_stack, lines).map_err(|e| {
+                if e.is::<Diagnostic>() {
+                    e
+                } else {
+                    Diagnostic::error(Some(location.clone()), e.to_string()).into()
+                }
+            })?;
+        } else {
+            lines.push((location, line.to_owned()));
+        }
+    }
+    include_stack.pop();
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Static worst-case usage of the memory stack, based on the [`ControlFlowGraph`].
+//!
+//! Within a routine, `PUSH A S n`/`POP A S n` (and the B variants) push `n` bytes, `POP S A n`
+//! pops them and `PUSH HI S`/`POP S HI` (and LI) move one byte. Where paths merge the deeper one
+//! counts. A `CALL` adds the 9-byte frame and the worst case of the called routine on top of the
+//! caller's depth at that point. Recursion and loops that keep pushing have no bound.
+//! Changing the stack with `POP AB SA` or `POP AB SS` is not followed.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::{
+    cfg::{ControlFlowGraph, Routine, Terminator},
+    emulator::cpu::CALL_FRAME_SIZE,
+    opcodes::Opcode,
+};
+
+/// Bytes of the stack used by a routine, `None` if there is no bound
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackUsage {
+    pub own: Option<u32>,   // pushed by the routine itself
+    pub total: Option<u32>, // including calls
+    pub deepest: Vec<u16>,  // entries of the routines called on the way to the deepest point
+}
+
+impl Display for StackUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.total {
+            Some(total) => write!(f, "{} bytes", total),
+            None => write!(f, "unbounded"),
+        }
+    }
+}
+
+pub struct StackAnalysis<'a> {
+    graph: &'a ControlFlowGraph,
+    usage: RefCell<HashMap<u16, StackUsage>>, // computed once per routine
+}
+
+/// Deepest point of a routine's own pushes and the depth at each of its calls
+struct Local {
+    max: Option<u32>,
+    calls: Vec<(u16, u32)>,
+}
+
+impl<'a> StackAnalysis<'a> {
+    pub fn new(graph: &'a ControlFlowGraph) -> Self {
+        Self {
+            graph,
+            usage: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Routines that are not called by any other: the reset address, interrupt handlers, tests
+    pub fn entry_points(&self) -> Vec<&'a Routine> {
+        let called: HashSet<u16> = self
+            .graph
+            .routines()
+            .flat_map(|r| r.calls.iter().copied())
+            .collect();
+        self.graph
+            .routines()
+            .filter(|r| !called.contains(&r.entry))
+            .collect()
+    }
+
+    /// Worst case of the routine at `entry`, `None` if it is not a routine
+    pub fn usage(&self, entry: u16) -> Option<StackUsage> {
+        self.graph.routine(entry)?;
+        Some(self.usage_of(entry, &mut vec![]))
+    }
+
+    fn usage_of(&self, entry: u16, path: &mut Vec<u16>) -> StackUsage {
+        if let Some(usage) = self.usage.borrow().get(&entry) {
+            return usage.clone();
+        }
+        let Some(routine) = self.graph.routine(entry) else {
+            // Not a part of the program, only its frame is counted
+            return StackUsage {
+                own: Some(0),
+                total: Some(0),
+                deepest: vec![],
+            };
+        };
+        path.push(entry);
+        let local = self.local(routine);
+        let mut usage = StackUsage {
+            own: local.max,
+            total: local.max,
+            deepest: vec![],
+        };
+        for (callee, depth) in local.calls {
+            let callee_usage = if path.contains(&callee) {
+                None // recursion
+            } else {
+                let callee_usage = self.usage_of(callee, path);
+                callee_usage
+                    .total
+                    .map(|total| (total, callee_usage.deepest))
+            };
+            match (usage.total, callee_usage) {
+                (Some(total), Some((callee_total, deepest))) => {
+                    let reached = depth + CALL_FRAME_SIZE as u32 + callee_total;
+                    if reached > total {
+                        usage.total = Some(reached);
+                        usage.deepest = vec![callee];
+                        usage.deepest.extend(deepest);
+                    }
+                }
+                (Some(_), None) => {
+                    usage.total = None;
+                    usage.deepest = vec![callee];
+                }
+                (None, _) => (),
+            }
+        }
+        path.pop();
+        // Results within a recursion depend on where it was entered
+        if !path.iter().any(|p| usage.deepest.contains(p)) {
+            self.usage.borrow_mut().insert(entry, usage.clone());
+        }
+        usage
+    }
+
+    fn local(&self, routine: &Routine) -> Local {
+        let mut depths: BTreeMap<u16, u32> = BTreeMap::new();
+        let mut updates: HashMap<u16, usize> = HashMap::new();
+        let mut calls: BTreeMap<u16, u32> = BTreeMap::new(); // call site -> depth
+        let mut max = 0;
+        let mut queue = vec![(routine.entry, 0)];
+        while let Some((address, depth)) = queue.pop() {
+            let Some(block) = self.graph.block(address) else {
+                continue;
+            };
+            if depths.get(&address).is_some_and(|d| *d >= depth) {
+                continue;
+            }
+            // A block reached deeper again and again is in a loop that keeps pushing
+            let count = updates.entry(address).or_default();
+            *count += 1;
+            if *count > routine.blocks.len() + 1 {
+                return Local {
+                    max: None,
+                    calls: vec![],
+                };
+            }
+            depths.insert(address, depth);
+            let mut depth = depth;
+            for instruction in &block.instructions {
+                depth = apply(depth, instruction.opcode, instruction.operand);
+                max = max.max(depth);
+            }
+            if let Terminator::Call { .. } = block.terminator {
+                let site = calls.entry(block.start).or_default();
+                *site = (*site).max(depth);
+            }
+            for successor in block.successors() {
+                queue.push((successor, depth));
+            }
+        }
+        let calls = calls
+            .into_iter()
+            .filter_map(|(site, depth)| match self.graph.block(site)?.terminator {
+                Terminator::Call { target, .. } => Some((target, depth)),
+                _ => None,
+            })
+            .collect();
+        Local {
+            max: Some(max),
+            calls,
+        }
+    }
+}
+
+/// Depth after the instruction
+fn apply(depth: u32, opcode: Opcode, operand: Option<u16>) -> u32 {
+    let count = operand.unwrap_or_default() as u32;
+    match opcode {
+        Opcode::PUSH_A_STACK | Opcode::POP_A_STACK | Opcode::PUSH_B_STACK | Opcode::POP_B_STACK => {
+            depth + count
+        }
+        Opcode::POP_STACK_A | Opcode::POP_STACK_B => depth.saturating_sub(count),
+        Opcode::PUSH_HI_STACK | Opcode::PUSH_LI_STACK => depth + 1,
+        Opcode::POP_STACK_HI | Opcode::POP_STACK_LI => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
@@ -0,0 +1,249 @@
+//! Structured control flow, lowered to jumps and generated labels while parsing:
+//!
+//! ```text
+//! .if zero          .while not_ok          .loop HLI
+//!     ...               ...                    ...
+//! .else             .endwhile              .endloop
+//!     ...
+//! .endif
+//! ```
+//!
+//! Conditions are `zero`, `overflow`, `error` and `ok`, or `not_` any of them, and test the flags
+//! as they are when the directive is reached: `.while` tests them again after every round, so the
+//! body has to set them. `.loop` runs its body as many times as the register (`HLI`, `HI` or
+//! `LI`) says, counting it down to zero, and not at all if it is zero already. `.break` leaves
+//! the innermost `.while` or `.loop` (a `.loop` register keeps the count it had).
+//!
+//! The CPU only jumps when a flag is set, so a condition on a set flag jumps over a `JMP` to the
+//! other branch. Generated labels start with a `.`, so they cannot clash with the program's own
+//! (eg. `.if1.else`, `.while2.top`).
+
+use anyhow::{anyhow, Error};
+
+use crate::{
+    ir::{Instruction, Label, Operand, StatementKind},
+    opcodes::Opcode,
+    source::Location,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Condition {
+    jump: Opcode, // jumps if the flag is set
+    negated: bool,
+}
+
+impl Condition {
+    fn parse(text: &str) -> Result<Condition, Error> {
+        let text = text.to_lowercase();
+        let (negated, flag) = match text.strip_prefix("not_") {
+            Some(flag) => (true, flag),
+            None => (false, text.as_str()),
+        };
+        let jump = match flag {
+            "zero" | "zer" => Opcode::JUMP_IF_ZERO,
+            "overflow" | "ovf" => Opcode::JUMP_IF_OVERFLOW,
+            "error" | "err" => Opcode::JUMP_IF_ERROR,
+            "ok" => Opcode::JUMP_IF_OK,
+            _ => {
+                return Err(anyhow!(
+                    "syntax error - expected a condition: zero, overflow, error, ok or not_ any of them, got `{}`",
+                    text
+                ))
+            }
+        };
+        Ok(Condition { jump, negated })
+    }
+}
+
+#[derive(Debug)]
+enum Block {
+    If { id: usize, has_else: bool },
+    While { id: usize },
+    Loop { id: usize, register: &'static str },
+}
+
+impl Block {
+    fn directive(&self) -> &'static str {
+        match self {
+            Block::If { .. } => ".if",
+            Block::While { .. } => ".while",
+            Block::Loop { .. } => ".loop",
+        }
+    }
+
+    fn end(&self) -> &'static str {
+        match self {
+            Block::If { .. } => ".endif",
+            Block::While { .. } => ".endwhile",
+            Block::Loop { .. } => ".endloop",
+        }
+    }
+}
+
+/// Open blocks of one source while it is being parsed
+#[derive(Default)]
+pub(crate) struct Structured {
+    blocks: Vec<(Block, Location)>,
+    count: usize, // blocks opened so far, numbers the labels
+}
+
+impl Structured {
+    /// Statements of `.if`, `.else`, `.endif`, `.while`, `.endwhile`, `.loop`, `.endloop` or
+    /// `.break` (upper case) with its arguments
+    pub(crate) fn lower(
+        &mut self,
+        directive: &str,
+        arguments: &str,
+        location: &Location,
+    ) -> Result<Vec<StatementKind>, Error> {
+        let mut statements = vec![];
+        match directive {
+            ".IF" => {
+                let condition = Condition::parse(arguments)?;
+                let id = self.open(
+                    |id| Block::If {
+                        id,
+                        has_else: false,
+                    },
+                    location,
+                );
+                // Skips to `.else`, or `.endif` if there is none
+                Self::branch(&mut statements, condition, &format!(".if{}", id), "else");
+            }
+            ".ELSE" => match self.blocks.last_mut() {
+                Some((Block::If { id, has_else }, _)) if !*has_else => {
+                    *has_else = true;
+                    statements.push(jump(Opcode::JUMP, &format!(".if{}.end", id)));
+                    statements.push(label(&format!(".if{}.else", id)));
+                }
+                _ => return Err(anyhow!(".else without .if")),
+            },
+            ".WHILE" => {
+                let condition = Condition::parse(arguments)?;
+                let id = self.open(|id| Block::While { id }, location);
+                statements.push(label(&format!(".while{}.top", id)));
+                Self::branch(&mut statements, condition, &format!(".while{}", id), "end");
+            }
+            ".LOOP" => {
+                let (register, compare) = match arguments.to_uppercase().as_str() {
+                    "HLI" => ("HLI", Opcode::CMP_IMMEDIATE_HLI),
+                    "HI" => ("HI", Opcode::CMP_IMMEDIATE_HI),
+                    "LI" => ("LI", Opcode::CMP_IMMEDIATE_LI),
+                    _ => return Err(anyhow!("syntax error - .loop counts HLI, HI or LI")),
+                };
+                let id = self.open(|id| Block::Loop { id, register }, location);
+                let zero = match register {
+                    "HLI" => Operand::Immediate16(0),
+                    _ => Operand::Immediate8(0),
+                };
+                statements.push(label(&format!(".loop{}.top", id)));
+                statements.push(StatementKind::Instruction(Instruction {
+                    opcode: compare,
+                    operands: vec![zero],
+                }));
+                statements.push(jump(Opcode::JUMP_IF_ZERO, &format!(".loop{}.end", id)));
+            }
+            ".BREAK" => {
+                let end = self.blocks.iter().rev().find_map(|(block, _)| match block {
+                    Block::While { id } => Some(format!(".while{}.end", id)),
+                    Block::Loop { id, .. } => Some(format!(".loop{}.end", id)),
+                    Block::If { .. } => None,
+                });
+                match end {
+                    Some(end) => statements.push(jump(Opcode::JUMP, &end)),
+                    None => return Err(anyhow!(".break outside of .while or .loop")),
+                }
+            }
+            ".ENDIF" | ".ENDWHILE" | ".ENDLOOP" => {
+                let block = match self.blocks.pop() {
+                    Some((block, _)) if block.end().eq_ignore_ascii_case(directive) => block,
+                    Some((block, location)) => {
+                        return Err(anyhow!(
+                            "{} does not close {} at line {}",
+                            directive.to_lowercase(),
+                            block.directive(),
+                            location.line
+                        ))
+                    }
+                    None => {
+                        return Err(anyhow!(
+                            "{} without {}",
+                            directive.to_lowercase(),
+                            directive.to_lowercase().replace(".end", ".")
+                        ))
+                    }
+                };
+                match block {
+                    Block::If { id, has_else: true } => {
+                        statements.push(label(&format!(".if{}.end", id)))
+                    }
+                    Block::If {
+                        id,
+                        has_else: false,
+                    } => statements.push(label(&format!(".if{}.else", id))),
+                    Block::While { id } => {
+                        statements.push(jump(Opcode::JUMP, &format!(".while{}.top", id)));
+                        statements.push(label(&format!(".while{}.end", id)));
+                    }
+                    Block::Loop { id, register } => {
+                        let decrement = match register {
+                            "HLI" => Opcode::DEC_HLI,
+                            "HI" => Opcode::DEC_HI,
+                            _ => Opcode::DEC_LI,
+                        };
+                        statements.push(StatementKind::Instruction(Instruction {
+                            opcode: decrement,
+                            operands: vec![],
+                        }));
+                        statements.push(jump(Opcode::JUMP, &format!(".loop{}.top", id)));
+                        statements.push(label(&format!(".loop{}.end", id)));
+                    }
+                }
+            }
+            _ => return Err(anyhow!("{} is not a structured directive", directive)),
+        }
+        Ok(statements)
+    }
+
+    /// Fails if a block is not closed at the end of the source
+    pub(crate) fn finish(&self) -> Result<(), (Location, Error)> {
+        match self.blocks.last() {
+            Some((block, location)) => Err((
+                location.clone(),
+                anyhow!("{} is not closed with {}", block.directive(), block.end()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Pushes a block with the next number and returns the number
+    fn open(&mut self, block: impl FnOnce(usize) -> Block, location: &Location) -> usize {
+        self.count += 1;
+        self.blocks.push((block(self.count), location.clone()));
+        self.count
+    }
+
+    /// Continues with the next statement if the condition holds, jumps to `prefix.skip` if not
+    fn branch(statements: &mut Vec<StatementKind>, condition: Condition, prefix: &str, skip: &str) {
+        if condition.negated {
+            statements.push(jump(condition.jump, &format!("{}.{}", prefix, skip)));
+        } else {
+            statements.push(jump(condition.jump, &format!("{}.then", prefix)));
+            statements.push(jump(Opcode::JUMP, &format!("{}.{}", prefix, skip)));
+            statements.push(label(&format!("{}.then", prefix)));
+        }
+    }
+}
+
+fn jump(opcode: Opcode, target: &str) -> StatementKind {
+    StatementKind::Instruction(Instruction {
+        opcode,
+        operands: vec![Operand::Label(target.to_owned())],
+    })
+}
+
+fn label(name: &str) -> StatementKind {
+    StatementKind::Label(Label {
+        name: name.to_owned(),
+    })
+}
@@ -0,0 +1,204 @@
+//! Tests written in the program with `.test` blocks, run in the [`Emulator`]:
+//!
+//! ```text
+//! .test "strlen of an empty string"
+//!     PUSH *empty HLI
+//!     CALL strlen
+//!     .expect A == 0x00, EX == 0
+//! .endtest
+//! ```
+//!
+//! Each test starts from a freshly reset emulator at its first instruction and runs until `HALT`
+//! (one is added at `.endtest`), then all of its expectations are checked.
+
+use std::io::Write;
+
+use anyhow::Error;
+
+use crate::{
+    assembly::Assembly,
+    emulator::{Emulator, Event},
+    ir::{ExpectTarget, Expectation, Operand},
+    source::Location,
+};
+
+/// Limit of instructions executed by a single test
+pub const MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitTest {
+    pub name: String,
+    pub address: u16,
+    pub location: Location,
+    pub expectations: Vec<Expectation>,
+}
+
+impl UnitTest {
+    /// Runs the test, returning why it failed
+    pub fn run(
+        &self,
+        emulator: &mut Emulator,
+        resolve: impl Fn(&str) -> Option<u16>,
+        max_steps: u64,
+    ) -> Result<(), Vec<String>> {
+        emulator.reset();
+        emulator.cpu.pc = self.address;
+        let mut steps = 0;
+        loop {
+            if steps == max_steps {
+                return Err(vec![format!(
+                    "did not halt after {} instructions",
+                    max_steps
+                )]);
+            }
+            match emulator.step() {
+                Ok(step) if step.event == Some(Event::Halt) => break,
+                Ok(_) => steps += 1,
+                Err(fault) => return Err(vec![format!("fault: {}", fault)]),
+            }
+        }
+        let failures: Vec<String> = self
+            .expectations
+            .iter()
+            .filter_map(|e| check(e, emulator, &resolve).err())
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+}
+
+/// Checks a single expectation against the state of the emulator
+pub fn check(
+    expectation: &Expectation,
+    emulator: &Emulator,
+    resolve: impl Fn(&str) -> Option<u16>,
+) -> Result<(), String> {
+    let cpu = &emulator.cpu;
+    let (name, value, width) = match &expectation.target {
+        ExpectTarget::Register(register) => {
+            let last = |values: &[u8]| values.last().map(|v| *v as u16);
+            let value = match register.as_str() {
+                "A" => last(&cpu.a),
+                "B" => last(&cpu.b),
+                "HI" => Some(cpu.hi as u16),
+                "LI" => Some(cpu.li as u16),
+                "EX" => Some(cpu.exit_code as u16),
+                "HLI" => Some(cpu.hli()),
+                _ => last(&cpu.a).zip(last(&cpu.b)).map(|(a, b)| a << 8 | b),
+            };
+            let width = if matches!(register.as_str(), "AB" | "HLI") {
+                4
+            } else {
+                2
+            };
+            let value = value.ok_or_else(|| format!("{} is empty", register))?;
+            (register.clone(), value, width)
+        }
+        ExpectTarget::Flag(flag) => {
+            let flags = cpu.flags;
+            let set = match flag.as_str() {
+                "ZER" => flags.zero,
+                "OVF" => flags.overflow,
+                "ERR" => flags.error,
+                "OK" => flags.ok,
+                _ => flags.irq,
+            };
+            (flag.clone(), set as u16, 1)
+        }
+        ExpectTarget::Memory(operand) => {
+            let (name, address) = match operand {
+                Operand::Label(label) | Operand::LabelAddress(label) => {
+                    let address = resolve(label)
+                        .ok_or_else(|| format!("label `{}` is not defined", label))?;
+                    (format!("&{}", label), address)
+                }
+                Operand::Immediate8(value) => (format!("&0x{:04x}", value), *value as u16),
+                Operand::Immediate16(address) | Operand::Address(address) => {
+                    (format!("&0x{:04x}", address), *address)
+                }
+            };
+            (name, emulator.memory()[address as usize] as u16, 2)
+        }
+    };
+    let hex = |value: u16| match width {
+        1 => value.to_string(),
+        _ => format!("0x{:0width$x}", value, width = width),
+    };
+    let (expected, text) = match &expectation.value {
+        Operand::LabelAddress(label) => {
+            let address =
+                resolve(label).ok_or_else(|| format!("label `{}` is not defined", label))?;
+            (address, format!("*{} ({})", label, hex(address)))
+        }
+        Operand::Immediate8(value) => (*value as u16, hex(*value as u16)),
+        Operand::Immediate16(value) | Operand::Address(value) => (*value, hex(*value)),
+        Operand::Label(label) => return Err(format!("`{}` is not a value", label)),
+    };
+    if expectation.comparison.holds(value, expected) {
+        return Ok(());
+    }
+    Err(format!(
+        "expected {} {} {}, got {}",
+        name,
+        expectation.comparison.symbol(),
+        text,
+        hex(value)
+    ))
+}
+
+/// Runs the tests whose names contain `filter` and prints a summary like `cargo test` does.
+/// Returns whether all of them passed
+pub fn run_tests(
+    tests: &[UnitTest],
+    filter: Option<&str>,
+    assembly: &Assembly,
+    emulator: &mut Emulator,
+    max_steps: u64,
+    output: &mut impl Write,
+) -> Result<bool, Error> {
+    let selected: Vec<&UnitTest> = tests
+        .iter()
+        .filter(|t| filter.is_none_or(|f| t.name.contains(f)))
+        .collect();
+    let plural = if selected.len() == 1 { "" } else { "s" };
+    writeln!(output, "\nrunning {} test{}", selected.len(), plural)?;
+    let mut failed = vec![];
+    for test in &selected {
+        let result = test.run(
+            emulator,
+            |name| assembly.symbol(name).map(|s| s.address),
+            max_steps,
+        );
+        let status = if result.is_ok() { "ok" } else { "FAILED" };
+        writeln!(output, "test {} ... {}", test.name, status)?;
+        if let Err(failures) = result {
+            failed.push((test, failures));
+        }
+    }
+    if !failed.is_empty() {
+        writeln!(output, "\nfailures:\n")?;
+        for (test, failures) in &failed {
+            writeln!(output, "---- {} ({}) ----", test.name, test.location)?;
+            for failure in failures {
+                writeln!(output, "{}", failure)?;
+            }
+            writeln!(output)?;
+        }
+        writeln!(output, "failures:")?;
+        for (test, _) in &failed {
+            writeln!(output, "    {}", test.name)?;
+        }
+    }
+    writeln!(
+        output,
+        "\ntest result: {}. {} passed; {} failed; {} filtered out\n",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        selected.len() - failed.len(),
+        failed.len(),
+        tests.len() - selected.len()
+    )?;
+    Ok(failed.is_empty())
+}
@@ -0,0 +1,152 @@
+//! Execution trace of programs running in the [`Emulator`], one line per instruction:
+//!
+//! ```text
+//! 0x0010 loop              PUSH source HLI          HI 0x00->0x80 LI 0x2a->0x04
+//! 0x001b loop+0xb          PEEK A &HLI              [0x8004] 0x00->0x4e
+//! ```
+
+use std::{io::Write, ops::RangeInclusive};
+
+use anyhow::Error;
+
+use crate::{
+    assembly::Assembly,
+    disassembler::disassemble,
+    emulator::{Cpu, Emulator, Fault, Step},
+};
+
+/// Which instructions are traced. With no ranges and no routines everything is traced
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub ranges: Vec<RangeInclusive<u16>>,
+    pub routines: Vec<u16>, // addresses of routines, traced along with everything they call
+}
+
+impl TraceFilter {
+    pub fn matches(&self, emulator: &Emulator, address: u16) -> bool {
+        if self.ranges.is_empty() && self.routines.is_empty() {
+            return true;
+        }
+        self.ranges.iter().any(|r| r.contains(&address))
+            || emulator
+                .call_stack()
+                .iter()
+                .any(|f| self.routines.contains(&f.target))
+    }
+}
+
+pub struct Tracer<'a> {
+    assembly: &'a Assembly,
+    filter: TraceFilter,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(assembly: &'a Assembly, filter: TraceFilter, output: impl Write + 'a) -> Self {
+        Self {
+            assembly,
+            filter,
+            output: Box::new(output),
+        }
+    }
+
+    /// Executes a single instruction and traces it if it matches the filter
+    pub fn step(&mut self, emulator: &mut Emulator) -> Result<Result<Step, Fault>, Error> {
+        let address = emulator.cpu.pc;
+        // A call is traced as a part of the calling routine, so routines are matched before stepping
+        let traced = self.filter.matches(emulator, address);
+        let before = emulator.cpu.clone();
+        let text = disassemble(emulator.memory(), address)
+            .format(emulator.memory(), |a| self.assembly.label_at(a));
+        let result = emulator.step();
+        let text = match &result {
+            Ok(step) if step.opcode.is_none() => {
+                let handler = emulator.cpu.pc;
+                let label = self.assembly.label_at(handler);
+                format!(
+                    "interrupt -> {}",
+                    label.unwrap_or(format!("0x{:04x}", handler))
+                )
+            }
+            _ => text,
+        };
+        if traced {
+            let location = self.assembly.symbolized(address).unwrap_or_default();
+            let mut changes = register_changes(&before, &emulator.cpu);
+            changes.extend(
+                emulator
+                    .last_writes()
+                    .iter()
+                    .map(|w| format!("[0x{:04x}] 0x{:02x}->0x{:02x}", w.address, w.old, w.new)),
+            );
+            if let Err(fault) = &result {
+                changes.push(format!("fault: {}", fault));
+            }
+            let line = format!(
+                "0x{:04x} {:<16} {:<24} {}",
+                address,
+                location,
+                text,
+                changes.join(" ")
+            );
+            writeln!(self.output, "{}", line.trim_end())?;
+        }
+        Ok(result)
+    }
+}
+
+fn register_changes(before: &Cpu, after: &Cpu) -> Vec<String> {
+    let mut changes = vec![];
+    let stack = |values: &[u8]| {
+        let values: Vec<String> = values.iter().map(|v| format!("0x{:02x}", v)).collect();
+        format!("[{}]", values.join(" "))
+    };
+    if before.a != after.a {
+        changes.push(format!("A {}->{}", stack(&before.a), stack(&after.a)));
+    }
+    if before.b != after.b {
+        changes.push(format!("B {}->{}", stack(&before.b), stack(&after.b)));
+    }
+    let bytes = [
+        ("HI", before.hi, after.hi),
+        ("LI", before.li, after.li),
+        ("EX", before.exit_code, after.exit_code),
+    ];
+    for (name, before, after) in bytes {
+        if before != after {
+            changes.push(format!("{} 0x{:02x}->0x{:02x}", name, before, after));
+        }
+    }
+    if before.flags != after.flags {
+        changes.push(format!("flags {}->{}", flags(before), flags(after)));
+    }
+    let words = [
+        ("SA", before.stack_address, after.stack_address),
+        ("SS", before.stack_size, after.stack_size),
+        ("SP", before.stack_pointer, after.stack_pointer),
+        ("IRA", before.irq_address, after.irq_address),
+    ];
+    for (name, before, after) in words {
+        if before != after {
+            changes.push(format!("{} 0x{:04x}->0x{:04x}", name, before, after));
+        }
+    }
+    changes
+}
+
+/// Set flags, eg. `ZERO|OK`
+fn flags(cpu: &Cpu) -> String {
+    let flags = [
+        ("ZERO", cpu.flags.zero),
+        ("OVF", cpu.flags.overflow),
+        ("ERR", cpu.flags.error),
+        ("OK", cpu.flags.ok),
+        ("IRQ", cpu.flags.irq),
+    ];
+    let set: Vec<&str> = flags.iter().filter(|f| f.1).map(|f| f.0).collect();
+    if set.is_empty() {
+        "-".to_owned()
+    } else {
+        set.join("|")
+    }
+}
@@ -0,0 +1,118 @@
+use nox_asm::{
+    cfg::{ControlFlowGraph, Terminator},
+    Assembler, Assembly, Opcode,
+};
+
+const PROGRAM: &str = "\
+main:
+    PUSH 0x00 A
+    CMP 0x00 A
+    JZE empty
+    CALL print
+    HALT
+empty:
+    CALL print
+    CALL print
+    JMP main
+print:
+    POP A
+    RET OK
+isr:
+    RET OK
+";
+
+fn graph(source: &str) -> (Assembly, ControlFlowGraph) {
+    let mut assembler = Assembler::from_source(source);
+    let assembly = assembler.build(false).unwrap();
+    let graph = assembler.control_flow_graph();
+    (assembly, graph)
+}
+
+fn address(assembly: &Assembly, label: &str) -> u16 {
+    assembly.symbol(label).unwrap().address
+}
+
+#[test]
+fn blocks_end_at_jumps_calls_and_returns() {
+    let (assembly, graph) = graph(PROGRAM);
+    let main = graph.block(0x0000).unwrap();
+    assert_eq!(main.instructions.len(), 3);
+    assert_eq!(
+        main.terminator,
+        Terminator::Branch {
+            opcode: Opcode::JUMP_IF_ZERO,
+            taken: address(&assembly, "empty"),
+            not_taken: main.end,
+        }
+    );
+    let call = graph.block(main.end).unwrap();
+    assert_eq!(
+        call.terminator,
+        Terminator::Call {
+            target: address(&assembly, "print"),
+            return_to: call.end,
+        }
+    );
+    assert_eq!(call.successors(), [call.end]);
+    assert_eq!(graph.block(call.end).unwrap().terminator, Terminator::Halt);
+    let print = graph.block(address(&assembly, "print")).unwrap();
+    assert_eq!(print.terminator, Terminator::Return(Opcode::RETURN_OK));
+    assert!(print.successors().is_empty());
+}
+
+#[test]
+fn routines_do_not_follow_calls() {
+    let (assembly, graph) = graph(PROGRAM);
+    let names: Vec<&str> = graph.routines().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["main", "print", "isr"]);
+    let main = graph.routine(0x0000).unwrap();
+    let print = address(&assembly, "print");
+    assert_eq!(main.calls, [print, print, print]);
+    assert!(!main.blocks.contains(&print));
+    assert!(main.blocks.contains(&address(&assembly, "empty")));
+    // Only reached through its label, eg. as an interrupt handler
+    assert!(graph
+        .routine(address(&assembly, "isr"))
+        .unwrap()
+        .calls
+        .is_empty());
+}
+
+#[test]
+fn procs_are_routines_even_when_jumped_to() {
+    let source = "main:\n    JMP done\n.proc done\n    HALT\n.endproc";
+    let (assembly, graph) = graph(source);
+    assert!(graph.routine(address(&assembly, "done")).is_some());
+}
+
+#[test]
+fn call_graph_counts_call_sites() {
+    let (_, graph) = graph(PROGRAM);
+    let dot = graph.call_graph_to_dot();
+    assert!(
+        dot.contains("\"main\" -> \"print\" [label=\"3\"];"),
+        "{}",
+        dot
+    );
+    let json = graph.call_graph_to_json();
+    assert_eq!(json["calls"][0]["caller"], "main");
+    assert_eq!(json["calls"][0]["callee"], "print");
+    assert_eq!(json["calls"][0]["sites"], 3);
+}
+
+#[test]
+fn cfg_exports_list_blocks_and_edges() {
+    let (assembly, graph) = graph(PROGRAM);
+    let print = address(&assembly, "print");
+    let json = graph.cfg_to_json(&assembly, &[print]);
+    let routines = json["routines"].as_array().unwrap();
+    assert_eq!(routines.len(), 1);
+    let block = &routines[0]["blocks"][0];
+    assert_eq!(block["label"], "print");
+    assert_eq!(block["terminator"], "return");
+    assert_eq!(block["instructions"][0]["text"], "POP A");
+
+    let dot = graph.cfg_to_dot(&assembly, &[]);
+    assert!(dot.starts_with("digraph cfg {"));
+    assert!(dot.contains("[label=\"JZE\"]"), "{}", dot);
+}
@@ -0,0 +1,122 @@
+use nox_asm::Assembler;
+
+const STRLEN: &str = include_str!("../test/strlen.nox");
+
+fn error(source: &str) -> String {
+    match Assembler::from_source(source).build(false) {
+        Ok(_) => panic!("the build should fail"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn strlen_keeps_to_its_contract() {
+    assert!(Assembler::from_source(STRLEN).build(false).is_ok());
+}
+
+#[test]
+fn undeclared_writes_point_at_the_instruction() {
+    let source = STRLEN.replace("uses A, HLI", "uses A");
+    assert_eq!(
+        error(&source),
+        "<source>:23: error: `strlen` writes HI, but does not declare it in `uses`"
+    );
+}
+
+#[test]
+fn calls_write_what_the_callee_leaves_changed() {
+    let source = "\
+main:
+    CALL outer
+    HALT
+.proc outer uses B
+    CALL inner
+    RET OK
+.endproc
+inner:
+    PUSH 0x01 A
+    RET OK
+";
+    let e = error(source);
+    assert!(
+        e.contains("`outer` writes A by calling `inner`, but does not declare it in `uses`"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn preserved_registers_can_only_be_scratch() {
+    let scratch = "\
+.proc scratch uses A, HI preserves B
+    PUSH 0x01 B
+    POP B A
+    POP A HI
+    RET OK
+.endproc
+";
+    assert!(Assembler::from_source(scratch).build(false).is_ok());
+
+    let takes = "\
+.proc takes preserves B
+    POP B A
+    RET OK
+.endproc
+";
+    let e = error(takes);
+    assert!(
+        e.contains(
+            "`takes` does not preserve B: it takes values it did not push or leaves its own behind"
+        ),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn returned_registers_count_as_used() {
+    let source = "\
+.proc two uses B returns A, A
+    PUSH 0x01 A
+    PUSH 0x02 A
+    RET OK
+.endproc
+";
+    assert!(Assembler::from_source(source).build(false).is_ok());
+}
+
+#[test]
+fn procs_only_return_a_and_b() {
+    let e = error(".proc address returns HLI\n    RET OK\n.endproc");
+    assert!(e.contains("`returns` takes A, B or AB"), "{}", e);
+    let e = error(".proc listed A\n    RET OK\n.endproc");
+    assert!(
+        e.contains("syntax error - expected `uses`, `preserves` or `returns`"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn labels_are_local_to_their_proc() {
+    let source = "\
+main:
+    CALL first
+    CALL second
+    JMP second.loop
+.proc first
+loop:
+    JMP loop
+.endproc
+.proc second
+loop:
+    RET OK
+.endproc
+";
+    let assembly = Assembler::from_source(source).build(false).unwrap();
+    let first = assembly.symbol("first.loop").unwrap().address;
+    let second = assembly.symbol("second.loop").unwrap().address;
+    assert_eq!(first, assembly.symbol("first").unwrap().address);
+    assert_eq!(second, assembly.symbol("second").unwrap().address);
+    assert!(assembly.symbol("loop").is_none());
+}
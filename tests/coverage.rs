@@ -0,0 +1,99 @@
+use nox_asm::{
+    coverage::Coverage,
+    emulator::{Emulator, Event},
+    Assembler,
+};
+
+const PROGRAM: &str = "\
+main:
+    PUSH 0x03 LI
+loop:
+    CMP  0x00 LI
+    JZE  done
+    DEC  LI
+    JMP  loop
+done:
+    JER  error
+    HALT
+error:
+    JOK  main
+    HALT";
+
+/// Coverage of `runs` runs of `PROGRAM`, and the assembler for its source lines
+fn coverage(runs: usize) -> (Coverage, Assembler) {
+    let mut assembler = Assembler::from_source(PROGRAM);
+    let assembly = assembler.build(false).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    for _ in 0..runs {
+        emulator.reset();
+        while emulator.step().unwrap().event != Some(Event::Halt) {}
+    }
+    (Coverage::new(assembler.statements(), &emulator), assembler)
+}
+
+#[test]
+fn lcov_has_lines_and_branches() {
+    let (coverage, _) = coverage(1);
+    // `JER` is never taken and `JOK` never reached, so its branches are `-`
+    assert_eq!(
+        coverage.to_lcov(),
+        "\
+TN:
+SF:<source>
+BRDA:5,0,0,1
+BRDA:5,0,1,3
+BRDA:9,0,0,0
+BRDA:9,0,1,1
+BRDA:12,0,0,-
+BRDA:12,0,1,-
+BRF:6
+BRH:3
+DA:2,1
+DA:4,4
+DA:5,4
+DA:6,3
+DA:7,3
+DA:9,1
+DA:10,1
+DA:12,0
+DA:13,0
+LF:9
+LH:7
+end_of_record
+"
+    );
+}
+
+#[test]
+fn coverage_adds_up_over_runs() {
+    let (coverage, _) = coverage(2);
+    let lcov = coverage.to_lcov();
+    assert!(lcov.contains("BRDA:5,0,0,2\nBRDA:5,0,1,6\n"), "{}", lcov);
+    assert!(lcov.contains("DA:4,8\n"), "{}", lcov);
+    assert_eq!(coverage.summary(std::path::Path::new("<source>")), (9, 7));
+}
+
+#[test]
+fn annotated_sources_show_counts_and_branches() {
+    let (coverage, assembler) = coverage(1);
+    assert_eq!(
+        coverage.annotate(assembler.source_lines()),
+        "\
+// <source>: 7/9 lines (77.8%)
+           main:
+        1      PUSH 0x03 LI
+           loop:
+        4      CMP  0x00 LI
+        4      JZE  done  // taken 1, not taken 3
+        3      DEC  LI
+        3      JMP  loop
+           done:
+        1      JER  error  // never taken
+        1      HALT
+           error:
+    #####      JOK  main
+    #####      HALT
+
+"
+    );
+}
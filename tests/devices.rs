@@ -0,0 +1,113 @@
+use std::{
+    cell::RefCell,
+    io::{Cursor, Write},
+    process::{Command, Stdio},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use nox_asm::{
+    assemble_str,
+    emulator::{
+        devices::{Console, Timer},
+        Device, Emulator, Event,
+    },
+};
+
+/// Output of a console that the test can read back
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Waits for the background thread of the console to deliver its input
+fn wait_for_input(console: &mut Console) {
+    let start = Instant::now();
+    while console.read(1) & 1 == 0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "no input received"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn programs_write_to_the_console() {
+    let source = "PUSH 0x68 A\nPOP A &0xfe00\nPUSH 0x69 A\nPOP A &0xfe00\nHALT";
+    let mut emulator = Emulator::new(&assemble_str(source).unwrap().bytes);
+    let output = Output::default();
+    emulator.map_device(0xfe00..=0xfe02, Console::output_only(output.clone()));
+    while emulator.step().unwrap().event != Some(Event::Halt) {}
+    assert_eq!(*output.0.borrow(), b"hi");
+}
+
+#[test]
+fn console_input_is_read_in_order() {
+    let mut console = Console::new(Cursor::new(b"ab".to_vec()), Output::default());
+    wait_for_input(&mut console);
+    assert_eq!(console.read(0), b'a');
+    wait_for_input(&mut console);
+    assert_eq!(console.read(0), b'b');
+    assert_eq!(console.read(1), 0b10);
+    assert_eq!(console.read(0), 0);
+}
+
+#[test]
+fn console_reset_drops_input_and_disables_interrupts() {
+    let mut console = Console::new(Cursor::new(b"x".to_vec()), Output::default());
+    console.write(2, 1);
+    wait_for_input(&mut console);
+    assert!(console.irq());
+    console.reset();
+    assert_eq!(console.read(1), 0b10);
+    assert_eq!(console.read(2), 0);
+    assert!(!console.irq());
+}
+
+#[test]
+fn timer_counts_instructions_and_expires() {
+    let mut timer = Timer::default();
+    timer.write(0, 0x00);
+    timer.write(1, 0x0a);
+    timer.write(4, 0b111); // running, IRQ, restart
+    (0..4).for_each(|_| timer.tick());
+    assert_eq!((timer.read(2), timer.read(3)), (0x00, 0x06));
+    (0..6).for_each(|_| timer.tick());
+    assert_eq!(timer.read(5), 1);
+    assert!(timer.irq());
+    assert_eq!(timer.read(3), 0x0a); // restarted
+    timer.write(5, 0);
+    assert!(!timer.irq());
+    timer.reset();
+    assert_eq!(timer.read(4), 0);
+    assert_eq!(timer.read(1), 0);
+}
+
+#[test]
+fn devices_past_the_end_of_memory_are_rejected() {
+    for (option, size) in [("--uart", 3), ("--timer", 6)] {
+        let output = Command::new(env!("CARGO_BIN_EXE_nox_asm"))
+            .args(["run", "-i", "test/strlen.nox", option, "0xfffe"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            format!(
+                "> {} 0xfffe: the device takes {} bytes and does not fit in memory\n",
+                option, size
+            )
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use nox_asm::{
+    assemble_str,
+    emulator::{devices::Timer, Emulator, Event, Fault, Register, Step},
+    Opcode,
+};
+
+fn load(source: &str) -> Emulator {
+    Emulator::new(&assemble_str(source).unwrap().bytes)
+}
+
+/// Runs until `HALT`, returning the steps
+fn run(emulator: &mut Emulator) -> Vec<Step> {
+    let mut steps = vec![];
+    loop {
+        let step = emulator.step().unwrap();
+        steps.push(step.clone());
+        if step.event == Some(Event::Halt) {
+            return steps;
+        }
+    }
+}
+
+#[test]
+fn a_and_b_are_stacks() {
+    let mut emulator = load("PUSH 0x01 A\nPUSH 0x02 A\nPUSH 0x03 B\nPOP A B\nHALT");
+    run(&mut emulator);
+    assert_eq!(emulator.cpu.a, [0x01]);
+    assert_eq!(emulator.cpu.b, [0x03, 0x02]);
+}
+
+#[test]
+fn arithmetic_pushes_the_result_and_sets_flags() {
+    let mut emulator = load("PUSH 0xff A\nADD 0x01 A\nHALT");
+    run(&mut emulator);
+    assert_eq!(emulator.cpu.a, [0xff, 0x00]);
+    assert!(emulator.cpu.flags.zero);
+    assert!(emulator.cpu.flags.overflow);
+
+    let mut emulator = load("PUSH 0x05 A\nSUB 0x03 A\nHALT");
+    run(&mut emulator);
+    assert_eq!(emulator.cpu.a, [0x05, 0x02]);
+    assert!(!emulator.cpu.flags.zero);
+    assert!(!emulator.cpu.flags.overflow);
+}
+
+#[test]
+fn compare_sets_zero_and_overflow_if_the_register_is_greater() {
+    let compare = |a: u8, value: u8| {
+        let mut emulator = load(&format!("PUSH 0x{:02x} A\nCMP 0x{:02x} A\nHALT", a, value));
+        run(&mut emulator);
+        assert_eq!(emulator.cpu.a, [a]);
+        (emulator.cpu.flags.zero, emulator.cpu.flags.overflow)
+    };
+    assert_eq!(compare(0x05, 0x05), (true, false));
+    assert_eq!(compare(0x05, 0x03), (false, true));
+    assert_eq!(compare(0x03, 0x05), (false, false));
+}
+
+#[test]
+fn ret_restores_the_caller_and_sets_the_exit_code() {
+    let mut emulator = load(
+        "PUSH 0x1234 HLI\nCALL routine\nPUSH EX B\nHALT\nroutine:\nPUSH 0x5678 HLI\nRET ERR 0x07",
+    );
+    run(&mut emulator);
+    assert_eq!(emulator.cpu.hli(), 0x1234);
+    assert_eq!(emulator.cpu.exit_code, 0x07);
+    assert!(emulator.cpu.flags.error);
+    assert!(!emulator.cpu.flags.ok);
+    assert_eq!(emulator.cpu.b, [0x07]);
+    assert_eq!(emulator.call_depth(), 0);
+}
+
+#[test]
+fn steps_are_the_executed_instructions() {
+    let mut emulator = load("NOOP\nPUSH 0x01 A\nHALT");
+    let steps: Vec<(u16, Option<Opcode>)> = run(&mut emulator)
+        .iter()
+        .map(|s| (s.address, s.opcode))
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            (0x0000, Some(Opcode::NOOP)),
+            (0x0001, Some(Opcode::PUSH_IMMEDIATE_A)),
+            (0x0003, Some(Opcode::HALT))
+        ]
+    );
+}
+
+#[test]
+fn writes_go_to_memory_unless_protected() {
+    let source = "PUSH 0x42 A\nPOP A &0x8000\nPUSH 0x43 A\nPOP A &0x0100\nHALT";
+    let mut emulator = load(source);
+    run(&mut emulator);
+    assert_eq!(emulator.memory()[0x8000], 0x42);
+    assert_eq!(emulator.memory()[0x0100], 0x43);
+
+    let mut emulator = load(source);
+    emulator.protect(0x0000..=0x7fff);
+    emulator.step().unwrap();
+    emulator.step().unwrap();
+    emulator.step().unwrap();
+    let fault = emulator.step().unwrap_err();
+    assert_eq!(
+        fault,
+        Fault::RomWrite {
+            address: 0x0007,
+            target: 0x0100
+        }
+    );
+}
+
+#[test]
+fn faults_are_errors() {
+    let mut emulator = load("POP A");
+    let fault = emulator.step().unwrap_err();
+    assert_eq!(
+        fault,
+        Fault::EmptyRegister {
+            address: 0x0000,
+            register: Register::A
+        }
+    );
+    assert_eq!(fault.to_string(), "register A is empty at 0x0000");
+
+    let mut emulator = load("HALT");
+    run(&mut emulator);
+    assert_eq!(emulator.step().unwrap_err(), Fault::Halted);
+}
+
+#[test]
+fn reset_reloads_the_image() {
+    let mut emulator = load("PUSH 0x42 A\nPOP A &0x0000\nHALT");
+    run(&mut emulator);
+    assert_eq!(emulator.memory()[0x0000], 0x42);
+    emulator.reset();
+    assert_ne!(emulator.memory()[0x0000], 0x42);
+    assert_eq!(emulator.cpu.pc, 0x0000);
+    assert!(!emulator.cpu.halted);
+}
+
+/// Sets up `handler` and enables interrupts before `body`
+const HANDLER: &str = "\
+    PUSH *handler AB
+    POP  AB IRA
+    SET  IRQ
+";
+
+fn interrupts(steps: &[Step]) -> Vec<Event> {
+    steps
+        .iter()
+        .filter_map(|s| s.event)
+        .filter(|e| matches!(e, Event::Interrupt { .. }))
+        .collect()
+}
+
+#[test]
+fn requested_interrupts_enter_the_handler() {
+    let source = format!(
+        "{}continue:\n    NOOP\n    HALT\nhandler:\n    PUSH 0x2a B\n    RET OK",
+        HANDLER
+    );
+    let assembly = assemble_str(&source).unwrap();
+    let address = |name| assembly.symbol(name).unwrap().address;
+    let mut emulator = Emulator::new(&assembly.bytes);
+    emulator.request_interrupt();
+    assert!(emulator.irq_pending());
+    // Taken after `SET IRQ`, before the instruction at `continue`
+    (0..3).for_each(|_| {
+        emulator.step().unwrap();
+    });
+    let step = emulator.step().unwrap();
+    assert_eq!(step.opcode, None);
+    assert_eq!(
+        step.event,
+        Some(Event::Interrupt {
+            target: address("handler"),
+            return_address: address("continue")
+        })
+    );
+    assert_eq!(emulator.cpu.pc, address("handler"));
+    assert!(!emulator.cpu.flags.irq);
+    assert!(!emulator.irq_pending());
+    assert!(emulator.call_stack()[0].interrupt);
+
+    let steps = run(&mut emulator);
+    assert_eq!(steps[2].address, address("continue"));
+    assert!(interrupts(&steps).is_empty()); // the request was taken once
+    assert_eq!(emulator.cpu.b, [0x2a]);
+    assert!(emulator.cpu.flags.irq);
+    assert_eq!(emulator.call_depth(), 0);
+}
+
+#[test]
+fn masked_requests_stay_pending() {
+    let source = format!(
+        "{}    CLR  IRQ\n    NOOP\n    NOOP\n    SET  IRQ\n    HALT\nhandler:\n    RET OK",
+        HANDLER
+    );
+    let mut emulator = load(&source);
+    (0..4).for_each(|_| {
+        emulator.step().unwrap();
+    });
+    emulator.request_interrupt();
+    // Not taken during the NOOPs after `CLR IRQ`
+    for _ in 0..3 {
+        let step = emulator.step().unwrap();
+        assert!(step.opcode.is_some());
+        assert!(emulator.irq_pending());
+    }
+    let steps = run(&mut emulator);
+    assert_eq!(interrupts(&steps).len(), 1);
+    assert!(matches!(steps[0].event, Some(Event::Interrupt { .. })));
+    assert!(!emulator.irq_pending());
+
+    // A halted CPU does not take it
+    let mut emulator = load(&format!("{}    HALT\nhandler:\n    RET OK", HANDLER));
+    run(&mut emulator);
+    emulator.request_interrupt();
+    assert_eq!(emulator.step().unwrap_err(), Fault::Halted);
+}
+
+#[test]
+fn handlers_restore_the_flags_and_keep_the_exit_code() {
+    // `routine` sets OK and the exit code, the handler sets ZER, OVF, ERR and another code
+    let source = format!(
+        "{}    CALL routine
+    PUSH 0x01 A
+    CMP  0x01 A
+    NOOP
+    HALT
+routine:
+    RET  OK 0x05
+handler:
+    PUSH 0x00 A
+    ADD  0xff A
+    ADD  0x01 A
+    POP  A
+    POP  A
+    POP  A
+    RET  ERR 0x07",
+        HANDLER
+    );
+    let mut emulator = load(&source);
+    (0..7).for_each(|_| {
+        emulator.step().unwrap();
+    });
+    let flags = emulator.cpu.flags;
+    assert!(flags.zero && flags.ok && flags.irq);
+    assert!(!flags.overflow && !flags.error);
+    emulator.request_interrupt();
+    let steps = run(&mut emulator);
+    assert_eq!(interrupts(&steps).len(), 1);
+    assert_eq!(emulator.cpu.flags, flags);
+    assert_eq!(emulator.cpu.exit_code, 0x05);
+    assert_eq!(emulator.cpu.a, [0x01]);
+}
+
+#[test]
+fn timer_interrupts_the_program() {
+    let assembly = assemble_str(include_str!("../test/interrupts.nox")).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    emulator.map_device(0xfe10..=0xfe10 + Timer::SIZE - 1, Timer::default());
+    let steps = run(&mut emulator);
+    let tick = assembly.symbol("tick").unwrap().address;
+    let interrupts = interrupts(&steps);
+    assert_eq!(interrupts.len(), 3);
+    assert!(interrupts
+        .iter()
+        .all(|i| matches!(i, Event::Interrupt { target, .. } if *target == tick)));
+    let ticks = assembly.symbol("ticks").unwrap().address;
+    assert_eq!(emulator.memory()[ticks as usize], 3);
+    assert!(!emulator.cpu.flags.irq);
+}
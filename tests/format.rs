@@ -0,0 +1,63 @@
+use std::{fs, path::PathBuf};
+
+use nox_asm::{format::format, Assembler};
+
+/// Fixtures kept the way they were written, they are only checked to keep their bytes
+const UNFORMATTED: [&str; 1] = ["possible_variants.nox"];
+
+fn samples() -> Vec<PathBuf> {
+    let mut samples: Vec<_> = fs::read_dir("test")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "nox"))
+        .collect();
+    samples.sort();
+    samples
+}
+
+#[test]
+fn samples_are_formatted() {
+    let formatted = samples()
+        .into_iter()
+        .filter(|path| !UNFORMATTED.iter().any(|name| path.ends_with(name)));
+    for path in formatted {
+        let source = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            format(&source),
+            source,
+            "{} is not formatted",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn formatting_keeps_the_bytes() {
+    let source = "main:\npush 0XFF a   // comment\n  pop a &0X8000\n\n\n$ 0XAB \"text\"\nhalt\n";
+    let formatted = format(source);
+    assert_eq!(
+        formatted,
+        "main:\n    PUSH 0xff A // comment\n    POP  A    &0x8000\n\n$ 0xab \"text\"\n    HALT\n"
+    );
+    let bytes = |source: &str| Assembler::from_source(source).assemble(false).unwrap();
+    assert_eq!(bytes(&formatted), bytes(source));
+    assert_eq!(format(&formatted), formatted);
+}
+
+#[test]
+fn fixtures_keep_their_bytes() {
+    for name in UNFORMATTED {
+        let source = fs::read_to_string(PathBuf::from("test").join(name)).unwrap();
+        let bytes = |source: &str| Assembler::from_source(source).assemble(false).unwrap();
+        assert_eq!(bytes(&format(&source)), bytes(&source), "{}", name);
+    }
+}
+
+#[test]
+fn only_code_under_labels_is_indented() {
+    let source = "NOOP\nmain:\nHALT\n.section rodata\nNOOP\n.proc p\nRET OK\n.endproc\nNOOP\n";
+    assert_eq!(
+        format(source),
+        "NOOP\nmain:\n    HALT\n.section rodata\nNOOP\n.proc p\n    RET OK\n.endproc\nNOOP\n"
+    );
+}
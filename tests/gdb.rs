@@ -0,0 +1,168 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+use nox_asm::{assemble_str, emulator::Emulator, gdb::GdbServer};
+
+const PROGRAM: &str = "\
+main:
+    PUSH 0x01 A
+    PUSH 0x02 A
+    POP  A value
+    HALT
+value:
+    .reserve 0x0001
+";
+
+struct Client {
+    stream: TcpStream,
+    server: Option<JoinHandle<()>>,
+}
+
+impl Client {
+    fn connect(source: &str) -> Self {
+        let bytes = assemble_str(source).unwrap().bytes;
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            // The emulator is not `Send`, so it is made on the server thread
+            GdbServer::new(Emulator::new(&bytes), false)
+                .serve(stream)
+                .unwrap();
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        Self {
+            stream,
+            server: Some(server),
+        }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    /// Sends a raw frame and returns the acknowledgement
+    fn send_raw(&mut self, frame: &[u8]) -> u8 {
+        self.stream.write_all(frame).unwrap();
+        self.byte()
+    }
+
+    /// Reads a reply, checks its checksum and acknowledges it
+    fn receive(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = vec![];
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.byte(), self.byte()];
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(
+            data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)),
+            checksum
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        assert_eq!(self.send_raw(&frame(packet.as_bytes())), b'+', "{}", packet);
+        self.receive()
+    }
+
+    fn detach(mut self) {
+        assert_eq!(self.request("D"), "OK");
+        self.server.take().unwrap().join().unwrap();
+    }
+}
+
+fn frame(data: &[u8]) -> Vec<u8> {
+    let checksum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    let mut frame = vec![b'$'];
+    frame.extend_from_slice(data);
+    frame.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+    frame
+}
+
+#[test]
+fn packets_are_checked_and_unescaped() {
+    let mut client = Client::connect(PROGRAM);
+    // A wrong checksum is rejected, the resent packet is accepted
+    assert_eq!(client.send_raw(b"$m0,2#00"), b'-');
+    assert_eq!(client.request("m0,2"), "0101");
+    // `}` escapes the next byte: `}` 0x10 is `0`
+    assert_eq!(client.send_raw(&frame(b"m}\x10,2")), b'+');
+    assert_eq!(client.receive(), "0101");
+    // Acks and interrupts between packets are skipped
+    client.stream.write_all(b"+\x03").unwrap();
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    client.detach();
+}
+
+#[test]
+fn registers_are_read_and_written() {
+    let mut client = Client::connect(PROGRAM);
+    // pc, a, b, hi, li, ex, flags, sa, ss, sp, ira
+    assert_eq!(client.request("g"), "0000000000000000f0000ff0f0000000");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("g"), "0002010000000000f0000ff0f0000000");
+    assert_eq!(client.request("G0004aa0b0c0d0e01f0000ff0f0001234"), "OK");
+    assert_eq!(client.request("g"), "0004aa0b0c0d0e01f0000ff0f0001234");
+    assert_eq!(client.request("p3"), "0c");
+    assert_eq!(client.request("P0=0007"), "OK");
+    assert_eq!(client.request("p0"), "0007");
+    assert_eq!(client.request("pb"), "E01");
+    assert_eq!(client.request("G00"), "E01");
+    client.detach();
+}
+
+#[test]
+fn memory_is_read_and_written() {
+    let mut client = Client::connect(PROGRAM);
+    assert_eq!(client.request("m0,9"), "010101021100088d00");
+    assert_eq!(client.request("M8,1:2a"), "OK");
+    assert_eq!(client.request("m7,2"), "8d2a");
+    assert_eq!(client.request("m7"), "E01");
+    client.detach();
+}
+
+#[test]
+fn execution_stops_at_breakpoints_and_watchpoints() {
+    let mut client = Client::connect(PROGRAM);
+    assert_eq!(client.request("Z0,4,1"), "OK");
+    assert_eq!(client.request("Z2,8,1"), "OK");
+    assert_eq!(client.request("c"), "T05swbreak:;");
+    assert_eq!(client.request("p0"), "0004");
+    assert_eq!(client.request("c"), "T05watch:0008;");
+    assert_eq!(client.request("m8,1"), "02");
+    assert_eq!(client.request("s"), "W00");
+    client.detach();
+
+    // Removed points don't stop, `c addr` continues at `addr`
+    let mut client = Client::connect(PROGRAM);
+    assert_eq!(client.request("Z0,4,1"), "OK");
+    assert_eq!(client.request("z0,4,1"), "OK");
+    assert_eq!(client.request("Z3,8,1"), "");
+    assert_eq!(client.request("c2"), "W00");
+    assert_eq!(client.request("m8,1"), "02");
+    // `PUSH 0x01 A` was skipped, so A is empty
+    assert_eq!(client.request("p1"), "00");
+    client.detach();
+}
+
+#[test]
+fn faults_are_signals() {
+    let mut client = Client::connect(PROGRAM);
+    assert_eq!(client.request("M0,1:ff"), "OK");
+    assert_eq!(client.request("c"), "S04");
+    client.detach();
+}
@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use nox_asm::{
+    assemble_str, Assembler, Assembly, Diagnostic, Location, MemoryFileSystem, Severity,
+};
+
+fn build(files: MemoryFileSystem) -> Result<Assembly, Error> {
+    Assembler::with_file_system(Path::new("src/main.nox"), files).build(false)
+}
+
+/// The diagnostic of a failed build
+fn error(files: MemoryFileSystem) -> Diagnostic {
+    match build(files) {
+        Ok(_) => panic!("the build should fail"),
+        Err(e) => e.downcast::<Diagnostic>().unwrap(),
+    }
+}
+
+fn location(file: &str, line: usize) -> Option<Location> {
+    Some(Location {
+        file: PathBuf::from(file),
+        line,
+    })
+}
+
+#[test]
+fn includes_are_assembled_in_place() {
+    // Paths are relative to the including file
+    let files = MemoryFileSystem::new()
+        .with_file(
+            "src/main.nox",
+            "main:\n    CALL sum\n.include \"lib/math.nox\"\n    HALT",
+        )
+        .with_file("src/lib/math.nox", "sum:\n.include \"add.nox\"\n    RET OK")
+        .with_file("src/lib/add.nox", "    ADD A B");
+    let assembly = build(files).unwrap();
+    let inline = assemble_str("main:\n    CALL sum\nsum:\n    ADD A B\n    RET OK\n    HALT");
+    assert_eq!(assembly.bytes, inline.unwrap().bytes);
+    assert_eq!(assembly.symbol("sum").unwrap().address, 0x0003);
+
+    // The source map points into the included files
+    let lines: Vec<Option<Location>> = [0x0000, 0x0003, 0x0004]
+        .iter()
+        .map(|address| {
+            assembly
+                .source_map
+                .lookup(*address)
+                .map(|e| e.location.clone())
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            location("src/main.nox", 2),
+            location("src/lib/add.nox", 1),
+            location("src/lib/math.nox", 3)
+        ]
+    );
+}
+
+#[test]
+fn include_cycles_are_errors() {
+    let files = MemoryFileSystem::new().with_file("src/main.nox", ".include \"main.nox\"");
+    let diagnostic = error(files);
+    assert_eq!(diagnostic.message, "src/main.nox includes itself");
+    assert_eq!(diagnostic.location, location("src/main.nox", 1));
+
+    let files = MemoryFileSystem::new()
+        .with_file("src/main.nox", "NOOP\n.include \"a.nox\"")
+        .with_file("src/a.nox", ".include \"b.nox\"")
+        .with_file("src/b.nox", "NOOP\n.include \"a.nox\"");
+    assert_eq!(
+        error(files).to_string(),
+        "src/b.nox:2: error: src/a.nox includes itself"
+    );
+
+    // Including a file sum is not a cycle
+    let files = MemoryFileSystem::new()
+        .with_file("src/main.nox", ".include \"a.nox\"\n.include \"a.nox\"")
+        .with_file("src/a.nox", "NOOP");
+    assert_eq!(build(files).unwrap().bytes[..2], [0x00, 0x00]);
+}
+
+#[test]
+fn include_errors_point_at_the_include() {
+    let files = MemoryFileSystem::new().with_file("src/main.nox", "NOOP\n.include \"missing.nox\"");
+    assert_eq!(
+        error(files).to_string(),
+        "src/main.nox:2: error: file src/missing.nox does not exist"
+    );
+    let files = MemoryFileSystem::new().with_file("src/main.nox", ".include missing.nox");
+    assert_eq!(
+        error(files).to_string(),
+        "src/main.nox:1: error: syntax error - .include requires a quoted path"
+    );
+}
+
+#[test]
+fn diagnostics_have_the_file_and_line_of_included_code() {
+    let files = MemoryFileSystem::new()
+        .with_file("src/main.nox", ".include \"lib/a.nox\"\n    HALT")
+        .with_file("src/lib/a.nox", "    NOOP\n    PUSH 0x01 Q");
+    let diagnostic = error(files);
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert_eq!(diagnostic.location, location("src/lib/a.nox", 2));
+    assert_eq!(
+        diagnostic.to_string(),
+        "src/lib/a.nox:2: error: syntax error"
+    );
+
+    let files = MemoryFileSystem::new()
+        .with_file("src/main.nox", ".include \"lib/a.nox\"\n    HALT")
+        .with_file("src/lib/a.nox", "    NOOP\n    JMP nowhere");
+    let warnings = build(files).unwrap().diagnostics;
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].severity, Severity::Warning);
+    assert_eq!(warnings[0].location, location("src/lib/a.nox", 2));
+    assert_eq!(
+        warnings[0].message,
+        "label `nowhere` is not defined, 0x0000 is used instead"
+    );
+}
@@ -0,0 +1,198 @@
+use nox_asm::{
+    ir::{
+        Comparison, Data, Directive, Instruction, Label, Operand, Proc, Register, Statement,
+        StatementKind,
+    },
+    lint::Rule,
+    Assembler, Opcode,
+};
+
+const PROGRAM: &str = "\
+.export main
+.stack_size 0x0100
+main:
+    PUSH 0x01 A
+    PUSH 0x1234 HLI
+    PUSH *message HLI
+    POP A &0x8000
+    JMP main
+.lint disable unreachable
+.proc helper uses A
+    RET OK
+.endproc
+.section rodata
+message:
+$ \"hi\"
+$ 0x01 0x0203
+.section bss
+> 0x8100
+buffer:
+.reserve 0x0004
+";
+
+fn statements() -> Vec<Statement> {
+    let mut assembler = Assembler::from_source(PROGRAM);
+    assembler.build(false).unwrap();
+    assembler.statements().to_vec()
+}
+
+fn instruction(opcode: Opcode, operands: Vec<Operand>) -> StatementKind {
+    StatementKind::Instruction(Instruction { opcode, operands })
+}
+
+fn label(name: &str) -> StatementKind {
+    StatementKind::Label(Label {
+        name: name.to_owned(),
+    })
+}
+
+#[test]
+fn lines_are_parsed_into_statements() {
+    let kinds: Vec<StatementKind> = statements().into_iter().map(|s| s.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            StatementKind::Directive(Directive::Export(vec!["main".to_owned()])),
+            StatementKind::Directive(Directive::StackSize(0x0100)),
+            label("main"),
+            instruction(Opcode::PUSH_IMMEDIATE_A, vec![Operand::Immediate8(0x01)]),
+            instruction(
+                Opcode::PUSH_IMMEDIATE_HLI,
+                vec![Operand::Immediate16(0x1234)]
+            ),
+            instruction(
+                Opcode::PUSH_IMMEDIATE_HLI,
+                vec![Operand::LabelAddress("message".to_owned())]
+            ),
+            instruction(Opcode::POP_A_ABSOLUTE, vec![Operand::Address(0x8000)]),
+            instruction(Opcode::JUMP, vec![Operand::Label("main".to_owned())]),
+            StatementKind::Directive(Directive::Lint {
+                enable: false,
+                rules: vec![Rule::Unreachable]
+            }),
+            StatementKind::Directive(Directive::Proc(Proc {
+                name: "helper".to_owned(),
+                uses: Some(vec![Register::A]),
+                preserves: vec![],
+                returns: vec![],
+            })),
+            instruction(Opcode::RETURN_OK, vec![]),
+            StatementKind::Directive(Directive::EndProc),
+            StatementKind::Directive(Directive::Section("rodata".to_owned())),
+            label("message"),
+            StatementKind::Data(Data {
+                values: vec![
+                    Operand::Immediate8(b'h'),
+                    Operand::Immediate8(b'i'),
+                    Operand::Immediate8(0)
+                ]
+            }),
+            StatementKind::Data(Data {
+                values: vec![Operand::Immediate8(0x01), Operand::Immediate16(0x0203)]
+            }),
+            StatementKind::Directive(Directive::Section("bss".to_owned())),
+            StatementKind::Directive(Directive::Origin(0x8100)),
+            label("buffer"),
+            StatementKind::Directive(Directive::Reserve(4)),
+        ]
+    );
+}
+
+#[test]
+fn statements_have_their_line_address_and_size() {
+    let placed: Vec<(usize, u16, u16)> = statements()
+        .iter()
+        .map(|s| (s.span.line, s.address, s.size()))
+        .collect();
+    assert_eq!(
+        placed,
+        [
+            (1, 0x0000, 0),
+            (2, 0x0000, 0),
+            (3, 0x0000, 0),
+            (4, 0x0000, 2),
+            (5, 0x0002, 3),
+            (6, 0x0005, 3),
+            (7, 0x0008, 3),
+            (8, 0x000b, 3),
+            (9, 0x000e, 0),
+            (10, 0x000e, 0),
+            (11, 0x000e, 1),
+            (12, 0x000f, 0),
+            (13, 0x000f, 0),
+            (14, 0x000f, 0),
+            (15, 0x000f, 3),
+            (16, 0x0012, 3),
+            (17, 0x8000, 0),
+            (18, 0x8100, 0),
+            (19, 0x8100, 0),
+            (20, 0x8100, 4),
+        ]
+    );
+}
+
+#[test]
+fn statements_are_encoded_with_resolved_labels() {
+    let statements = statements();
+    let resolve = |name: &str| (name == "message").then_some(0x000f);
+    let operands: Vec<(u16, Operand)> = statements[5]
+        .operands()
+        .map(|(address, operand)| (address, operand.clone()))
+        .collect();
+    assert_eq!(
+        operands,
+        [(0x0006, Operand::LabelAddress("message".to_owned()))]
+    );
+    assert_eq!(
+        statements[5].encode(&resolve),
+        [Opcode::PUSH_IMMEDIATE_HLI as u8, 0x00, 0x0f]
+    );
+    // Labels that cannot be resolved are 0x0000
+    assert_eq!(
+        statements[7].encode(&resolve),
+        [Opcode::JUMP as u8, 0x00, 0x00]
+    );
+    assert_eq!(statements[15].encode(&resolve), [0x01, 0x02, 0x03]);
+    let data: Vec<u16> = statements[15]
+        .operands()
+        .map(|(address, _)| address)
+        .collect();
+    assert_eq!(data, [0x0012, 0x0013]);
+    assert_eq!(statements[19].encode(&resolve), [0x00; 4]);
+    assert!(statements[0].encode(&resolve).is_empty());
+}
+
+#[test]
+fn statements_name_their_labels_and_instructions() {
+    let statements = statements();
+    let labels: Vec<&str> = statements.iter().filter_map(Statement::label).collect();
+    assert_eq!(labels, ["main", "helper", "message", "buffer"]);
+    assert_eq!(
+        statements[10].instruction().map(|i| i.opcode),
+        Some(Opcode::RETURN_OK)
+    );
+    assert_eq!(statements[9].instruction(), None);
+    assert_eq!(Operand::LabelAddress("x".to_owned()).label(), Some("x"));
+    assert_eq!(Operand::Address(0x8000).label(), None);
+}
+
+#[test]
+fn register_names_and_comparisons() {
+    assert_eq!(
+        Register::from_name("hli"),
+        Some(&[Register::HI, Register::LI][..])
+    );
+    assert_eq!(
+        Register::from_name("AB"),
+        Some(&[Register::A, Register::B][..])
+    );
+    assert_eq!(Register::from_name("EX"), None);
+    assert_eq!(
+        Comparison::split("<= 120"),
+        Some((Comparison::LessOrEqual, "120"))
+    );
+    assert_eq!(Comparison::split("< 120"), Some((Comparison::Less, "120")));
+    assert_eq!(Comparison::split("= 1"), None);
+    assert!(Comparison::GreaterOrEqual.holds(3, 3));
+    assert!(!Comparison::NotEqual.holds(3, 3));
+}
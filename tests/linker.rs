@@ -0,0 +1,138 @@
+use nox_asm::{linker::DEFAULT_LINKER_SCRIPT, Assembler, LinkerScript, Opcode};
+
+const SECTIONS: &str = include_str!("../test/sections.nox");
+
+fn address(assembly: &nox_asm::Assembly, label: &str) -> u16 {
+    assembly.symbol(label).unwrap().address
+}
+
+#[test]
+fn default_layout_groups_sections() {
+    let assembly = Assembler::from_source(SECTIONS).build(false).unwrap();
+    // Code from both `.section code` blocks comes first in ROM, then the read-only data
+    assert_eq!(address(&assembly, "main"), 0x0000);
+    assert_eq!(address(&assembly, "print"), 0x0007);
+    assert_eq!(address(&assembly, "greeting"), 0x000c);
+    assert_eq!(&assembly.bytes[0x000c..0x0018], b"Hello, Nox!\0");
+    assert_eq!(address(&assembly, "buffer"), 0x8000);
+    assert_eq!(address(&assembly, "counter"), 0x8020);
+    assert_eq!(address(&assembly, "reset_vector"), 0xfff0);
+}
+
+#[test]
+fn custom_script_moves_sections() {
+    let script: LinkerScript = "\
+        MEMORY ROM 0x1000 0x1fff\n\
+        MEMORY RAM 0x4000 0x4fff // variables\n\
+        MEMORY VECTORS 0xfff0 0xffff\n\
+        SECTION rodata ROM\n\
+        SECTION code ROM\n\
+        SECTION bss RAM NOLOAD\n\
+        SECTION vectors VECTORS\n"
+        .parse()
+        .unwrap();
+    let assembly = Assembler::from_source(SECTIONS)
+        .with_linker_script(script)
+        .build(false)
+        .unwrap();
+    assert_eq!(address(&assembly, "greeting"), 0x1000);
+    assert_eq!(address(&assembly, "main"), 0x100c);
+    assert_eq!(address(&assembly, "buffer"), 0x4000);
+    // `PUSH *greeting HLI` is relocated to the new address
+    assert_eq!(&assembly.bytes[0x100d..0x100f], [0x10, 0x00]);
+}
+
+#[test]
+fn overflowing_a_region_is_an_error() {
+    let script: LinkerScript = "MEMORY ROM 0x0000 0x0003\nSECTION code ROM"
+        .parse()
+        .unwrap();
+    let error = Assembler::from_source("NOOP\nNOOP\nNOOP\nNOOP\nNOOP")
+        .with_linker_script(script)
+        .build(false)
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("section `code` overflows region ROM (0x0000-0x0003) by 1 bytes"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn sections_missing_from_the_script_are_an_error() {
+    let error = Assembler::from_source(".section fast\nNOOP")
+        .build(false)
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("section `fast` is not placed by the linker script"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn sections_are_placed_around_origins() {
+    let source = "\
+.section code
+main:
+    NOOP
+    NOOP
+    NOOP
+    NOOP
+    HALT
+.section rodata
+table:
+$ 0x01 0x02
+> 0x0003
+marker:
+$ 0xff
+";
+    let assembly = Assembler::from_source(source).build(false).unwrap();
+    // `main` does not fit below `marker` and goes after it, `table` fills the gap
+    assert_eq!(address(&assembly, "marker"), 0x0003);
+    assert_eq!(address(&assembly, "main"), 0x0004);
+    assert_eq!(address(&assembly, "table"), 0x0000);
+    assert_eq!(
+        &assembly.bytes[0x0000..0x0005],
+        [0x01, 0x02, 0x00, 0xff, 0x00]
+    );
+    assert_eq!(assembly.bytes[0x0008], Opcode::HALT as u8);
+}
+
+#[test]
+fn overlapping_origins_are_an_error() {
+    let error = Assembler::from_source("> 0x0010\nNOOP\nNOOP\n> 0x0011\nNOOP")
+        .build(false)
+        .unwrap_err();
+    assert!(error.to_string().contains("overlaps"), "{}", error);
+}
+
+#[test]
+fn script_errors_name_the_line() {
+    let parse = |script: &str| script.parse::<LinkerScript>().unwrap_err().to_string();
+    assert_eq!(
+        parse("MEMORY ROM 0x0000 0x7fff\nSECTION code RAM"),
+        "linker script line 2: unknown memory region RAM"
+    );
+    assert_eq!(
+        parse("MEMORY ROM 0x1000 0x0fff"),
+        "linker script line 1: region ROM ends before it starts"
+    );
+    assert_eq!(
+        parse("MEMORY ROM 0x0000 0x7fff\nSECTION code ROM FAST"),
+        "linker script line 2: unknown section flags: FAST"
+    );
+}
+
+#[test]
+fn default_script_is_the_documented_one() {
+    let script = LinkerScript::default();
+    assert_eq!(script, DEFAULT_LINKER_SCRIPT.parse().unwrap());
+    assert_eq!(script.region_of(0x8000).unwrap().name, "RAM");
+    assert!(script.section("bss").unwrap().noload);
+    assert_eq!(script.region("ROM").unwrap().size(), 0x8000);
+}
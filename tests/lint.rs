@@ -0,0 +1,128 @@
+use nox_asm::{
+    lint::{LintConfig, Rule},
+    Assembler,
+};
+
+const LINT: &str = include_str!("../test/lint.nox");
+
+/// Warnings as `line: message [rule]`
+fn lint(source: &str, config: &LintConfig) -> Vec<String> {
+    let mut assembler = Assembler::from_source(source).with_tests();
+    let mut warnings = assembler.build(false).unwrap().diagnostics;
+    warnings.extend(assembler.lint(config));
+    warnings
+        .iter()
+        .map(|w| format!("{}: {}", w.location.as_ref().unwrap().line, w.message))
+        .collect()
+}
+
+fn rules(warnings: &[String]) -> Vec<&str> {
+    warnings
+        .iter()
+        .map(|w| &w[w.rfind('[').unwrap() + 1..w.len() - 1])
+        .collect()
+}
+
+#[test]
+fn sample_triggers_every_rule_once() {
+    let warnings = lint(LINT, &LintConfig::default());
+    assert_eq!(
+        warnings,
+        [
+            "6: label `unused` is never referenced [unused-label]",
+            "12: `POP A S 0x00` moves no bytes [empty-stack-move]",
+            "13: `spin` never reaches RET [call-without-ret]",
+            "16: unreachable code after HALT [unreachable]",
+            "20: paths meet with different A/B stack depths in `spin`: A +0, B +0 and A +1, B +0 \
+             [unbalanced-merge]",
+            "24: JZE can be reached without an instruction setting ZER before it [unset-flag]",
+            "26: writes to ROM at `table` [rom-write]",
+            "32: `count` returns with A +1, B +0 since its entry [unbalanced-return]",
+            "37: execution falls through into data at `table` [fallthrough-into-data]",
+        ]
+    );
+    let mut fired = rules(&warnings);
+    fired.sort();
+    let mut all: Vec<&str> = Rule::ALL.iter().map(|r| r.name()).collect();
+    all.sort();
+    assert_eq!(fired, all);
+}
+
+#[test]
+fn directives_silence_every_rule() {
+    for rule in Rule::ALL {
+        // Disabled for the whole sample, the other rules still fire
+        let source = format!(".lint disable {}\n{}", rule.name(), LINT);
+        let warnings = lint(&source, &LintConfig::default());
+        assert_eq!(warnings.len(), Rule::ALL.len() - 1, "{}", rule.name());
+        assert!(!rules(&warnings).contains(&rule.name()), "{}", rule.name());
+    }
+}
+
+#[test]
+fn directives_only_apply_until_enabled_again() {
+    let source = "\
+main:
+    HALT
+.lint disable unreachable
+    NOOP
+.lint enable unreachable
+done:
+    HALT
+    NOOP
+";
+    assert_eq!(
+        lint(source, &LintConfig::default()),
+        [
+            "6: label `done` is never referenced [unused-label]",
+            "8: unreachable code after HALT [unreachable]"
+        ]
+    );
+}
+
+#[test]
+fn config_file_silences_every_rule() {
+    for rule in Rule::ALL {
+        let config: LintConfig = format!("disable {} // not for this program", rule.name())
+            .parse()
+            .unwrap();
+        let warnings = lint(LINT, &config);
+        assert_eq!(warnings.len(), Rule::ALL.len() - 1, "{}", rule.name());
+        assert!(!rules(&warnings).contains(&rule.name()), "{}", rule.name());
+    }
+    let config: LintConfig = "disable unused-label\nenable unused-label".parse().unwrap();
+    assert_eq!(lint(LINT, &config).len(), Rule::ALL.len());
+}
+
+#[test]
+fn config_file_adds_read_only_ranges() {
+    let source = "main:\n    PUSH 0x01 A\n    POP A &0x9000\n    HALT";
+    assert!(lint(source, &LintConfig::default()).is_empty());
+    let config: LintConfig = "rom 0x9000-0x90ff".parse().unwrap();
+    assert_eq!(
+        lint(source, &config),
+        ["3: writes to ROM at `main+0x9000` [rom-write]"]
+    );
+}
+
+#[test]
+fn config_errors_name_the_line() {
+    let error = "disable unused-label\ndisable missing"
+        .parse::<LintConfig>()
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "lint config line 2: unknown lint rule `missing`"
+    );
+}
+
+#[test]
+fn lint_cli_exits_with_the_warnings() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_nox_asm"))
+        .args(["lint", "-i", "test/lint.nox"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.ends_with("> 9 lint warnings\n"), "{}", stderr);
+}
@@ -0,0 +1,208 @@
+use nox_asm::lsp::LanguageServer;
+use serde_json::{json, Value};
+
+const URI: &str = "file:///project/main.nox";
+
+const SOURCE: &str = "\
+main:
+    CALL draw
+    HALT
+draw: // 🦀 draws
+    PUSH 0x01 A
+    POP A screen
+    RET OK
+screen:
+";
+
+fn open(server: &mut LanguageServer, text: &str) -> Vec<Value> {
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": URI, "languageId": "nox", "version": 1, "text": text } },
+    }))
+}
+
+fn request(server: &mut LanguageServer, method: &str, line: u32, character: u32) -> Value {
+    let mut replies = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": method,
+        "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+            "context": { "includeDeclaration": true },
+        },
+    }));
+    assert_eq!(replies.len(), 1);
+    let reply = replies.remove(0);
+    assert_eq!(reply["id"], 7);
+    reply["result"].clone()
+}
+
+fn range(line: u32, start: u32, end: u32) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+fn diagnostics(replies: &[Value]) -> Value {
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+    assert_eq!(replies[0]["params"]["uri"], URI);
+    replies[0]["params"]["diagnostics"].clone()
+}
+
+#[test]
+fn diagnostics_follow_the_document() {
+    let mut server = LanguageServer::new();
+    assert_eq!(diagnostics(&open(&mut server, SOURCE)), json!([]));
+
+    // The range ends at the UTF-16 length of the line, the crab takes two units
+    let replies = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "main:\n    CALL missing // 🦀\n    HALT\n" }],
+        },
+    }));
+    assert_eq!(
+        diagnostics(&replies),
+        json!([{
+            "range": range(1, 0, 22),
+            "severity": 2,
+            "source": "nox_asm",
+            "message": "label `missing` is not defined, 0x0000 is used instead",
+        }])
+    );
+
+    let replies = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": URI, "version": 3 },
+            "contentChanges": [{ "text": "main:\n    PUSH 0x01 Q\n" }],
+        },
+    }));
+    let errors = diagnostics(&replies);
+    assert_eq!(errors[0]["range"], range(1, 0, 15));
+    assert_eq!(errors[0]["severity"], 1);
+}
+
+#[test]
+fn definition_of_a_label() {
+    let mut server = LanguageServer::new();
+    open(&mut server, SOURCE);
+    let expected = json!({ "uri": URI, "range": range(3, 0, 4) });
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 10),
+        expected
+    );
+    // The end of the word counts too
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 1, 13),
+        expected
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/definition", 2, 5),
+        Value::Null
+    );
+}
+
+#[test]
+fn references_of_a_label() {
+    let mut server = LanguageServer::new();
+    open(&mut server, SOURCE);
+    assert_eq!(
+        request(&mut server, "textDocument/references", 3, 2),
+        json!([
+            { "uri": URI, "range": range(1, 9, 13) },
+            { "uri": URI, "range": range(3, 0, 4) },
+        ])
+    );
+    assert_eq!(
+        request(&mut server, "textDocument/references", 5, 12),
+        json!([
+            { "uri": URI, "range": range(5, 10, 16) },
+            { "uri": URI, "range": range(7, 0, 6) },
+        ])
+    );
+}
+
+#[test]
+fn hover_describes_the_word() {
+    let mut server = LanguageServer::new();
+    open(&mut server, SOURCE);
+    let hover = |server: &mut LanguageServer, line, character| {
+        request(server, "textDocument/hover", line, character)["contents"]["value"].clone()
+    };
+    let call = hover(&mut server, 1, 5);
+    assert!(
+        call.as_str().unwrap().starts_with("**CALL** - "),
+        "{}",
+        call
+    );
+    assert!(call
+        .as_str()
+        .unwrap()
+        .ends_with("\n\n`CALL x` - opcode 0x81, 3 bytes"));
+    assert_eq!(hover(&mut server, 6, 9), "**OK** flag - Set by `RET OK`");
+    assert_eq!(
+        hover(&mut server, 1, 11),
+        "**draw** - label at /project/main.nox:4, address 0x0004"
+    );
+    // Nothing in comments
+    assert_eq!(
+        request(&mut server, "textDocument/hover", 3, 12),
+        Value::Null
+    );
+}
+
+#[test]
+fn completion_lists_keywords_and_labels() {
+    let mut server = LanguageServer::new();
+    open(&mut server, SOURCE);
+    let items = request(&mut server, "textDocument/completion", 1, 4);
+    let item = |label: &str| {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|i| i["label"] == label)
+            .cloned()
+            .unwrap_or_else(|| panic!("no completion `{}`", label))
+    };
+    assert_eq!(item("CALL")["kind"], 14);
+    assert_eq!(item("HI")["kind"], 6);
+    assert_eq!(item("OVF")["kind"], 21);
+    assert_eq!(item(".proc")["kind"], 14);
+    assert_eq!(
+        item("screen"),
+        json!({ "label": "screen", "kind": 3, "detail": "/project/main.nox:8" })
+    );
+}
+
+#[test]
+fn symbols_end_at_the_utf16_length() {
+    let mut server = LanguageServer::new();
+    open(&mut server, SOURCE);
+    let symbols = request(&mut server, "textDocument/documentSymbol", 0, 0);
+    let draw = &symbols.as_array().unwrap()[1];
+    assert_eq!(draw["name"], "draw");
+    assert_eq!(draw["range"], range(3, 0, 17));
+}
+
+#[test]
+fn unknown_requests_are_errors() {
+    let mut server = LanguageServer::new();
+    let replies =
+        server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "workspace/symbol" }));
+    assert_eq!(
+        replies[0]["error"]["message"],
+        "unsupported method `workspace/symbol`"
+    );
+    assert!(server
+        .handle(&json!({ "jsonrpc": "2.0", "method": "initialized" }))
+        .is_empty());
+}
@@ -0,0 +1,76 @@
+use nox_asm::machine::{Machine, Registers};
+
+const STRLEN: &str = include_str!("../test/strlen.nox");
+
+fn strlen(machine: &mut Machine, input: &[u8]) -> Registers {
+    machine
+        .set_memory(0x8100, input)
+        .set_memory(0x8100 + input.len() as u16, &[0]);
+    let registers = Registers {
+        hli: 0x8100,
+        b: vec![0x42],
+        ..Default::default()
+    };
+    machine.call("strlen", registers).unwrap().registers()
+}
+
+#[test]
+fn strlen_returns_the_length_in_a() {
+    let mut machine = Machine::from_source(STRLEN).unwrap();
+    for input in [&b""[..], b"a", b"hello", &[b'x'; 200]] {
+        let registers = strlen(&mut machine, input);
+        assert_eq!(registers.a, [input.len() as u8]);
+        assert_eq!(machine.memory_at("length", 1).unwrap(), [input.len() as u8]);
+        // `preserves B`, HLI is restored by RET
+        assert_eq!(registers.b, [0x42]);
+        assert_eq!(registers.hli, 0x8100);
+        assert!(registers.flags.ok);
+    }
+}
+
+#[test]
+fn strlen_steps_grow_with_the_length() {
+    let mut machine = Machine::from_source(STRLEN).unwrap();
+    strlen(&mut machine, b"");
+    let empty = machine.steps();
+    strlen(&mut machine, b"a");
+    let one = machine.steps();
+    strlen(&mut machine, b"ab");
+    let two = machine.steps();
+    assert!(empty > 0);
+    assert_eq!(two - one, one - empty);
+}
+
+#[test]
+fn unknown_labels_and_halts_are_errors() {
+    let mut machine = Machine::from_source("stop:\n    HALT").unwrap();
+    let Err(error) = machine.call("missing", Registers::default()) else {
+        panic!("called a missing label");
+    };
+    assert_eq!(error.to_string(), "label `missing` is not defined");
+    assert!(machine.call("stop", Registers::default()).is_err());
+}
+
+#[test]
+fn endless_loops_stop_at_the_step_limit() {
+    let mut machine = Machine::from_source("spin:\n    JMP spin")
+        .unwrap()
+        .with_max_steps(100);
+    let Err(error) = machine.call("spin", Registers::default()) else {
+        panic!("returned from an endless loop");
+    };
+    assert_eq!(
+        error.to_string(),
+        "`spin` did not return after 100 instructions"
+    );
+}
+
+#[test]
+fn reset_restores_the_program() {
+    let mut machine = Machine::from_source(STRLEN).unwrap();
+    let length = machine.address_of("length").unwrap();
+    strlen(&mut machine, b"abc");
+    assert_eq!(machine.memory(length..length + 1), [3]);
+    machine.reset();
+    assert_eq!(machine.memory(length..length + 1), [0]);
+}
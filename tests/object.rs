@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use nox_asm::{
+    archive::Archive,
+    linker::link,
+    object::{Object, ObjectSection, Relocation, Symbol},
+    Assembler, LinkerScript,
+};
+
+fn object(path: &str) -> Object {
+    Assembler::new(Path::new(path))
+        .assemble_object(false)
+        .unwrap()
+}
+
+#[test]
+fn objects_survive_serialization() {
+    for path in ["test/link/main.nox", "test/link/print.nox"] {
+        let object = object(path);
+        assert_eq!(Object::from_bytes(&object.to_bytes()).unwrap(), object);
+    }
+}
+
+#[test]
+fn objects_record_exports_imports_and_relocations() {
+    let main = object("test/link/main.nox");
+    assert!(main.symbol("main").unwrap().exported);
+    assert!(!main.symbol("greeting").unwrap().exported);
+    assert_eq!(main.imports, ["print"]);
+    let symbols: Vec<&str> = main.relocations.iter().map(|r| r.symbol.as_str()).collect();
+    assert_eq!(symbols, ["greeting", "print"]);
+
+    let print = object("test/link/print.nox");
+    let bss = print.sections.iter().find(|s| s.name == "bss").unwrap();
+    assert_eq!(bss.size, 1);
+    assert!(bss.noload);
+    assert!(bss.data.is_empty());
+}
+
+#[test]
+fn empty_sections_stay_loadable() {
+    // `.section rodata` without any data still has a fragment
+    let object = Assembler::from_source("NOOP\n.section rodata\n.section bss\n")
+        .assemble_object(false)
+        .unwrap();
+    let read = Object::from_bytes(&object.to_bytes()).unwrap();
+    let sections: Vec<(&str, u16, bool)> = read
+        .sections
+        .iter()
+        .map(|s| (s.name.as_str(), s.size, s.noload))
+        .collect();
+    assert_eq!(
+        sections,
+        [("code", 1, false), ("rodata", 0, false), ("bss", 0, true)]
+    );
+}
+
+#[test]
+fn linking_resolves_symbols_across_objects() {
+    let objects = [object("test/link/main.nox"), object("test/link/print.nox")];
+    let image = link(&objects, &LinkerScript::default(), false).unwrap();
+    // main: PUSH *greeting HLI, CALL print, HALT; print follows it in ROM, greeting after the code
+    let print = 0x0007u16;
+    let greeting = print + 14;
+    assert_eq!(&image[0x0001..0x0003], greeting.to_be_bytes());
+    assert_eq!(&image[0x0004..0x0006], print.to_be_bytes());
+    assert_eq!(
+        &image[greeting as usize..greeting as usize + 16],
+        b"Hello from main\0"
+    );
+}
+
+#[test]
+fn undefined_and_duplicate_symbols_are_errors() {
+    let main = object("test/link/main.nox");
+    let error = link(std::slice::from_ref(&main), &LinkerScript::default(), false).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "undefined symbol `print` referenced in test/link/main.nox"
+    );
+
+    let print = object("test/link/print.nox");
+    let error = link(
+        &[main, print.clone(), print],
+        &LinkerScript::default(),
+        false,
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "symbol `print` is exported by both test/link/print.nox and test/link/print.nox"
+    );
+}
+
+#[test]
+fn relocations_past_the_end_of_memory_are_errors() {
+    // Only a hand-made object can have one, the assembler rejects a 16 bit value at 0xffff
+    let object = Object {
+        source: "end.o".to_owned(),
+        sections: vec![ObjectSection {
+            name: "code".to_owned(),
+            origin: Some(0xffff),
+            size: 1,
+            noload: false,
+            data: vec![0],
+        }],
+        symbols: vec![Symbol {
+            name: "end".to_owned(),
+            section: 0,
+            offset: 0,
+            exported: false,
+        }],
+        imports: vec![],
+        relocations: vec![Relocation {
+            section: 0,
+            offset: 0,
+            symbol: "end".to_owned(),
+        }],
+    };
+    let error = link(&[object], &LinkerScript::default(), false).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "relocation of `end` at 0xffff in end.o does not fit in memory"
+    );
+}
+
+#[test]
+fn archives_only_add_the_members_that_are_needed() {
+    let archive = Archive {
+        members: vec![
+            object("test/link/print.nox"),
+            object("test/link/unused.nox"),
+        ],
+    };
+    let archive = Archive::from_bytes(&archive.to_bytes()).unwrap();
+    assert!(Archive::is_archive(&archive.to_bytes()));
+    let mut objects = vec![object("test/link/main.nox")];
+    Archive::resolve(&[archive], &mut objects, false);
+    let sources: Vec<&str> = objects.iter().map(|o| o.source.as_str()).collect();
+    assert_eq!(sources, ["test/link/main.nox", "test/link/print.nox"]);
+}
+
+#[test]
+fn other_files_are_not_objects() {
+    let error = Object::from_bytes(b"NOXA\x01").unwrap_err();
+    assert_eq!(error.to_string(), "not a Nox object file");
+    let mut bytes = object("test/link/main.nox").to_bytes();
+    bytes.truncate(bytes.len() - 1);
+    assert!(Object::from_bytes(&bytes).is_err());
+}
@@ -0,0 +1,79 @@
+use nox_asm::{
+    assemble_str,
+    emulator::{Emulator, Event},
+    profile::Profiler,
+};
+
+const PROGRAM: &str = "\
+main:
+    PUSH *tick AB
+    POP  AB IRA
+    SET  IRQ
+    CALL draw
+    CALL draw
+    HALT
+draw:
+    CALL plot
+    NOOP
+    RET  OK
+plot:
+    NOOP
+    NOOP
+    RET  OK
+tick:
+    RET  OK
+";
+
+/// Folded stacks of `PROGRAM`, interrupted when it first enters `plot`
+fn folded() -> (String, u64) {
+    let assembly = assemble_str(PROGRAM).unwrap();
+    let plot = assembly.symbol("plot").unwrap().address;
+    let mut emulator = Emulator::new(&assembly.bytes);
+    let mut profiler = Profiler::new();
+    let mut interrupted = false;
+    loop {
+        if emulator.cpu.pc == plot && !interrupted {
+            emulator.request_interrupt();
+            interrupted = true;
+        }
+        let step = emulator.step().unwrap();
+        profiler.record(&emulator, &step);
+        if step.event == Some(Event::Halt) {
+            break;
+        }
+    }
+    (profiler.folded(&assembly), profiler.total_instructions())
+}
+
+#[test]
+fn folded_stacks_count_the_instructions_of_each_call_stack() {
+    let (folded, total) = folded();
+    // `CALL` counts in the caller, `RET` in the routine that returns, the handler where it
+    // interrupted the program
+    assert_eq!(
+        folded,
+        "\
+main 6
+main;draw 6
+main;draw;plot 6
+main;draw;plot;tick 1
+"
+    );
+    assert_eq!(total, 19);
+}
+
+#[test]
+fn reset_starts_from_the_reset_address() {
+    let assembly = assemble_str(PROGRAM).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    let mut profiler = Profiler::new();
+    // Into `draw` and `plot`, then reset: the next instruction runs in `main` again
+    for _ in 0..5 {
+        let step = emulator.step().unwrap();
+        profiler.record(&emulator, &step);
+    }
+    emulator.reset();
+    let step = emulator.step().unwrap();
+    profiler.record(&emulator, &step);
+    assert_eq!(profiler.folded(&assembly), "main 5\nmain;draw 1\n");
+}
@@ -0,0 +1,101 @@
+use nox_asm::{emulator::Emulator, testing, Assembler};
+
+/// Runs the `.test` blocks of `source`, returning whether they passed and the output
+fn run_tests(source: &str) -> (bool, String) {
+    let mut assembler = Assembler::from_source(source).with_tests();
+    let assembly = assembler.build(false).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    let mut output = vec![];
+    let passed = testing::run_tests(
+        &assembler.tests(),
+        None,
+        &assembly,
+        &mut emulator,
+        testing::MAX_STEPS,
+        &mut output,
+    )
+    .unwrap();
+    (passed, String::from_utf8(output).unwrap())
+}
+
+fn bytes(source: &str) -> Vec<u8> {
+    Assembler::from_source(source).build(false).unwrap().bytes
+}
+
+fn error(source: &str) -> String {
+    match Assembler::from_source(source).build(false) {
+        Ok(_) => panic!("the build should fail"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn sample_tests_pass() {
+    let (passed, output) = run_tests(include_str!("../test/pseudo.nox"));
+    assert!(passed, "{}", output);
+    assert!(
+        output.contains("test result: ok. 9 passed; 0 failed"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn expansions_are_the_instructions_written_by_hand() {
+    let data = "\nHALT\nword:\n.reserve 0x02\ncopy:\n.reserve 0x02";
+    let same = |pseudo: &str, by_hand: &str| {
+        assert_eq!(
+            bytes(&format!("{}{}", pseudo, data)),
+            bytes(&format!("{}{}", by_hand, data)),
+            "{}",
+            pseudo
+        )
+    };
+    same("MOV16 0x1234, HLI", "PUSH 0x1234 HLI");
+    same("MOV16 *word HLI", "PUSH *word HLI");
+    same("MOV16 word, HLI", "PUSH word HLI");
+    same("MOV16 HLI, word", "STO HLI word");
+    same("MOV16 word, copy", "PUSH word AB\nPOP AB copy");
+    same("MOV16 0x1234, word", "PUSH 0x1234 AB\nPOP AB word");
+    same("LOADI word, B", "PUSH *word HLI\nPUSH &HLI B");
+    same("NEG A", "PUSH 0x00 B\nSUB B A\nPOP B");
+    same("NEG B", "PUSH 0x00 A\nSUB A B\nPOP A");
+    same("JNZ word", "JZE skip\nJMP word\nskip:");
+    same(
+        "CALL_IF_ZERO word",
+        "JZE enter\nJMP skip\nenter:\nCALL word\nskip:",
+    );
+}
+
+#[test]
+fn bad_mov16_operands_are_errors() {
+    let syntax = "<source>:1: error: syntax error - expected MOV16 <value, address or HLI>, \
+                  <address or HLI>";
+    assert_eq!(error("MOV16 A, HLI"), syntax);
+    assert_eq!(error("MOV16 0x1234, A"), syntax);
+    assert_eq!(error("MOV16 0x1234"), syntax);
+    assert_eq!(
+        error("MOV16 HLI, HLI"),
+        "<source>:1: error: MOV16 HLI, HLI does nothing"
+    );
+}
+
+#[test]
+fn bad_loadi_operands_are_errors() {
+    let syntax = "<source>:1: error: syntax error - expected LOADI <address>, <A or B>";
+    assert_eq!(error("LOADI hello, HI"), syntax);
+    assert_eq!(error("LOADI A, A"), syntax);
+    assert_eq!(error("LOADI hello"), syntax);
+}
+
+#[test]
+fn bad_neg_operands_are_errors() {
+    let syntax = "<source>:1: error: syntax error - expected NEG <A or B>";
+    assert_eq!(error("NEG HLI"), syntax);
+    assert_eq!(error("NEG"), syntax);
+    assert_eq!(error("NEG A B"), syntax);
+    assert_eq!(
+        error("JNZ 0x12"),
+        "<source>:1: error: syntax error - expected JNZ <label>"
+    );
+}
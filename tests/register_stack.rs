@@ -0,0 +1,130 @@
+use nox_asm::{
+    ir::Register,
+    register_stack::{declared, Depth, Imbalance, RegisterStackAnalysis},
+    Assembler, Assembly,
+};
+
+fn analyse(source: &str) -> (Assembly, RegisterStackAnalysis) {
+    let mut assembler = Assembler::from_source(source);
+    let assembly = assembler.build(false).unwrap();
+    let graph = assembler.control_flow_graph();
+    let analysis = RegisterStackAnalysis::new(&graph, &declared(assembler.statements()));
+    (assembly, analysis)
+}
+
+fn address(assembly: &Assembly, label: &str) -> u16 {
+    assembly.symbol(label).unwrap().address
+}
+
+#[test]
+fn balanced_routines_preserve_their_callers_values() {
+    let source = "\
+main:
+    PUSH 0x01 A
+    CALL scratch
+    POP A B
+    HALT
+scratch:
+    PUSH 0x02 A
+    POP A B
+    POP B A
+    POP A HI
+    RET OK
+";
+    let (assembly, analysis) = analyse(source);
+    let scratch = address(&assembly, "scratch");
+    assert!(analysis.imbalances(scratch).is_empty());
+    assert_eq!(analysis.effect(scratch), Depth::default());
+    assert!(analysis.preserves(scratch, Register::A));
+    assert!(analysis.preserves(scratch, Register::B));
+    assert!(analysis.imbalances(0x0000).is_empty());
+}
+
+#[test]
+fn paths_meeting_at_other_depths_are_reported() {
+    let source = "\
+main:
+    CMP 0x00 A
+    JZE done
+    PUSH 0x01 A
+done:
+    HALT
+";
+    let (assembly, analysis) = analyse(source);
+    assert_eq!(
+        analysis.imbalances(0x0000),
+        [Imbalance::Merge {
+            address: address(&assembly, "done"),
+            depths: [Depth { a: 1, b: 0 }, Depth { a: 0, b: 0 }],
+        }]
+    );
+}
+
+#[test]
+fn returns_leaving_values_behind_are_reported() {
+    let source = "\
+main:
+    CALL leaky
+    HALT
+leaky:
+    PUSH 0x01 B
+    RET OK
+";
+    let (assembly, analysis) = analyse(source);
+    let leaky = address(&assembly, "leaky");
+    let [Imbalance::Return {
+        depth, expected, ..
+    }] = analysis.imbalances(leaky)
+    else {
+        panic!("{:?}", analysis.imbalances(leaky));
+    };
+    assert_eq!(*depth, Depth { a: 0, b: 1 });
+    assert_eq!(*expected, Depth::default());
+    assert!(analysis.preserves(leaky, Register::A));
+    assert!(!analysis.preserves(leaky, Register::B));
+    // Its callers see the value it left
+    assert_eq!(analysis.effect(leaky), Depth { a: 0, b: 1 });
+}
+
+#[test]
+fn procs_return_the_values_they_declare() {
+    let source = "\
+main:
+    CALL one
+    POP A B
+    HALT
+.proc one uses A returns A
+    PUSH 0x01 A
+    RET OK
+.endproc
+";
+    let (assembly, analysis) = analyse(source);
+    let one = address(&assembly, "one");
+    assert!(analysis.imbalances(one).is_empty());
+    assert!(analysis.preserves(one, Register::A));
+    assert_eq!(analysis.effect(one), Depth { a: 1, b: 0 });
+    // The value `one` returns is the one `main` pops
+    assert!(analysis.imbalances(0x0000).is_empty());
+}
+
+#[test]
+fn procs_returning_less_than_they_declare_are_reported() {
+    let source = "\
+main:
+    CALL none
+    HALT
+.proc none returns A
+    RET OK
+.endproc
+";
+    let (assembly, analysis) = analyse(source);
+    let none = address(&assembly, "none");
+    assert_eq!(
+        analysis.imbalances(none),
+        [Imbalance::Return {
+            address: none,
+            depth: Depth::default(),
+            expected: Depth { a: 1, b: 0 },
+        }]
+    );
+}
@@ -0,0 +1,83 @@
+use nox_asm::{stack::StackUsage, Assembler};
+
+const STACK: &str = include_str!("../test/stack.nox");
+
+fn usage(source: &str, label: &str) -> StackUsage {
+    let mut assembler = Assembler::from_source(source);
+    let assembly = assembler.build(false).unwrap();
+    let graph = assembler.control_flow_graph();
+    let entry = assembly.symbol(label).unwrap().address;
+    assembler.stack_analysis(&graph).usage(entry).unwrap()
+}
+
+#[test]
+fn deepest_path_goes_through_the_calls() {
+    let mut assembler = Assembler::from_source(STACK);
+    let assembly = assembler.build(false).unwrap();
+    let graph = assembler.control_flow_graph();
+    let analysis = assembler.stack_analysis(&graph);
+    let entries: Vec<&str> = analysis
+        .entry_points()
+        .iter()
+        .map(|r| r.name.as_str())
+        .collect();
+    assert_eq!(entries, ["main"]);
+
+    let main = analysis.usage(0x0000).unwrap();
+    assert_eq!(main.own, Some(2));
+    assert_eq!(main.total, Some(25));
+    let draw = assembly.symbol("draw").unwrap().address;
+    let plot = assembly.symbol("plot").unwrap().address;
+    assert_eq!(main.deepest, [draw, plot]);
+    assert_eq!(analysis.usage(draw).unwrap().total, Some(4 + 9 + 1));
+    assert_eq!(analysis.usage(plot).unwrap().total, Some(1));
+}
+
+#[test]
+fn usage_over_the_stack_size_fails_the_build() {
+    let source = STACK.replace(".stack_size 0x0020", ".stack_size 0x0018");
+    let Err(e) = Assembler::from_source(&source).build(false) else {
+        panic!("the build should fail");
+    };
+    assert!(
+        e.to_string().contains(
+            "stack usage of `main` can reach 25 bytes through main -> draw -> plot, \
+             more than the stack size of 24 bytes"
+        ),
+        "{}",
+        e
+    );
+
+    let source = STACK.replace(".stack_size 0x0020", ".stack_size 0x0019");
+    assert!(Assembler::from_source(&source).build(false).is_ok());
+}
+
+#[test]
+fn merging_paths_keep_the_deeper_one() {
+    let source = "\
+main:
+    CMP 0x00 A
+    JZE short
+    PUSH A S 0x03
+    POP S A 0x03
+    HALT
+short:
+    PUSH HI S
+    POP S HI
+    HALT
+";
+    assert_eq!(usage(source, "main").total, Some(3));
+}
+
+#[test]
+fn recursion_and_growing_loops_are_unbounded() {
+    let recursion = "main:\n    CALL main\n    HALT";
+    let usage_of_main = usage(recursion, "main");
+    assert_eq!(usage_of_main.total, None);
+    assert_eq!(usage_of_main.to_string(), "unbounded");
+
+    let growing = "main:\n    PUSH HI S\n    JMP main";
+    let usage_of_main = usage(growing, "main");
+    assert_eq!(usage_of_main.own, None);
+    assert_eq!(usage_of_main.total, None);
+}
@@ -0,0 +1,101 @@
+use nox_asm::{emulator::Emulator, testing, Assembler};
+
+/// Runs the `.test` blocks of `source`, returning whether they passed and the output
+fn run_tests(source: &str) -> (bool, String) {
+    let mut assembler = Assembler::from_source(source).with_tests();
+    let assembly = assembler.build(false).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    let mut output = vec![];
+    let passed = testing::run_tests(
+        &assembler.tests(),
+        None,
+        &assembly,
+        &mut emulator,
+        testing::MAX_STEPS,
+        &mut output,
+    )
+    .unwrap();
+    (passed, String::from_utf8(output).unwrap())
+}
+
+fn error(source: &str) -> String {
+    match Assembler::from_source(source).build(false) {
+        Ok(_) => panic!("the build should fail"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn sample_tests_pass() {
+    let (passed, output) = run_tests(include_str!("../test/structured.nox"));
+    assert!(passed, "{}", output);
+    assert!(
+        output.contains("test result: ok. 7 passed; 0 failed"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn blocks_lower_to_the_jumps_written_by_hand() {
+    let bytes = |source: &str| Assembler::from_source(source).build(false).unwrap().bytes;
+    assert_eq!(
+        bytes(".if zero\n    PUSH 0x00 B\n.else\n    PUSH 0x01 B\n.endif\nHALT"),
+        bytes(
+            "JZE then\nJMP else\nthen:\n    PUSH 0x00 B\nJMP end\nelse:\n    PUSH 0x01 B\nend:\nHALT"
+        )
+    );
+    assert_eq!(
+        bytes(".while not_zero\n.if ok\n.break\n.endif\n    INC HLI\n.endwhile\nHALT"),
+        bytes(
+            "top:\nJZE end\nJOK then\nJMP skip\nthen:\nJMP end\nskip:\n    INC HLI\nJMP top\nend:\nHALT"
+        )
+    );
+    assert_eq!(
+        bytes(".loop LI\n.break\n.endloop\nHALT"),
+        bytes("top:\nCMP 0x00 LI\nJZE end\nJMP end\nDEC LI\nJMP top\nend:\nHALT")
+    );
+}
+
+#[test]
+fn unbalanced_blocks_are_errors() {
+    assert_eq!(
+        error("main:\n.if zero\n    NOOP\n    HALT"),
+        "<source>:2: error: .if is not closed with .endif"
+    );
+    assert_eq!(error(".endif"), "<source>:1: error: .endif without .if");
+    assert_eq!(error(".else"), "<source>:1: error: .else without .if");
+    assert_eq!(
+        error(".if zero\n.else\n.else\n.endif"),
+        "<source>:3: error: .else without .if"
+    );
+    assert_eq!(
+        error(".while zero\n.endif"),
+        "<source>:2: error: .endif does not close .while at line 1"
+    );
+}
+
+#[test]
+fn break_outside_of_a_loop_is_an_error() {
+    assert_eq!(
+        error(".break"),
+        "<source>:1: error: .break outside of .while or .loop"
+    );
+    assert_eq!(
+        error(".if zero\n.break\n.endif"),
+        "<source>:2: error: .break outside of .while or .loop"
+    );
+}
+
+#[test]
+fn bad_arguments_are_errors() {
+    assert_eq!(
+        error(".if nonzero\n.endif"),
+        "<source>:1: error: syntax error - expected a condition: zero, overflow, error, ok or \
+         not_ any of them, got `nonzero`"
+    );
+    assert_eq!(
+        error(".loop A\n.endloop"),
+        "<source>:1: error: syntax error - .loop counts HLI, HI or LI"
+    );
+}
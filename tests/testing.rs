@@ -0,0 +1,286 @@
+use nox_asm::{
+    emulator::Emulator,
+    ir::{Comparison, ExpectTarget, Expectation, Operand},
+    testing::{self, MAX_STEPS},
+    Assembler, UnitTest,
+};
+
+/// The tests of `source`
+fn tests(source: &str) -> Vec<UnitTest> {
+    let mut assembler = Assembler::from_source(source).with_tests();
+    assembler.build(false).unwrap();
+    assembler.tests()
+}
+
+/// Runs the only test of `source`
+fn run(source: &str, max_steps: u64) -> Result<(), Vec<String>> {
+    let mut assembler = Assembler::from_source(source).with_tests();
+    let assembly = assembler.build(false).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    let tests = assembler.tests();
+    assert_eq!(tests.len(), 1);
+    tests[0].run(
+        &mut emulator,
+        |name| assembly.symbol(name).map(|s| s.address),
+        max_steps,
+    )
+}
+
+/// A test checking `expect` after pushing 0x05 to A, 0x0203 to HLI and 0x2a to B and `data`
+fn expect(expect: &str) -> Result<(), Vec<String>> {
+    let source = format!(
+        "\
+main:
+    HALT
+data:
+.reserve 0x01
+.test \"t\"
+    PUSH 0x05   A
+    PUSH 0x0203 HLI
+    PUSH 0x2a   B
+    PUSH 0x2a   B
+    POP  B      data
+    .expect {}
+.endtest",
+        expect
+    );
+    run(&source, MAX_STEPS)
+}
+
+fn error(source: &str) -> String {
+    match Assembler::from_source(source).with_tests().build(false) {
+        Ok(_) => panic!("the build should fail"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn expectations_are_parsed() {
+    let tests = tests(
+        "main:\n    HALT\n.test \"parsed\"\n    .expect A == 0x00, zero != 1, &0x8000 <= 'a', \
+         &result > 10, HLI >= *main // comment\n.endtest\nresult:\n.reserve 0x01",
+    );
+    let expectation = |target, comparison, value| Expectation {
+        target,
+        comparison,
+        value,
+    };
+    assert_eq!(tests[0].name, "parsed");
+    assert_eq!(tests[0].location.line, 3);
+    assert_eq!(
+        tests[0].expectations,
+        [
+            expectation(
+                ExpectTarget::Register("A".to_owned()),
+                Comparison::Equal,
+                Operand::Immediate16(0x00)
+            ),
+            expectation(
+                ExpectTarget::Flag("ZER".to_owned()),
+                Comparison::NotEqual,
+                Operand::Immediate16(1)
+            ),
+            expectation(
+                ExpectTarget::Memory(Operand::Address(0x8000)),
+                Comparison::LessOrEqual,
+                Operand::Immediate16(b'a' as u16)
+            ),
+            expectation(
+                ExpectTarget::Memory(Operand::Label("result".to_owned())),
+                Comparison::Greater,
+                Operand::Immediate16(10)
+            ),
+            expectation(
+                ExpectTarget::Register("HLI".to_owned()),
+                Comparison::GreaterOrEqual,
+                Operand::LabelAddress("main".to_owned())
+            ),
+        ]
+    );
+}
+
+#[test]
+fn every_comparison_is_checked() {
+    for (comparison, holds) in [
+        ("==", [false, true, false]),
+        ("!=", [true, false, true]),
+        ("<", [false, false, true]),
+        ("<=", [false, true, true]),
+        (">", [true, false, false]),
+        (">=", [true, true, false]),
+    ] {
+        // A is 0x05
+        for (value, holds) in [0x04, 0x05, 0x06].into_iter().zip(holds) {
+            let expectation = format!("A {} 0x{:02x}", comparison, value);
+            assert_eq!(expect(&expectation).is_ok(), holds, "{}", expectation);
+        }
+    }
+}
+
+#[test]
+fn every_target_is_checked() {
+    let passing = [
+        "A == 0x05",
+        "A == 5",
+        "B == 0x2a",
+        "HI == 0x02",
+        "LI == 0x03",
+        "HLI == 0x0203",
+        "AB == 0x052a",
+        "EX == 0",
+        "ZER == 0",
+        "zero == 0",
+        "OVF == 0",
+        "ERR == 0",
+        "OK == 0",
+        "IRQ == 0",
+        "&data == '*'",
+        "&0x0001 == 0x2a",
+        "HLI != *data",
+    ];
+    for expectation in passing {
+        assert_eq!(expect(expectation), Ok(()), "{}", expectation);
+    }
+    assert_eq!(expect(&passing.join(", ")), Ok(()));
+}
+
+#[test]
+fn failures_tell_what_was_expected() {
+    assert_eq!(
+        expect("A == 0x06, ZER == 1, EX != 0"),
+        Err(vec![
+            "expected A == 0x06, got 0x05".to_owned(),
+            "expected ZER == 1, got 0".to_owned(),
+            "expected EX != 0x00, got 0x00".to_owned(),
+        ])
+    );
+    assert_eq!(
+        expect("HLI == *data"),
+        Err(vec!["expected HLI == *data (0x0001), got 0x0203".to_owned()])
+    );
+    assert_eq!(
+        expect("&data < 3, &0x0001 == 0x01"),
+        Err(vec![
+            "expected &data < 0x03, got 0x2a".to_owned(),
+            "expected &0x0001 == 0x01, got 0x2a".to_owned(),
+        ])
+    );
+    assert_eq!(
+        expect("&missing == 1, HLI > *missing"),
+        Err(vec![
+            "label `missing` is not defined".to_owned(),
+            "label `missing` is not defined".to_owned(),
+        ])
+    );
+}
+
+#[test]
+fn empty_registers_fail() {
+    let source = "main:\n    HALT\n.test \"empty\"\n    .expect B == 0, AB == 0\n.endtest";
+    assert_eq!(
+        run(source, MAX_STEPS),
+        Err(vec!["B is empty".to_owned(), "AB is empty".to_owned()])
+    );
+}
+
+#[test]
+fn tests_stop_at_faults_and_the_step_limit() {
+    let source = "main:\n    HALT\n.test \"spins\"\nspin:\n    JMP spin\n.endtest";
+    assert_eq!(
+        run(source, 100),
+        Err(vec!["did not halt after 100 instructions".to_owned()])
+    );
+    let source = "main:\n    HALT\n.test \"faults\"\n    POP A\n    .expect A == 0\n.endtest";
+    assert_eq!(
+        run(source, MAX_STEPS),
+        Err(vec!["fault: register A is empty at 0x0001".to_owned()])
+    );
+}
+
+#[test]
+fn run_tests_prints_a_summary() {
+    let source =
+        "main:\n    HALT\n.test \"passes\"\n    PUSH 0x01 A\n    .expect A == 1\n.endtest\n\
+                  .test \"fails\"\n    PUSH 0x01 A\n    .expect A == 2\n.endtest";
+    let mut assembler = Assembler::from_source(source).with_tests();
+    let assembly = assembler.build(false).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    let mut output = vec![];
+    let passed = testing::run_tests(
+        &assembler.tests(),
+        None,
+        &assembly,
+        &mut emulator,
+        MAX_STEPS,
+        &mut output,
+    )
+    .unwrap();
+    assert!(!passed);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "
+running 2 tests
+test passes ... ok
+test fails ... FAILED
+
+failures:
+
+---- fails (<source>:7) ----
+expected A == 0x02, got 0x01
+
+failures:
+    fails
+
+test result: FAILED. 1 passed; 1 failed; 0 filtered out
+
+"
+    );
+
+    let mut output = vec![];
+    let passed = testing::run_tests(
+        &assembler.tests(),
+        Some("pass"),
+        &assembly,
+        &mut emulator,
+        MAX_STEPS,
+        &mut output,
+    )
+    .unwrap();
+    assert!(passed);
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with("test result: ok. 1 passed; 0 failed; 1 filtered out\n\n"));
+}
+
+#[test]
+fn bad_expectations_are_errors() {
+    let error = |expect: &str| error(&format!(".test \"t\"\n    .expect {}\n.endtest", expect));
+    assert_eq!(
+        error("A = 1"),
+        "<source>:2: error: syntax error - expected eg. `A == 0x00`, got `A = 1`"
+    );
+    assert_eq!(
+        error("A"),
+        "<source>:2: error: syntax error - expected eg. `A == 0x00`, got `A`"
+    );
+    assert_eq!(
+        error("Q == 1"),
+        "<source>:2: error: cannot check `Q`, expected a register, a flag or `&address`"
+    );
+    assert_eq!(
+        error("A == zz"),
+        "<source>:2: error: zz is not a valid value"
+    );
+    assert_eq!(
+        error("&0xzz == 1"),
+        "<source>:2: error: &0xzz is not a valid address"
+    );
+}
+
+#[test]
+fn expect_is_only_allowed_in_tests() {
+    assert_eq!(
+        error("main:\n    .expect A == 1"),
+        "<source>:2: error: .expect is only allowed in a test"
+    );
+}
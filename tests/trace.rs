@@ -0,0 +1,118 @@
+use std::process::{Command, Stdio};
+
+use nox_asm::{
+    assemble_str,
+    emulator::{Emulator, Event},
+    trace::{TraceFilter, Tracer},
+};
+
+#[test]
+fn undefined_routines_are_reported() {
+    for (command, option) in [("run", "--trace-routine"), ("graph", "--routine")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_nox_asm"))
+            .args([command, "-i", "test/strlen.nox", option, "missing"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            format!("> {} missing: the routine is not defined\n", option)
+        );
+    }
+}
+
+const PROGRAM: &str = "\
+main:
+    PUSH *handler AB
+    POP  AB IRA
+    SET  IRQ
+    PUSH 0x05 A
+    CALL store
+    PUSH value A
+    CMP  0x05 A
+    HALT
+store:
+    PUSH 0x0102 HLI
+    POP  A value
+    RET  OK 0x03
+handler:
+    RET  OK
+value:
+.reserve 0x01
+";
+
+/// Trace of `PROGRAM` with an interrupt requested at the start
+fn trace(filter: TraceFilter) -> String {
+    let assembly = assemble_str(PROGRAM).unwrap();
+    let mut emulator = Emulator::new(&assembly.bytes);
+    emulator.request_interrupt();
+    let mut output = vec![];
+    let mut tracer = Tracer::new(&assembly, filter, &mut output);
+    while tracer.step(&mut emulator).unwrap().unwrap().event != Some(Event::Halt) {}
+    drop(tracer);
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn trace_lines_show_the_changes() {
+    assert_eq!(
+        trace(TraceFilter::default()),
+        "\
+0x0000 main             PUSH 0x0018 AB           A []->[0x00] B []->[0x18]
+0x0003 main+0x3         POP AB IRA               A [0x00]->[] B [0x18]->[] IRA 0x0000->0x0018
+0x0004 main+0x4         SET IRQ                  flags -->IRQ
+0x0005 main+0x5         interrupt -> handler     flags IRQ->- SA 0xf000->0xf009 SS 0x0ff0->0x0fe7 \
+SP 0xf000->0xf009 [0xf000] 0x00->0x10 [0xf001] 0x00->0x00 [0xf002] 0x00->0x00 [0xf003] 0x00->0x00 \
+[0xf004] 0x00->0x05 [0xf005] 0x00->0x0f [0xf006] 0x00->0xf0 [0xf007] 0x00->0xf0 [0xf008] 0x00->0x00
+0x0018 handler          RET OK                   flags -->IRQ SA 0xf009->0xf000 SS 0x0fe7->0x0ff0 \
+SP 0xf009->0xf000
+0x0005 main+0x5         PUSH 0x05 A              A []->[0x05]
+0x0007 main+0x7         CALL store               SA 0xf000->0xf009 SS 0x0ff0->0x0fe7 SP 0xf000->0xf009 \
+[0xf000] 0x10->0x10 [0xf001] 0x00->0x00 [0xf002] 0x00->0x00 [0xf003] 0x00->0x00 [0xf004] 0x05->0x0a \
+[0xf005] 0x0f->0x0f [0xf006] 0xf0->0xf0 [0xf007] 0xf0->0xf0 [0xf008] 0x00->0x00
+0x0010 store            PUSH 0x0102 HLI          HI 0x00->0x01 LI 0x00->0x02
+0x0013 store+0x3        POP A value              A [0x05]->[] [0x0019] 0x00->0x05
+0x0016 store+0x6        RET OK 0x03              HI 0x01->0x00 LI 0x02->0x00 EX 0x00->0x03 \
+flags IRQ->OK|IRQ SA 0xf009->0xf000 SS 0x0fe7->0x0ff0 SP 0xf009->0xf000
+0x000a main+0xa         PUSH value A             A []->[0x05]
+0x000d main+0xd         CMP 0x05 A               flags OK|IRQ->ZERO|OK|IRQ
+0x000f main+0xf         HALT
+"
+    );
+}
+
+#[test]
+fn filters_limit_the_traced_lines() {
+    let lines = |filter| -> Vec<String> {
+        trace(filter)
+            .lines()
+            .map(|line| line[..line.len().min(40)].trim_end().to_owned())
+            .collect()
+    };
+    // A routine is traced from its first instruction to its `RET`
+    let routine = TraceFilter {
+        routines: vec![0x0010],
+        ..Default::default()
+    };
+    assert_eq!(
+        lines(routine),
+        [
+            "0x0010 store            PUSH 0x0102 HLI",
+            "0x0013 store+0x3        POP A value",
+            "0x0016 store+0x6        RET OK 0x03",
+        ]
+    );
+    let range = TraceFilter {
+        ranges: vec![0x000a..=0x000f],
+        ..Default::default()
+    };
+    assert_eq!(
+        lines(range),
+        [
+            "0x000a main+0xa         PUSH value A",
+            "0x000d main+0xd         CMP 0x05 A",
+            "0x000f main+0xf         HALT",
+        ]
+    );
+}